@@ -1,4 +1,5 @@
 use translation::xtensa_op::XtensaInstruction;
+use error::Result;
 
 pub enum InstructionKind {
     Load,
@@ -10,6 +11,8 @@ pub enum InstructionKind {
 pub enum InstructionArch {
     Xtensa,
     Arm,
+    Aarch64,
+    C,
     Other,
 }
 
@@ -19,7 +22,7 @@ pub trait Operand {
 }
 
 pub trait ParseInstruction {
-    fn from_str(&mut self, s: &str);
+    fn from_str(&mut self, s: &str) -> Result<()>;
 }
 
 #[derive(Default)]
@@ -29,6 +32,9 @@ pub struct Instruction {
     pub kind: InstructionKind,
     pub arch: InstructionArch,
     pub referenced: bool,
+    /// Decoded instruction, if it was read from
+    /// binary rather than parsed from disassembly
+    pub decoded: Option<XtensaInstruction>,
 }
 
 impl Instruction {
@@ -0,0 +1,275 @@
+use std::vec::Vec;
+use std::collections::BTreeSet;
+
+use function::Function;
+use translation::xtensa_op::XtensaInstruction;
+use error::Result;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum EdgeKind {
+    /// Taken branch or jump
+    Branch,
+    /// Execution continues with the next block
+    FallThrough,
+}
+
+/// Edge between two blocks, given as block indices
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Edge {
+    pub from: usize,
+    pub to: usize,
+    pub kind: EdgeKind,
+}
+
+/// Call made from inside a basic block
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct CallSite {
+    pub offset: u32,
+    /// None for callx
+    pub target: Option<u32>,
+}
+
+/// Zero-overhead loop, given as instruction indices
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct HardwareLoop {
+    /// Index of the loop instruction, the body follows it
+    pub start: usize,
+    /// Index past the last instruction of the body
+    pub end: usize,
+}
+
+/// Straight-line run of instructions. Calls don't end a block
+#[derive(Default, Debug)]
+pub struct BasicBlock {
+    pub address: u32,
+    /// Index of the first instruction in the function
+    pub start: usize,
+    /// Index past the last instruction
+    pub end: usize,
+    pub successors: Vec<usize>,
+    pub predecessors: Vec<usize>,
+    pub calls: Vec<CallSite>,
+    /// Branch targets outside of the function, e.g. tail jumps
+    pub exits: Vec<u32>,
+}
+
+/// Control-flow graph of a single function
+#[derive(Default)]
+pub struct Cfg {
+    pub blocks: Vec<BasicBlock>,
+    pub edges: Vec<Edge>,
+    /// Loops with a body end inside the function
+    pub loops: Vec<HardwareLoop>,
+}
+
+impl BasicBlock {
+    pub fn len(&self) -> usize {
+        self.end - self.start
+    }
+
+    /// Index of the last instruction
+    pub fn last(&self) -> usize {
+        self.end - 1
+    }
+}
+
+impl Cfg {
+    pub fn build(function: &Function) -> Result<Cfg> {
+        let offsets: Vec<u32> = function.instructions.iter().map(|i| i.offset).collect();
+        Ok(Cfg::from_instructions(&function.xtensa_instructions()?, &offsets))
+    }
+
+    /// Builds the graph from instructions in program order.
+    /// `offsets` holds instruction addresses used to resolve branch targets
+    pub fn from_instructions(instructions: &[XtensaInstruction], offsets: &[u32]) -> Cfg {
+        let mut cfg = Cfg::default();
+        let mut leaders = BTreeSet::<usize>::new();
+        let index_of = |address: u32| offsets.iter().position(|&o| o == address);
+
+        if instructions.is_empty() {
+            return cfg;
+        }
+
+        leaders.insert(0);
+
+        for (i, instruction) in instructions.iter().enumerate() {
+            if let Some(target) = instruction.branch_target() {
+                leaders.extend(index_of(target));
+            }
+
+            if (instruction.branch_target().is_some() || instruction.is_terminator()) &&
+                    i + 1 < instructions.len() {
+                leaders.insert(i + 1);
+            }
+
+            // Body end branches back to the instruction after the loop one
+            if let Some(end) = instruction.loop_end().and_then(&index_of) {
+                if end > i + 1 {
+                    leaders.insert(i + 1);
+                    leaders.insert(end);
+                    cfg.loops.push(HardwareLoop { start: i, end });
+                }
+            }
+        }
+
+        let starts: Vec<usize> = leaders.into_iter().collect();
+
+        for (n, &start) in starts.iter().enumerate() {
+            let end = starts.get(n + 1).cloned().unwrap_or(instructions.len());
+            let mut block = BasicBlock { address: offsets[start], start, end, ..Default::default() };
+
+            for i in start .. end {
+                if instructions[i].is_call() {
+                    block.calls.push(CallSite { offset: offsets[i], target: instructions[i].call_target() });
+                }
+            }
+
+            cfg.blocks.push(block);
+        }
+
+        for n in 0 .. cfg.blocks.len() {
+            let last = &instructions[cfg.blocks[n].last()];
+
+            if let Some(target) = last.branch_target() {
+                match index_of(target).and_then(|i| starts.iter().position(|&s| s == i)) {
+                    Some(to) => cfg.edges.push(Edge { from: n, to, kind: EdgeKind::Branch }),
+                    None => cfg.blocks[n].exits.push(target),
+                }
+            }
+
+            if !last.is_terminator() && n + 1 < cfg.blocks.len() {
+                cfg.edges.push(Edge { from: n, to: n + 1, kind: EdgeKind::FallThrough });
+            }
+        }
+
+        for l in cfg.loops.clone() {
+            let body = starts.iter().position(|&s| s == l.start + 1).unwrap();
+
+            if !instructions[l.end - 1].is_terminator() {
+                cfg.edges.push(Edge { from: cfg.block_of(l.end - 1).unwrap(), to: body, kind: EdgeKind::Branch });
+            }
+        }
+
+        for edge in cfg.edges.clone() {
+            if !cfg.blocks[edge.from].successors.contains(&edge.to) {
+                cfg.blocks[edge.from].successors.push(edge.to);
+                cfg.blocks[edge.to].predecessors.push(edge.from);
+            }
+        }
+
+        cfg
+    }
+
+    /// Returns index of the block starting at address
+    pub fn block_at(&self, address: u32) -> Option<usize> {
+        self.blocks.iter().position(|b| b.address == address)
+    }
+
+    /// Returns index of the block holding instruction with given index
+    pub fn block_of(&self, index: usize) -> Option<usize> {
+        self.blocks.iter().position(|b| b.start <= index && index < b.end)
+    }
+
+    /// Whether a branch inside the function jumps to address,
+    /// i.e. it needs a label
+    pub fn is_branch_target(&self, address: u32) -> bool {
+        match self.block_at(address) {
+            Some(b) => self.edges.iter().any(|e| e.to == b && e.kind == EdgeKind::Branch),
+            None => false
+        }
+    }
+
+    /// Edges going backwards in program order, which close loops
+    pub fn back_edges(&self) -> Vec<Edge> {
+        self.edges.iter()
+            .filter(|e| e.to <= e.from)
+            .cloned()
+            .collect()
+    }
+
+    /// Returns Graphviz DOT representation of the graph.
+    /// Fall-through edges are dashed
+    pub fn to_dot(&self, function: &Function) -> String {
+        let mut result = format!("digraph \"{:}\" {{\n", function.name);
+        result.push_str("\tnode [shape=box, fontname=\"monospace\"];\n");
+
+        for (n, block) in self.blocks.iter().enumerate() {
+            let mut label = format!("loc_{:x}:\\l", block.address);
+
+            for instruction in &function.instructions[block.start .. block.end] {
+                let opcode = instruction.opcode.replace('\\', "\\\\").replace('"', "\\\"");
+                label.push_str(&format!("  {:}\\l", opcode));
+            }
+
+            for exit in &block.exits {
+                label.push_str(&format!("exit to 0x{:x}\\l", exit));
+            }
+
+            result.push_str(&format!("\tbb_{:} [label=\"{:}\"];\n", n, label));
+        }
+
+        for edge in &self.edges {
+            let style = match edge.kind {
+                EdgeKind::Branch => "",
+                EdgeKind::FallThrough => " [style=dashed]",
+            };
+
+            result.push_str(&format!("\tbb_{:} -> bb_{:}{:};\n", edge.from, edge.to, style));
+        }
+
+        result.push_str("}\n");
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use cfg::{Cfg, Edge, EdgeKind, CallSite, HardwareLoop};
+    use function::fixture;
+
+    #[test]
+    fn test_cfg_1() {
+        let f = fixture(&[
+            "beqz a2, 0x109", "movi a2, 1", "j 0x10c",
+            "movi a2, 2", "call0 0x4000", "ret",
+        ]);
+        let cfg = Cfg::build(&f).unwrap();
+
+        assert_eq!(cfg.blocks.len(), 4);
+        assert_eq!(cfg.blocks[0].successors, vec![2, 1]);
+        assert_eq!(cfg.blocks[1].successors, vec![3]);
+        assert_eq!(cfg.blocks[3].predecessors, vec![1, 2]);
+        assert_eq!(cfg.blocks[3].calls, vec![CallSite { offset: 0x10c, target: Some(0x4000) }]);
+        assert!(cfg.is_branch_target(0x109));
+        assert!(!cfg.is_branch_target(0x103));
+        assert!(cfg.back_edges().is_empty());
+    }
+
+    #[test]
+    fn test_cfg_2() {
+        let f = fixture(&["movi a8, 0", "addi a8, a8, 1", "bnei a8, 4, 0x103", "j 0x200"]);
+        let cfg = Cfg::build(&f).unwrap();
+
+        assert_eq!(cfg.blocks.len(), 3);
+        assert_eq!(cfg.back_edges(), vec![Edge { from: 1, to: 1, kind: EdgeKind::Branch }]);
+        assert_eq!(cfg.blocks[2].exits, vec![0x200]);
+        assert_eq!(cfg.block_of(2), Some(1));
+
+        let dot = cfg.to_dot(&f);
+        assert!(dot.starts_with("digraph \"f\" {"));
+        assert!(dot.contains("bb_1 -> bb_1;"));
+        assert!(dot.contains("bb_0 -> bb_1 [style=dashed];"));
+    }
+
+    #[test]
+    fn test_cfg_3() {
+        let f = fixture(&["movi a2, 0", "loopnez a3, 0x10c", "addi a2, a2, 1", "addi a2, a2, 1", "ret"]);
+        let cfg = Cfg::build(&f).unwrap();
+
+        assert_eq!(cfg.loops, vec![HardwareLoop { start: 1, end: 4 }]);
+        assert_eq!(cfg.blocks.len(), 3);
+        assert_eq!(cfg.blocks[0].successors, vec![2, 1]);
+        assert_eq!(cfg.back_edges(), vec![Edge { from: 1, to: 1, kind: EdgeKind::Branch }]);
+        assert!(cfg.is_branch_target(0x106));
+    }
+}
@@ -0,0 +1,347 @@
+use std::vec::Vec;
+use std::fs::File;
+use std::io::Read;
+
+use error::{Error, Result};
+
+/// xtensa machine id
+pub const EM_XTENSA: u16 = 94;
+
+const SHT_SYMTAB: u32 = 2;
+const SHT_NOBITS: u32 = 8;
+
+const SHF_ALLOC: u32 = 0x2;
+const SHF_EXECINSTR: u32 = 0x4;
+
+const STT_NOTYPE: u8 = 0;
+const STT_OBJECT: u8 = 1;
+const STT_FUNC: u8 = 2;
+
+const SHN_UNDEF: u16 = 0;
+const SHN_LORESERVE: u16 = 0xff00;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum SymbolKind {
+    NoType,
+    Object,
+    Function,
+    Other,
+}
+
+/// ELF section header along with section contents
+pub struct Section {
+    pub name: String,
+    pub address: u32,
+    pub size: u32,
+    pub kind: u32,
+    pub flags: u32,
+    /// Section contents, zero-filled for .bss-like sections
+    pub data: Vec<u8>,
+}
+
+/// Symbol table entry
+pub struct Symbol {
+    pub name: String,
+    pub value: u32,
+    pub size: u32,
+    pub kind: SymbolKind,
+    /// Index of the section this symbol belongs to
+    pub section: u16,
+}
+
+/// Minimal ELF32 little-endian reader.
+/// Only section headers and the symbol table
+/// are parsed, program headers are ignored
+pub struct Elf {
+    pub machine: u16,
+    pub entry: u32,
+    pub flags: u32,
+    pub sections: Vec<Section>,
+    pub symbols: Vec<Symbol>,
+}
+
+fn read_u16(data: &[u8], offset: usize) -> Result<u16> {
+    if offset + 2 > data.len() {
+        return Err(Error::Elf(format!("read past the end of file at 0x{:x}", offset)));
+    }
+
+    Ok((data[offset] as u16) | ((data[offset + 1] as u16) << 8))
+}
+
+fn read_u32(data: &[u8], offset: usize) -> Result<u32> {
+    if offset + 4 > data.len() {
+        return Err(Error::Elf(format!("read past the end of file at 0x{:x}", offset)));
+    }
+
+    Ok((data[offset] as u32) |
+        ((data[offset + 1] as u32) << 8) |
+        ((data[offset + 2] as u32) << 16) |
+        ((data[offset + 3] as u32) << 24))
+}
+
+/// Reads NUL-terminated string from a string table
+fn read_str(table: &[u8], offset: usize) -> String {
+    if offset >= table.len() {
+        return String::new();
+    }
+
+    let end = table[offset..].iter()
+        .position(|&c| c == 0)
+        .map(|p| offset + p)
+        .unwrap_or(table.len());
+
+    String::from_utf8_lossy(&table[offset..end]).into_owned()
+}
+
+impl Section {
+    /// Whether this section occupies memory at run time
+    pub fn is_alloc(&self) -> bool {
+        self.flags & SHF_ALLOC != 0
+    }
+
+    /// Whether this section contains executable code
+    pub fn is_exec(&self) -> bool {
+        self.flags & SHF_EXECINSTR != 0
+    }
+
+    /// Whether the provided address belongs to this section
+    pub fn contains(&self, address: u32) -> bool {
+        self.is_alloc() &&
+            address >= self.address &&
+            (address as u64) < (self.address as u64) + (self.size as u64)
+    }
+}
+
+impl Elf {
+    pub fn open(path: &str) -> Result<Elf> {
+        let mut file = File::open(path).map_err(|why| Error::io(path, why))?;
+        let mut data = Vec::<u8>::new();
+
+        file.read_to_end(&mut data).map_err(|why| Error::io(path, why))?;
+
+        Elf::parse(&data)
+    }
+
+    pub fn parse(data: &[u8]) -> Result<Elf> {
+        if data.len() < 52 || &data[0..4] != b"\x7fELF" {
+            return Err(Error::Elf("bad magic".to_string()));
+        }
+
+        if data[4] != 1 {
+            return Err(Error::Elf("only 32-bit files are supported".to_string()));
+        }
+
+        if data[5] != 1 {
+            return Err(Error::Elf("only little-endian files are supported".to_string()));
+        }
+
+        let mut elf = Elf {
+            machine: read_u16(data, 18)?,
+            entry: read_u32(data, 24)?,
+            flags: read_u32(data, 36)?,
+            sections: Vec::new(),
+            symbols: Vec::new(),
+        };
+
+        let sh_offset = read_u32(data, 32)? as usize;
+        let sh_entsize = read_u16(data, 46)? as usize;
+        let sh_count = read_u16(data, 48)? as usize;
+        let sh_strndx = read_u16(data, 50)? as usize;
+
+        let mut name_offsets = Vec::<usize>::new();
+        let mut links = Vec::<usize>::new();
+
+        for i in 0 .. sh_count {
+            let header = sh_offset + i * sh_entsize;
+            let kind = read_u32(data, header + 4)?;
+            let offset = read_u32(data, header + 16)? as usize;
+            let size = read_u32(data, header + 20)?;
+
+            let contents = if kind == SHT_NOBITS {
+                vec![0; size as usize]
+            } else {
+                if offset + size as usize > data.len() {
+                    return Err(Error::Elf(format!("section {:} is out of file bounds", i)));
+                }
+
+                data[offset .. offset + size as usize].to_vec()
+            };
+
+            name_offsets.push(read_u32(data, header)? as usize);
+            links.push(read_u32(data, header + 24)? as usize);
+
+            elf.sections.push(Section {
+                name: String::new(),
+                address: read_u32(data, header + 12)?,
+                size,
+                kind,
+                flags: read_u32(data, header + 8)?,
+                data: contents,
+            });
+        }
+
+        if sh_strndx < elf.sections.len() {
+            let names: Vec<String> = name_offsets.iter()
+                .map(|&offset| read_str(&elf.sections[sh_strndx].data, offset))
+                .collect();
+
+            for (section, name) in elf.sections.iter_mut().zip(names) {
+                section.name = name;
+            }
+        }
+
+        for (i, &link) in links.iter().enumerate() {
+            if elf.sections[i].kind == SHT_SYMTAB {
+                let symbols = elf.symbols_parse(i, link)?;
+                elf.symbols.extend(symbols);
+            }
+        }
+
+        Ok(elf)
+    }
+
+    fn symbols_parse(&self, index: usize, strtab: usize) -> Result<Vec<Symbol>> {
+        let table = &self.sections[index].data;
+        let strings = match self.sections.get(strtab) {
+            Some(s) => &s.data,
+            None => return Err(Error::Elf(format!("bad string table index {:}", strtab)))
+        };
+        let mut result = Vec::<Symbol>::new();
+
+        for i in 0 .. table.len() / 16 {
+            let entry = i * 16;
+            let info = table[entry + 12];
+
+            result.push(Symbol {
+                name: read_str(strings, read_u32(table, entry)? as usize),
+                value: read_u32(table, entry + 4)?,
+                size: read_u32(table, entry + 8)?,
+                kind: match info & 0xf {
+                    STT_NOTYPE => SymbolKind::NoType,
+                    STT_OBJECT => SymbolKind::Object,
+                    STT_FUNC => SymbolKind::Function,
+                    _ => SymbolKind::Other,
+                },
+                section: read_u16(table, entry + 14)?,
+            });
+        }
+
+        Ok(result)
+    }
+
+    /// Returns the section a symbol is defined in
+    pub fn symbol_section<'a>(&'a self, symbol: &Symbol) -> Option<&'a Section> {
+        if symbol.section == SHN_UNDEF || symbol.section >= SHN_LORESERVE {
+            return None;
+        }
+
+        self.sections.get(symbol.section as usize)
+    }
+
+    /// Looks up a section by name
+    pub fn get_section<'a>(&'a self, name: &str) -> Option<&'a Section> {
+        self.sections.iter().find(|s| s.name == name)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use elf::{Elf, SymbolKind, EM_XTENSA};
+    use error::Error;
+
+    fn push_u16(v: &mut Vec<u8>, x: u16) {
+        v.push(x as u8);
+        v.push((x >> 8) as u8);
+    }
+
+    fn push_u32(v: &mut Vec<u8>, x: u32) {
+        push_u16(v, x as u16);
+        push_u16(v, (x >> 16) as u16);
+    }
+
+    /// Appends a section header from its name, type, flags,
+    /// address, offset, size and link fields
+    fn push_section(v: &mut Vec<u8>, fields: [u32; 7]) {
+        for x in fields.iter().chain(&[0, 4, 0]) {
+            push_u32(v, *x);
+        }
+    }
+
+    /// Builds an image with .text, .symtab, .strtab and .shstrtab sections
+    fn image() -> Vec<u8> {
+        let text = [0x0du8, 0xf0];
+        let strtab = b"\0f\0";
+        let shstrtab = b"\0.text\0.symtab\0.strtab\0.shstrtab\0";
+
+        let text_offset = 52;
+        let symtab_offset = text_offset + 4;
+        let strtab_offset = symtab_offset + 32;
+        let shstrtab_offset = strtab_offset + strtab.len() as u32;
+        let sh_offset = (shstrtab_offset + shstrtab.len() as u32 + 3) & !3;
+
+        let mut v = Vec::<u8>::new();
+        v.extend_from_slice(b"\x7fELF\x01\x01\x01\0\0\0\0\0\0\0\0\0");
+        push_u16(&mut v, 2);
+        push_u16(&mut v, EM_XTENSA);
+        push_u32(&mut v, 1);
+        push_u32(&mut v, 0x40100000);
+        push_u32(&mut v, 0);
+        push_u32(&mut v, sh_offset);
+        push_u32(&mut v, 0);
+        push_u16(&mut v, 52);
+        push_u16(&mut v, 0);
+        push_u16(&mut v, 0);
+        push_u16(&mut v, 40);
+        push_u16(&mut v, 5);
+        push_u16(&mut v, 4);
+
+        v.extend_from_slice(&text);
+        v.extend_from_slice(&[0, 0]);
+
+        v.extend_from_slice(&[0; 16]);
+        push_u32(&mut v, 1);
+        push_u32(&mut v, 0x40100000);
+        push_u32(&mut v, 2);
+        v.extend_from_slice(&[0x12, 0]);
+        push_u16(&mut v, 1);
+
+        v.extend_from_slice(strtab);
+        v.extend_from_slice(shstrtab);
+
+        while (v.len() as u32) < sh_offset {
+            v.push(0);
+        }
+
+        push_section(&mut v, [0, 0, 0, 0, 0, 0, 0]);
+        push_section(&mut v, [1, 1, 6, 0x40100000, text_offset, 2, 0]);
+        push_section(&mut v, [7, 2, 0, 0, symtab_offset, 32, 3]);
+        push_section(&mut v, [15, 3, 0, 0, strtab_offset, strtab.len() as u32, 0]);
+        push_section(&mut v, [23, 3, 0, 0, shstrtab_offset, shstrtab.len() as u32, 0]);
+
+        v
+    }
+
+    #[test]
+    fn test_elf_1() {
+        assert_eq!(Elf::parse(&[0; 64]).err(), Some(Error::Elf("bad magic".to_string())));
+    }
+
+    #[test]
+    fn test_elf_2() {
+        let elf = Elf::parse(&image()).unwrap();
+
+        assert_eq!(elf.machine, EM_XTENSA);
+        assert_eq!(elf.sections.len(), 5);
+        assert_eq!(elf.sections[1].name, ".text");
+        assert!(elf.sections[1].is_exec());
+        assert!(elf.sections[1].contains(0x40100001));
+        assert_eq!(elf.sections[1].data, vec![0x0d, 0xf0]);
+
+        let symbol = elf.symbols.iter().find(|s| s.name == "f").unwrap();
+
+        assert_eq!(symbol.value, 0x40100000);
+        assert_eq!(symbol.size, 2);
+        assert_eq!(symbol.kind, SymbolKind::Function);
+        assert_eq!(elf.symbol_section(symbol).unwrap().name, ".text");
+    }
+}
@@ -0,0 +1,99 @@
+use std::error;
+use std::fmt;
+use std::result;
+
+/// Errors reported by parsing, translation and output
+#[derive(Clone, PartialEq, Debug)]
+pub enum Error {
+    /// File couldn't be opened, read or written
+    Io { path: String, message: String },
+    /// Malformed input ELF
+    Elf(String),
+    /// Unexpected radare2 JSON output
+    Json(String),
+    /// Function or pattern that can't be selected
+    Selection(String),
+    /// Mnemonic not known to the parser
+    UnknownMnemonic(String),
+    /// Operand that can't be parsed
+    BadOperand(String),
+    /// Instruction has fewer operands than its mnemonic requires
+    MissingOperand,
+    /// Bytes the decoder doesn't know, at an address
+    Decode { address: u32, bytes: String },
+    /// Instruction the translator has no lowering for
+    Unsupported(String),
+    /// Address of a call or literal that can't be resolved
+    Unresolved(u32),
+    /// Instruction not valid in the input ABI
+    Abi(String),
+    /// Malformed machine configuration file
+    Config(String),
+    /// Failure of a single instruction of a function
+    Instruction { function: String, offset: u32, text: String, cause: Box<Error> },
+}
+
+pub type Result<T> = result::Result<T, Error>;
+
+impl Error {
+    pub fn io<E: fmt::Display>(path: &str, why: E) -> Error {
+        Error::Io { path: path.to_string(), message: why.to_string() }
+    }
+
+    /// Attaches location of the instruction which failed
+    pub fn at(self, function: &str, offset: u32, text: &str) -> Error {
+        Error::Instruction {
+            function: function.to_string(),
+            offset,
+            text: text.to_string(),
+            cause: Box::new(self),
+        }
+    }
+
+    /// Returns the error with instruction location stripped
+    pub fn cause(&self) -> &Error {
+        match *self {
+            Error::Instruction { ref cause, .. } => cause.cause(),
+            _ => self
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::Io { ref path, ref message } => write!(f, "{:}: {:}", path, message),
+            Error::Elf(ref s) => write!(f, "ELF: {:}", s),
+            Error::Json(ref s) => write!(f, "radare2 output: {:}", s),
+            Error::Selection(ref s) => write!(f, "{:}", s),
+            Error::UnknownMnemonic(ref s) => write!(f, "opcode not supported: {:}", s),
+            Error::BadOperand(ref s) => write!(f, "bad operand: {:?}", s),
+            Error::MissingOperand => write!(f, "missing operand"),
+            Error::Decode { address, ref bytes } =>
+                write!(f, "instruction not supported at 0x{:x}: {:}", address, bytes),
+            Error::Unsupported(ref s) => write!(f, "translation not supported: {:}", s),
+            Error::Unresolved(address) => write!(f, "unable to resolve address 0x{:x}", address),
+            Error::Abi(ref s) => write!(f, "{:}", s),
+            Error::Config(ref s) => write!(f, "configuration: {:}", s),
+            Error::Instruction { ref function, offset, ref text, ref cause } if text.is_empty() =>
+                write!(f, "{:} at 0x{:x}: {:}", function, offset, cause),
+            Error::Instruction { ref function, offset, ref text, ref cause } =>
+                write!(f, "{:} at 0x{:x} `{:}`: {:}", function, offset, text, cause),
+        }
+    }
+}
+
+impl error::Error for Error {}
+
+#[cfg(test)]
+mod tests {
+    use error::Error;
+
+    #[test]
+    fn test_error_1() {
+        let e = Error::UnknownMnemonic("wsr".to_string()).at("f", 0x40100000, "wsr a2, sar");
+
+        assert_eq!(e.to_string(), "f at 0x40100000 `wsr a2, sar`: opcode not supported: wsr");
+        assert_eq!(*e.cause(), Error::UnknownMnemonic("wsr".to_string()));
+    }
+}
@@ -2,7 +2,10 @@ use std::vec::Vec;
 use std::default::Default;
 
 use rustc_serialize::json;
-use assembly::{Instruction, InstructionKind, InstructionArch};
+use assembly::{Instruction, InstructionKind, InstructionArch, ParseInstruction, Operand};
+use translation::xtensa_decode;
+use translation::xtensa_op::{XtensaInstruction, XtensaOpcode};
+use error::{Error, Result};
 
 #[derive(Default)]
 pub struct Function {
@@ -15,26 +18,28 @@ impl Function {
         Default::default()
     }
 
-    pub fn from_json(&mut self, json: json::Json) {
-        let json_object = json.into_object().unwrap();
-        let json_ops = json_object.get("ops").cloned().unwrap();
-        let array_ops = json_ops.into_array().unwrap();
+    pub fn from_json(&mut self, json: json::Json) -> Result<()> {
+        let name = self.name.clone();
+        let bad = |what: &str| Error::Json(format!("unexpected {:} in function {:}", what, name));
+        let json_object = json.into_object().ok_or_else(|| bad("function"))?;
+        let json_ops = json_object.get("ops").cloned().ok_or_else(|| bad("ops"))?;
+        let array_ops = json_ops.into_array().ok_or_else(|| bad("ops"))?;
 
 		for element in &array_ops {
-            let json_object = element.as_object().unwrap();
+            let json_object = element.as_object().ok_or_else(|| bad("op"))?;
             let mut instruction = Instruction::new();
 
 		    for (key, value) in json_object.iter() {
                 match key as &str {
-                    "opcode" => { instruction.opcode = String::from(value.as_string().unwrap()) }
-                    "offset" => { instruction.offset = value.as_u64().unwrap() as u32 }
+                    "opcode" => { instruction.opcode = String::from(value.as_string().ok_or_else(|| bad(key))?) }
+                    "offset" => { instruction.offset = value.as_u64().ok_or_else(|| bad(key))? as u32 }
                     "type" => {
-                        instruction.kind = match value.as_string().unwrap() as &str {
+                        instruction.kind = match value.as_string().ok_or_else(|| bad(key))? as &str {
                             "store" => InstructionKind::Store,
                             "load" => InstructionKind::Load,
                             "cjmp" => {
-                                let jump = json_object.get("jump").unwrap();
-                                let address = jump.as_u64().unwrap() as u32;
+                                let jump = json_object.get("jump").and_then(|j| j.as_u64());
+                                let address = jump.ok_or_else(|| bad("jump"))? as u32;
                                 InstructionKind::BranchImm { target: address }
                             }
                             _ => InstructionKind::Other
@@ -47,5 +52,112 @@ impl Function {
             instruction.arch = InstructionArch::Xtensa;
             self.instructions.push(instruction);
         }
+
+        Ok(())
     }
+
+    /// Decodes raw function code located at `address`.
+    /// Unknown encodings are kept as raw bytes so that
+    /// the rest of the function is still usable
+    pub fn decode(&mut self, data: &[u8], address: u32) -> Result<()> {
+        let mut offset = 0;
+
+        while offset < data.len() {
+            let pc = address + offset as u32;
+            let length = xtensa_decode::length(data[offset]);
+            let (decoded, size) = match xtensa_decode::decode(&data[offset..], pc) {
+                Ok(result) => result,
+                Err(Error::Decode { .. }) if offset + length <= data.len() => {
+                    (XtensaInstruction::undecoded(&data[offset .. offset + length]), length)
+                }
+                Err(e) => return Err(e.at(&self.name, pc, "")),
+            };
+            let mut instruction = Instruction::new();
+
+            instruction.kind = match decoded.opcode {
+                XtensaOpcode::L32i |
+                XtensaOpcode::L32r |
+                XtensaOpcode::L16ui |
+                XtensaOpcode::L16si |
+                XtensaOpcode::L8ui |
+                XtensaOpcode::L32ai => InstructionKind::Load,
+                XtensaOpcode::S32i |
+                XtensaOpcode::S16i |
+                XtensaOpcode::S8i |
+                XtensaOpcode::S32ri |
+                XtensaOpcode::S32c1i => InstructionKind::Store,
+                _ if decoded.is_branch() => {
+                    let address = decoded.branch_target().unwrap();
+                    InstructionKind::BranchImm { target: address }
+                }
+                _ => InstructionKind::Other
+            };
+
+            instruction.opcode = match decoded.opcode {
+                XtensaOpcode::Other => {
+                    let bytes: Vec<String> = data[offset .. offset + size].iter()
+                        .map(|b| format!("0x{:02x}", b))
+                        .collect();
+                    format!(".byte {:}", bytes.join(", "))
+                }
+                _ => decoded.to_string()
+            };
+            instruction.offset = pc;
+            instruction.arch = InstructionArch::Xtensa;
+            instruction.decoded = Some(decoded);
+
+            self.instructions.push(instruction);
+            offset += size;
+        }
+
+        Ok(())
+    }
+
+    /// Returns xtensa instruction at `index`, parsing
+    /// it if it was not decoded from binary
+    pub fn xtensa_instruction(&self, index: usize) -> Result<XtensaInstruction> {
+        let instruction = &self.instructions[index];
+        let result = match instruction.decoded {
+            Some(ref decoded) if decoded.opcode == XtensaOpcode::Other => {
+                // Raw bytes, printed the way the decoder prints words
+                let bytes: Vec<String> = decoded.operands.iter()
+                    .rev()
+                    .map(|o| format!("{:02x}", o.get_imm()))
+                    .collect();
+                Err(Error::Decode { address: instruction.offset, bytes: bytes.concat() })
+            }
+            Some(ref decoded) => Ok(decoded.clone()),
+            None => {
+                let mut parsed = XtensaInstruction::new();
+                parsed.from_str(&instruction.opcode).map(|_| parsed)
+            }
+        };
+
+        result.map_err(|e| e.at(&self.name, instruction.offset, &instruction.opcode))
+    }
+
+    /// Returns xtensa instructions of the function, parsing
+    /// the ones which were not decoded from binary
+    pub fn xtensa_instructions(&self) -> Result<Vec<XtensaInstruction>> {
+        (0 .. self.instructions.len())
+            .map(|i| self.xtensa_instruction(i))
+            .collect()
+    }
+}
+
+/// Test fixture: function `f` made of the given instructions,
+/// placed 3 bytes apart starting at 0x100
+#[cfg(test)]
+pub fn fixture(code: &[&str]) -> Function {
+    let mut f = Function::new();
+
+    for (n, s) in code.iter().enumerate() {
+        let mut i = Instruction::new();
+        i.opcode = s.to_string();
+        i.offset = 0x100 + 3 * n as u32;
+        f.instructions.push(i);
+    }
+
+    f.name = "f".to_string();
+    f
 }
@@ -1,35 +1,93 @@
 #![allow(dead_code)]
 
-extern crate r2pipe;
 extern crate rustc_serialize;
 extern crate clap;
+extern crate regex;
 
+mod error;
+mod elf;
 mod object_storage;
 mod function;
+mod cfg;
+mod selection;
+mod stats;
 mod assembly;
 mod translation;
+#[cfg(feature = "radare2")]
+mod r2pipe;
 
+#[cfg(feature = "radare2")]
 use r2pipe::R2Pipe;
 
+use elf::Elf;
+use error::{Error, Result};
 use function::{Function};
-use object_storage::{Object, ObjectKind, ObjectStorage};
-use translation::xtensa_arm::Translator;
+use cfg::Cfg;
+use object_storage::ObjectStorage;
+use selection::FunctionSelection;
+use stats::Coverage;
+use rustc_serialize::json::ToJson;
+use translation::{xtensa_arm, xtensa_aarch64, xtensa_c};
+use translation::backend::{Backend, Target};
+use translation::xtensa_abi::XtensaAbi;
+use translation::arm_cpu::{ArmCpu, InstructionSet};
+use translation::xtensa_sr::SrMap;
 
 use clap::{Arg, SubCommand};
 
-use std::cell::{RefCell, RefMut};
-use std::rc::Rc;
 use std::option::Option;
 use std::boxed::Box;
-use std::error::Error;
 use std::fs::File;
-use std::io::Write;
+use std::io::{self, Write};
+use std::fs;
+use std::path::Path;
+use std::process;
+use std::mem;
+use std::collections::BTreeSet;
 
 #[derive(Default)]
 struct App {
     objects: ObjectStorage,
     functions_in: Vec<Function>,
     functions_out: Vec<Function>,
+    /// Names of functions read so far, including ones
+    /// which failed, so each is only tried once
+    visited: BTreeSet<String>,
+    selection: FunctionSelection,
+    /// Translate functions reachable through call0
+    follow_calls: bool,
+    /// Call graph depth limit for `follow_calls`
+    follow_depth: Option<u32>,
+    /// Functions provided elsewhere which are
+    /// not translated when following calls
+    exclude: FunctionSelection,
+    /// Input ABI, detected from ELF if not set
+    abi: Option<XtensaAbi>,
+    /// Output architecture
+    target: Target,
+    /// ARM architecture revision of the output
+    cpu: ArmCpu,
+    /// Output instruction set on ARM
+    isa: InstructionSet,
+    /// Special register mapping of the machine
+    special_regs: SrMap,
+    /// Output file path, `-` for stdout
+    output: String,
+    /// Files included at the top of the output
+    includes: Vec<String>,
+    /// File scope declarations needed by C output
+    declarations: Vec<String>,
+    /// Directory to dump control-flow graphs to
+    dot_dir: Option<String>,
+    /// Number of functions which failed to read or translate
+    failed: usize,
+    /// Emit placeholders for untranslatable instructions
+    lenient: bool,
+    /// Function called by placeholders instead of `udf`
+    placeholder_helper: Option<String>,
+    /// Run the IR optimization passes over ARM output
+    optimize: bool,
+    #[cfg(feature = "radare2")]
     pipe: Box<Option<R2Pipe>>,
 }
 
@@ -38,96 +96,415 @@ impl App {
         Default::default()
     }
 
-    fn symbols_read(&mut self) {
-        let symbols = self.pipe_get().cmdj("isj").unwrap();
-        self.objects.from_json(symbols);
+    /// Reports an error which only affects a single function
+    fn diagnostic(&mut self, error: Error) {
+        eprintln!("error: {:}", error);
+        self.failed += 1;
+    }
+
+    fn symbols_read(&mut self, input: &str) -> Result<()> {
+        let elf = Elf::open(input)?;
+
+        if self.abi.is_none() {
+            self.abi = elf.get_section(".xtensa.info")
+                .and_then(|s| XtensaAbi::from_info(&String::from_utf8_lossy(&s.data)));
+
+            if let Some(abi) = self.abi {
+                eprintln!("Detected ABI: {:?}", abi);
+            }
+        }
+
+        self.objects.load_elf(elf);
 
-        println!("Read symbols: {:?}", self.objects.len());
+        eprintln!("Read symbols: {:?}", self.objects.len());
+        Ok(())
     }
 
-    fn analyze(&mut self) {
-        self.pipe_get().cmd("aa").unwrap();
+    #[cfg(feature = "radare2")]
+    fn analyze(&mut self) -> Result<()> {
+        self.pipe_get().cmd("aa").map_err(Error::Json)?;
+        Ok(())
     }
 
-    fn functions_create(&mut self) {
-        let names = vec!["sdk_rom_i2c_writeReg"];
+    fn function_read(&mut self, name: &str) -> Result<Function> {
+        let mut f = Function::new();
+        f.name = name.to_string();
+
+        #[cfg(feature = "radare2")]
+        {
+            if self.pipe.is_some() {
+                let command = format!("pdfj @ sym.{:}", name);
+                let json = self.pipe_get().cmdj(&command).map_err(Error::Json)?;
+
+                f.from_json(json)?;
+                return Ok(f);
+            }
+        }
+
+        let object = match self.objects.get_object_by_name(name) {
+            Some(o) => o,
+            None => return Err(Error::Selection(format!("Function not found: {:}", name)))
+        };
+
+        let data = match self.objects.object_data(object) {
+            Some(d) => d,
+            None => return Err(Error::Unresolved(object.address))
+        };
+
+        f.decode(data, object.address)?;
+        Ok(f)
+    }
+
+    fn functions_create(&mut self) -> Result<()> {
+        let names = self.selection.select(&self.objects)?;
+
+        eprintln!("Selected functions: {:?}", names.len());
 
-        for function in &names {
-            let mut f = Function::new();
-            let command = format!("pdfj @ sym.{:}", function);
-            let json = self.pipe_get().cmdj(&command).unwrap();
+        for name in &names {
+            self.visited.insert(name.clone());
 
-            f.from_json(json);
-            f.name = function.to_string();
+            match self.function_read(name) {
+                Ok(f) => self.functions_in.push(f),
+                Err(e) => self.diagnostic(e),
+            }
+        }
 
-            self.functions_in.push(f);
+        Ok(())
+    }
+
+    /// Creates translator for the selected target
+    fn backend(&self, lenient: bool) -> Box<dyn Backend> {
+        let abi = self.abi.unwrap_or_default();
+
+        match self.target {
+            Target::Arm => {
+                let mut translator = xtensa_arm::Translator::with_abi(abi);
+                translator.cpu = self.cpu;
+                translator.isa = self.isa;
+                translator.special_regs = self.special_regs.clone();
+                translator.lenient = lenient;
+                translator.placeholder_helper = self.placeholder_helper.clone();
+                translator.optimize = self.optimize;
+                Box::new(translator)
+            }
+            Target::Aarch64 => {
+                let mut translator = xtensa_aarch64::Translator::with_abi(abi);
+                translator.special_regs = self.special_regs.clone();
+                translator.lenient = lenient;
+                translator.placeholder_helper = self.placeholder_helper.clone();
+                translator.optimize = self.optimize;
+                Box::new(translator)
+            }
+            Target::C => {
+                let mut translator = xtensa_c::Translator::with_abi(abi);
+                translator.special_regs = self.special_regs.clone();
+                translator.lenient = lenient;
+                translator.placeholder_helper = self.placeholder_helper.clone();
+                translator.optimize = self.optimize;
+                Box::new(translator)
+            }
         }
     }
 
     fn functions_translate(&mut self) {
-        let mut translator = Translator::new();
-        let mut pipe = self.pipe.as_mut().as_mut().unwrap();
-        let ref mut objects = self.objects;
+        let mut translator = self.backend(self.lenient);
+        let mut depths = vec![0; self.functions_in.len()];
+        let mut i = 0;
+
+        // functions_in grows as callees are discovered
+        while i < self.functions_in.len() {
+            let depth = depths[i];
+
+            match translator.translate(&mut self.functions_in[i], &mut self.objects) {
+                Ok(function) => self.functions_out.push(function),
+                Err(e) => {
+                    self.diagnostic(e);
+                    i += 1;
+                    continue;
+                }
+            }
+
+            i += 1;
+
+            if !self.follow_calls || self.follow_depth.is_some_and(|d| depth >= d) {
+                continue;
+            }
+
+            let callees: Vec<String> = translator.referenced_objects().iter()
+                .filter_map(|&a| self.objects.get_object(a))
+                .map(|o| o.name.clone())
+                .collect();
+
+            for name in callees {
+                if !self.visited.insert(name.clone()) {
+                    continue;
+                }
+
+                if self.exclude.matches(&name) {
+                    eprintln!("Excluded call target: {:}", name);
+                    continue;
+                }
+
+                match self.function_read(&name) {
+                    Ok(f) => {
+                        self.functions_in.push(f);
+                        depths.push(depth + 1);
+                    }
+                    Err(e) => self.diagnostic(e),
+                }
+            }
+        }
+
+        eprintln!("Translated functions: {:?}", self.functions_out.len());
+
+        self.declarations = translator.declarations();
+
+        let placeholders = translator.placeholders();
 
-        for function in &mut self.functions_in {
-            let function = translator.translate(function, pipe, objects);
-            self.functions_out.push(function);
+        if !placeholders.is_empty() {
+            eprintln!("Placeholders: {:?}", placeholders.len());
+
+            for placeholder in placeholders.iter() {
+                eprintln!("\t{:}", placeholder);
+            }
         }
     }
 
-    fn pipe_create(&mut self, input: &str) {
-        println!("Opening r2pipe");
+    /// Translates every selected function in lenient mode
+    /// and reports what couldn't be parsed or translated
+    fn stats(&mut self, json: Option<&str>) -> Result<()> {
+        let mut translator = self.backend(true);
+        let mut coverage = Coverage::new();
+
+        for name in self.selection.select(&self.objects)? {
+            let address = self.objects.get_object_by_name(&name).map_or(0, |o| o.address);
+            let mut function = match self.function_read(&name) {
+                Ok(f) => f,
+                Err(e) => {
+                    coverage.add_failure(&name, address, &e);
+                    continue;
+                }
+            };
+
+            match translator.translate(&mut function, &mut self.objects) {
+                Ok(_) => {
+                    let placeholders = mem::take(translator.placeholders());
+                    coverage.add_function(&function, address, &placeholders);
+                }
+                Err(e) => coverage.add_failure(&name, address, &e),
+            }
+        }
+
+        let report = format!("{:}\n", coverage.to_json().pretty());
+
+        match json {
+            Some("-") => print!("{:}", report),
+            Some(path) => {
+                print!("{:}", coverage.table());
+                File::create(path)
+                    .and_then(|mut file| file.write_all(report.as_bytes()))
+                    .map_err(|why| Error::io(path, why))?;
+            }
+            None => print!("{:}", coverage.table()),
+        }
+
+        Ok(())
+    }
+
+    #[cfg(feature = "radare2")]
+    fn pipe_create(&mut self, input: &str) -> Result<()> {
+        eprintln!("Opening r2pipe");
 
-        let spawn_input = input.to_string();
-        self.pipe = Box::new(Some(R2Pipe::spawn(spawn_input, None).unwrap()));
+        let pipe = R2Pipe::spawn(input).map_err(|why| Error::io(input, why))?;
+
+        self.pipe = Box::new(Some(pipe));
+        Ok(())
     }
 
+    #[cfg(feature = "radare2")]
     fn pipe_get<'a>(&'a mut self) -> &'a mut R2Pipe {
         self.pipe.as_mut().as_mut().unwrap()
     }
 
+    #[cfg(feature = "radare2")]
     fn pipe_close(&mut self) {
-        println!("Closing r2pipe");
+        if self.pipe.is_none() {
+            return;
+        }
+
+        eprintln!("Closing r2pipe");
         self.pipe_get().close();
     }
 
-    fn output_write(&self) {
-        let mut file = match File::create("result.S") {
-            Err(why) => panic!("Couldn't open output file: {}", why.description()),
-            Ok(file) => file,
+    #[cfg(not(feature = "radare2"))]
+    fn pipe_create(&mut self, _input: &str) -> Result<()> {
+        Err(Error::Selection("--radare2 needs a build with the radare2 feature".to_string()))
+    }
+
+    #[cfg(not(feature = "radare2"))]
+    fn pipe_close(&mut self) {}
+
+    /// Writes CFG of every input function to `<name>.dot`
+    fn cfg_write(&mut self) -> Result<()> {
+        let dir = match self.dot_dir {
+            Some(ref d) => d.clone(),
+            None => return Ok(())
+        };
+
+        let mut written = 0;
+
+        for i in 0 .. self.functions_in.len() {
+            let cfg = match Cfg::build(&self.functions_in[i]) {
+                Ok(cfg) => cfg,
+                Err(e) => {
+                    self.diagnostic(e);
+                    continue;
+                }
+            };
+
+            let function = &self.functions_in[i];
+            let path = Path::new(&dir).join(format!("{:}.dot", function.name));
+            let path_str = path.display().to_string();
+
+            File::create(&path)
+                .and_then(|mut file| file.write_all(cfg.to_dot(function).as_bytes()))
+                .map_err(|why| Error::io(&path_str, why))?;
+
+            written += 1;
+        }
+
+        eprintln!("Written control-flow graphs: {:?}", written);
+        Ok(())
+    }
+
+    /// Checks machine description exists and adds it to includes,
+    /// except for C output. Special register mapping is read
+    /// from NAME.sr if present
+    fn mach_select(&mut self, mach_dir: &str, mach: &str) -> Result<()> {
+        let file = format!("{:}.S", mach);
+
+        if !Path::new(mach_dir).join(&file).is_file() {
+            let mut available: Vec<String> = match fs::read_dir(mach_dir) {
+                Ok(entries) => entries
+                    .filter_map(|e| e.ok())
+                    .filter_map(|e| e.file_name().into_string().ok())
+                    .filter(|n| n.ends_with(".S"))
+                    .map(|n| n.trim_end_matches(".S").to_string())
+                    .collect(),
+                Err(_) => Vec::new(),
+            };
+
+            available.sort();
+            return Err(Error::Selection(format!("Unknown machine {:}, available in {:}: {:}",
+                mach, mach_dir, available.join(", "))));
+        }
+
+        let sr_file = Path::new(mach_dir).join(format!("{:}.sr", mach));
+
+        if sr_file.is_file() {
+            self.special_regs = SrMap::open(&sr_file.to_string_lossy())?;
+        }
+
+        if self.target != Target::C {
+            self.includes.insert(0, file);
+        }
+
+        Ok(())
+    }
+
+    fn output_write(&self) -> Result<()> {
+        let result = if self.output == "-" {
+            let stdout = io::stdout();
+            let mut out = stdout.lock();
+            self.output_emit(&mut out)
+        } else {
+            File::create(&self.output).and_then(|mut file| self.output_emit(&mut file))
         };
 
-        let includes = vec!["esp8266.S"];
+        result.map_err(|why| Error::io(&self.output, why))
+    }
 
-        for include in &includes {
+    fn output_emit<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        for include in &self.includes {
             let s = format!("#include \"{:}\"\n", include);
-            file.write(s.as_bytes()).unwrap();
+            out.write_all(s.as_bytes())?;
+        }
+
+        if self.target == Target::C {
+            return self.output_emit_c(out);
+        }
+
+        let thumb = self.isa == InstructionSet::Thumb2;
+
+        if thumb {
+            out.write_all("\n.syntax unified\n.thumb\n".as_bytes())?;
         }
 
         for function in &self.functions_out {
             let header = format!(
                 "\n\n\
                 .global {:};\n\
-                {:}:\n",
-                function.name, function.name
+                {:}{:}:\n",
+                function.name, if thumb { ".thumb_func\n" } else { "" }, function.name
             );
 
-            file.write(header.as_bytes()).unwrap();
+            out.write_all(header.as_bytes())?;
 
             for instruction in &function.instructions {
                 if instruction.referenced {
                     let reference = format!("loc_{:x}:\n", instruction.offset);
-                    file.write(reference.as_bytes()).unwrap();
+                    out.write_all(reference.as_bytes())?;
+                }
+
+                // Optimized instructions may need no code
+                if !instruction.opcode.is_empty() {
+                    out.write_all("\t".as_bytes())?;
+                    out.write_all(instruction.opcode.as_bytes())?;
+                    out.write_all("\n".as_bytes())?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Writes C output: the prelude, declarations of everything
+    /// called and one function per symbol
+    fn output_emit_c<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        out.write_all(format!("\n{:}\n", xtensa_c::PRELUDE).as_bytes())?;
+
+        let prototypes = self.functions_out.iter()
+            .map(|f| format!("{:};", xtensa_c::signature(&f.name)));
+        let declarations: BTreeSet<String> = self.declarations.iter().cloned().chain(prototypes).collect();
+
+        for declaration in &declarations {
+            out.write_all(format!("{:}\n", declaration).as_bytes())?;
+        }
+
+        for function in &self.functions_out {
+            let header = format!("\n{:}\n{{\n", xtensa_c::signature(&function.name));
+
+            out.write_all(header.as_bytes())?;
+
+            for instruction in &function.instructions {
+                // Labels need a statement to follow
+                if instruction.referenced {
+                    let reference = format!("loc_{:x}:;\n", instruction.offset);
+                    out.write_all(reference.as_bytes())?;
                 }
 
-                file.write("\t".as_bytes()).unwrap();
-                file.write(instruction.opcode.as_bytes()).unwrap();
-                file.write("\n".as_bytes()).unwrap();
+                if !instruction.opcode.is_empty() {
+                    out.write_all(format!("\t{:}\n", instruction.opcode).as_bytes())?;
+                }
             }
+
+            out.write_all("}\n".as_bytes())?;
         }
+
+        Ok(())
     }
 
-    pub fn run(&mut self) {
+    pub fn run(&mut self) -> Result<()> {
         let args = clap::App::new("xtensa2arm")
             .version("0.1")
             .arg(Arg::with_name("input")
@@ -135,21 +512,221 @@ impl App {
                 .long("input")
                 .value_name("FILE")
                 .required(true))
+            .arg(Arg::with_name("function")
+                .short("f")
+                .long("function")
+                .value_name("NAME")
+                .help("Function to translate, may contain * and ? wildcards")
+                .multiple(true)
+                .number_of_values(1))
+            .arg(Arg::with_name("function-regex")
+                .long("function-regex")
+                .value_name("REGEX")
+                .help("Translate functions matching a regular expression")
+                .multiple(true)
+                .number_of_values(1))
+            .arg(Arg::with_name("functions-file")
+                .long("functions-file")
+                .value_name("FILE")
+                .help("Read function names or wildcards from a file, one per line"))
+            .arg(Arg::with_name("all-functions")
+                .long("all-functions")
+                .help("Translate every function symbol"))
+            .arg(Arg::with_name("follow-calls")
+                .long("follow-calls")
+                .help("Also translate every function reachable through call0"))
+            .arg(Arg::with_name("max-depth")
+                .long("max-depth")
+                .value_name("N")
+                .help("Call graph depth limit for --follow-calls")
+                .requires("follow-calls"))
+            .arg(Arg::with_name("exclude")
+                .long("exclude")
+                .value_name("NAME")
+                .help("Function supplied elsewhere, not followed into; may contain wildcards")
+                .multiple(true)
+                .number_of_values(1))
+            .arg(Arg::with_name("abi")
+                .long("abi")
+                .value_name("ABI")
+                .help("Input calling convention, detected from .xtensa.info by default")
+                .possible_values(&["call0", "windowed"]))
+            .arg(Arg::with_name("target")
+                .long("target")
+                .value_name("ARCH")
+                .default_value("arm")
+                .help("Architecture of the output")
+                .possible_values(&["arm", "aarch64", "c"]))
+            .arg(Arg::with_name("cpu")
+                .long("cpu")
+                .value_name("ARCH")
+                .default_value("armv7-a")
+                .help("ARM architecture of the output, older ones get longer instruction sequences")
+                .possible_values(&["armv5te", "armv6", "armv6t2", "armv7-a", "armv7ve", "armv7-m", "armv7e-m"]))
+            .arg(Arg::with_name("thumb")
+                .long("thumb")
+                .help("Emit Thumb-2 instead of ARM code, implied by M-profile CPUs"))
+            .arg(Arg::with_name("optimize")
+                .short("O")
+                .help("Propagate constants and copies, fuse compares and remove dead code"))
+            .arg(Arg::with_name("output")
+                .short("o")
+                .long("output")
+                .value_name("FILE")
+                .default_value("result.S")
+                .help("Output file, - for stdout"))
+            .arg(Arg::with_name("include")
+                .long("include")
+                .value_name("FILE")
+                .help("Add #include of FILE to the output")
+                .multiple(true)
+                .number_of_values(1))
+            .arg(Arg::with_name("mach")
+                .long("mach")
+                .value_name("NAME")
+                .default_value("esp8266")
+                .help("Machine description to include, NAME.S from the mach directory"))
+            .arg(Arg::with_name("mach-dir")
+                .long("mach-dir")
+                .value_name("DIR")
+                .default_value("mach")
+                .help("Directory holding machine descriptions"))
+            .arg(Arg::with_name("lenient")
+                .long("lenient")
+                .help("Replace untranslatable instructions with placeholders instead of skipping the function"))
+            .arg(Arg::with_name("placeholder-helper")
+                .long("placeholder-helper")
+                .value_name("NAME")
+                .help("Function placeholders call instead of executing udf")
+                .requires("lenient"))
+            .arg(Arg::with_name("dot")
+                .long("dot")
+                .value_name("DIR")
+                .help("Write control-flow graph of each function to DIR/<name>.dot"))
+            .arg(Arg::with_name("radare2")
+                .long("radare2")
+                .help("Disassemble functions with radare2 instead of the built-in decoder"))
+            .subcommand(SubCommand::with_name("stats")
+                .about("Reports opcodes which can't be parsed or translated, \
+                    over every function unless some are selected")
+                .arg(Arg::with_name("json")
+                    .long("json")
+                    .value_name("FILE")
+                    .help("Also write the report as JSON, - prints only JSON to stdout")))
             .get_matches();
 
         let input = args.value_of("input").unwrap().to_string();
 
-        self.pipe_create(&input);
-        self.analyze();
-        self.symbols_read();
-        self.functions_create();
+        if let Some(names) = args.values_of("function") {
+            for name in names {
+                self.selection.add_pattern(name);
+            }
+        }
+
+        if let Some(regexes) = args.values_of("function-regex") {
+            for regex in regexes {
+                self.selection.add_regex(regex)?;
+            }
+        }
+
+        if let Some(file) = args.value_of("functions-file") {
+            self.selection.add_file(file)?;
+        }
+
+        self.selection.all = args.is_present("all-functions");
+
+        self.abi = args.value_of("abi").map(|a| a.parse::<XtensaAbi>().unwrap());
+        self.target = args.value_of("target").unwrap().parse::<Target>().unwrap();
+        self.cpu = args.value_of("cpu").unwrap().parse::<ArmCpu>().unwrap();
+
+        if self.target != Target::Arm {
+            if args.occurrences_of("cpu") > 0 || args.is_present("thumb") {
+                return Err(Error::Selection(format!("--cpu and --thumb don't apply to target {:}",
+                    self.target.name())));
+            }
+
+        } else if args.is_present("thumb") || !self.cpu.has_arm_state() {
+            if !self.cpu.has_thumb2() {
+                return Err(Error::Selection(format!("Thumb-2 is not available on {:}", self.cpu.name())));
+            }
+
+            self.isa = InstructionSet::Thumb2;
+        }
+
+        self.output = args.value_of("output").unwrap().to_string();
+
+        if let Some(includes) = args.values_of("include") {
+            for include in includes {
+                self.includes.push(include.to_string());
+            }
+        }
+
+        self.mach_select(args.value_of("mach-dir").unwrap(), args.value_of("mach").unwrap())?;
+
+        self.dot_dir = args.value_of("dot").map(|d| d.to_string());
+        self.lenient = args.is_present("lenient");
+        self.placeholder_helper = args.value_of("placeholder-helper").map(|h| h.to_string());
+        self.optimize = args.is_present("optimize");
+        self.follow_calls = args.is_present("follow-calls");
+        self.follow_depth = match args.value_of("max-depth") {
+            Some(d) => match d.parse::<u32>() {
+                Ok(d) => Some(d),
+                Err(_) => return Err(Error::Selection(format!("Invalid --max-depth value: {:}", d))),
+            },
+            None => None,
+        };
+
+        if let Some(names) = args.values_of("exclude") {
+            for name in names {
+                self.exclude.add_pattern(name);
+            }
+        }
+
+        let stats = args.subcommand_matches("stats");
+
+        if stats.is_some() && self.selection.is_empty() {
+            self.selection.all = true;
+        }
+
+        if self.selection.is_empty() {
+            return Err(Error::Selection("No functions selected, use --function, --function-regex, \
+                --functions-file or --all-functions".to_string()));
+        }
+
+        if args.is_present("radare2") {
+            self.pipe_create(&input)?;
+            #[cfg(feature = "radare2")]
+            self.analyze()?;
+        }
+
+        self.symbols_read(&input)?;
+
+        if let Some(stats) = stats {
+            self.stats(stats.value_of("json"))?;
+            self.pipe_close();
+            return Ok(());
+        }
+
+        self.functions_create()?;
         self.functions_translate();
-        self.output_write();
+        self.output_write()?;
+        self.cfg_write()?;
         self.pipe_close();
+
+        Ok(())
     }
 }
 
 fn main() {
     let mut app: App = App::new();
-    app.run();
+
+    if let Err(e) = app.run() {
+        eprintln!("error: {:}", e);
+        process::exit(1);
+    }
+
+    if app.failed > 0 {
+        eprintln!("Failed functions: {:?}", app.failed);
+        process::exit(1);
+    }
 }
@@ -2,6 +2,8 @@ use std::vec::Vec;
 use std::option::Option;
 
 use rustc_serialize::json;
+use elf::{Elf, Section, SymbolKind};
+use error::{Error, Result};
 
 pub enum ObjectKind {
 	Object {
@@ -19,12 +21,18 @@ pub struct Object {
 	pub address: u32,
 	pub size: u32,
 	pub name: String,
+	/// Name of the section this object belongs to,
+	/// empty if unknown
+	pub section: String,
 	pub kind: ObjectKind,
 }
 
 #[derive(Default)]
 pub struct ObjectStorage {
-	objects: Vec<Object>
+	objects: Vec<Object>,
+	/// Allocated sections used to read
+	/// raw memory contents
+	sections: Vec<Section>,
 }
 
 /// Stores objects extracted from ELF file
@@ -36,37 +44,44 @@ impl ObjectStorage {
 		self.objects.len()
 	}
 
+	/// Iterates over all objects
+	pub fn iter<'a>(&'a self) -> ::std::slice::Iter<'a, Object> {
+		self.objects.iter()
+	}
+
 	/// Takes Json output from radare2
 	/// and populates object array
-	pub fn from_json(&mut self, json: json::Json) {
-		let array = json.into_array().unwrap();
+	pub fn from_json(&mut self, json: json::Json) -> Result<()> {
+		let bad = |what: &str| Error::Json(format!("unexpected symbol {:}", what));
+		let array = json.into_array().ok_or_else(|| bad("list"))?;
 
 		for element in &array {
-			let json_object = element.as_object().unwrap();
+			let json_object = element.as_object().ok_or_else(|| bad("entry"))?;
 			let mut binary_object = Object {
 				address: 0,
 				size: 0,
 				name: Default::default(),
+				section: Default::default(),
 				kind: ObjectKind::Unknown
 			};
 
 		    for (key, value) in json_object.iter() {
     			match key as &str {
-					"name" => {	binary_object.name = String::from(value.as_string().unwrap()) }
-					"size" => { binary_object.size = value.as_u64().unwrap() as u32 }
+					"name" => {	binary_object.name = String::from(value.as_string().ok_or_else(|| bad(key))?) }
+					"size" => { binary_object.size = value.as_u64().ok_or_else(|| bad(key))? as u32 }
 					"type" => {
-						binary_object.kind = match value.as_string().unwrap() {
+						binary_object.kind = match value.as_string().ok_or_else(|| bad(key))? {
 							"OBJECT" => ObjectKind::Object { data: Vec::default(), read: false },
 							"NOTYPE" => ObjectKind::Unknown,
 							"FUNC"   => ObjectKind::Function,
-							_ => panic!(),
+							t => return Err(bad(&format!("type {:}", t))),
 						}
 					}
-					"vaddr" => { binary_object.address = value.as_u64().unwrap() as u32 }
+					"vaddr" => { binary_object.address = value.as_u64().ok_or_else(|| bad(key))? as u32 }
 					"paddr" |
 					"demname" |
 					"flagname" => {},
-					_ => panic!(),
+					_ => return Err(bad(&format!("key {:}", key))),
 				}
     		}
 
@@ -74,6 +89,100 @@ impl ObjectStorage {
 		}
 
 		// TODO sort
+		Ok(())
+	}
+
+	/// Takes parsed ELF file and populates object
+	/// and section arrays. Object data is read
+	/// right away
+	pub fn load_elf(&mut self, elf: Elf) {
+		for symbol in &elf.symbols {
+			if symbol.name.is_empty() {
+				continue;
+			}
+
+			let section = match elf.symbol_section(symbol) {
+				Some(s) => s,
+				None => continue,
+			};
+
+			let kind = match symbol.kind {
+				SymbolKind::Object => {
+					let start = symbol.value.wrapping_sub(section.address) as usize;
+					let end = start + symbol.size as usize;
+					let data = match section.data.get(start .. end) {
+						Some(d) => d.to_vec(),
+						None => Vec::default(),
+					};
+
+					ObjectKind::Object { data, read: true }
+				}
+				SymbolKind::Function => ObjectKind::Function,
+				SymbolKind::NoType => ObjectKind::Unknown,
+				SymbolKind::Other => continue,
+			};
+
+			self.objects.push(Object {
+				address: symbol.value,
+				size: symbol.size,
+				name: symbol.name.clone(),
+				section: section.name.clone(),
+				kind,
+			});
+		}
+
+		for section in elf.sections {
+			if section.is_alloc() {
+				self.sections.push(section);
+			}
+		}
+	}
+
+	/// Searches for an object by its name
+	pub fn get_object_by_name<'a>(&'a self, name: &str) -> Option<&'a Object> {
+		self.objects.iter().find(|o| o.name == name)
+	}
+
+	/// Returns raw contents of an object. Objects with
+	/// unknown size extend up to the next object
+	/// or the end of the section
+	pub fn object_data<'a>(&'a self, object: &Object) -> Option<&'a [u8]> {
+		let section = self.get_section(object.address)?;
+
+		let mut size = object.size;
+
+		if size == 0 {
+			let end = self.objects.iter()
+				.filter(|o| o.address > object.address && section.contains(o.address))
+				.map(|o| o.address)
+				.min()
+				.unwrap_or(section.address + section.size);
+
+			size = end - object.address;
+		}
+
+		self.read(object.address, size)
+	}
+
+	/// Returns the section containing the provided address
+	pub fn get_section(&self, address: u32) -> Option<&Section> {
+		self.sections.iter().find(|s| s.contains(address))
+	}
+
+	/// Reads raw memory contents. The whole range
+	/// must be contained in a single section
+	pub fn read(&self, address: u32, size: u32) -> Option<&[u8]> {
+		let section = self.get_section(address)?;
+
+		let start = (address - section.address) as usize;
+		section.data.get(start .. start + size as usize)
+	}
+
+	/// Reads little-endian 32-bit word
+	pub fn read_u32(&self, address: u32) -> Option<u32> {
+		self.read(address, 4).map(|b| {
+			(b[0] as u32) | ((b[1] as u32) << 8) | ((b[2] as u32) << 16) | ((b[3] as u32) << 24)
+		})
 	}
 
 	/// Searches for an object at the provided address
@@ -0,0 +1,64 @@
+use rustc_serialize::json::Json;
+
+use std::io::{self, BufRead, BufReader, Write};
+use std::process::{Child, ChildStdin, ChildStdout, Command, Stdio};
+
+/// Pipe to a radare2 process started with `r2 -q0`.
+/// Commands are sent one per line, each output
+/// is terminated by a NUL byte
+pub struct R2Pipe {
+    child: Child,
+    input: ChildStdin,
+    output: BufReader<ChildStdout>,
+}
+
+impl R2Pipe {
+    /// Opens `path` in a new radare2 process
+    pub fn spawn(path: &str) -> io::Result<R2Pipe> {
+        let mut child = Command::new("r2")
+            .args(["-q0", path])
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()?;
+
+        let input = child.stdin.take().unwrap();
+        let output = BufReader::new(child.stdout.take().unwrap());
+        let mut pipe = R2Pipe { child, input, output };
+
+        // radare2 signals it is ready with a single NUL byte
+        pipe.read()?;
+
+        Ok(pipe)
+    }
+
+    fn read(&mut self) -> io::Result<String> {
+        let mut output = Vec::new();
+
+        if self.output.read_until(0, &mut output)? == 0 {
+            return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "radare2 exited"));
+        }
+
+        output.pop();
+        Ok(String::from_utf8_lossy(&output).into_owned())
+    }
+
+    /// Runs a command and returns its output
+    pub fn cmd(&mut self, command: &str) -> Result<String, String> {
+        writeln!(self.input, "{:}", command)
+            .and_then(|_| self.read())
+            .map_err(|why| format!("radare2 command {:} failed: {:}", command, why))
+    }
+
+    /// Runs a command and parses its output as JSON
+    pub fn cmdj(&mut self, command: &str) -> Result<Json, String> {
+        let output = self.cmd(command)?;
+
+        Json::from_str(&output).map_err(|why| format!("bad output of radare2 command {:}: {:}", command, why))
+    }
+
+    /// Quits radare2 and waits for it to exit
+    pub fn close(&mut self) {
+        let _ = writeln!(self.input, "q!");
+        let _ = self.child.wait();
+    }
+}
@@ -0,0 +1,170 @@
+use std::vec::Vec;
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+
+use regex::Regex;
+
+use object_storage::{ObjectKind, ObjectStorage};
+use error::{Error, Result};
+
+/// Decides which functions get translated.
+/// Names may contain `*` and `?` wildcards
+#[derive(Default)]
+pub struct FunctionSelection {
+    pub all: bool,
+    patterns: Vec<String>,
+    regexes: Vec<Regex>,
+}
+
+/// Matches name against a shell-style wildcard pattern
+pub fn glob_match(pattern: &str, name: &str) -> bool {
+    let p: Vec<char> = pattern.chars().collect();
+    let n: Vec<char> = name.chars().collect();
+
+    let (mut pi, mut ni) = (0, 0);
+    // Position of the last `*` and the name position it was tried at
+    let mut star: Option<(usize, usize)> = None;
+
+    while ni < n.len() {
+        if pi < p.len() && (p[pi] == '?' || p[pi] == n[ni]) {
+            pi += 1;
+            ni += 1;
+        } else if pi < p.len() && p[pi] == '*' {
+            star = Some((pi, ni));
+            pi += 1;
+        } else if let Some((sp, sn)) = star {
+            pi = sp + 1;
+            ni = sn + 1;
+            star = Some((sp, sn + 1));
+        } else {
+            return false;
+        }
+    }
+
+    p[pi..].iter().all(|&c| c == '*')
+}
+
+fn is_glob(pattern: &str) -> bool {
+    pattern.contains('*') || pattern.contains('?')
+}
+
+impl FunctionSelection {
+    pub fn new() -> FunctionSelection {
+        Default::default()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        !self.all && self.patterns.is_empty() && self.regexes.is_empty()
+    }
+
+    /// Adds function name or wildcard pattern
+    pub fn add_pattern(&mut self, pattern: &str) {
+        self.patterns.push(pattern.to_string());
+    }
+
+    pub fn add_regex(&mut self, regex: &str) -> Result<()> {
+        let r = Regex::new(regex)
+            .map_err(|why| Error::Selection(format!("Invalid function regex {:}: {}", regex, why)))?;
+
+        self.regexes.push(r);
+        Ok(())
+    }
+
+    /// Reads patterns from a file, one per line.
+    /// Empty lines and lines starting with `#` are skipped
+    pub fn add_file(&mut self, path: &str) -> Result<()> {
+        let file = File::open(path).map_err(|why| Error::io(path, why))?;
+
+        for line in BufReader::new(file).lines() {
+            let line = line.map_err(|why| Error::io(path, why))?;
+            let line = line.trim();
+
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            self.add_pattern(line);
+        }
+
+        Ok(())
+    }
+
+    pub fn matches(&self, name: &str) -> bool {
+        self.all ||
+            self.patterns.iter().any(|p| glob_match(p, name)) ||
+            self.regexes.iter().any(|r| r.is_match(name))
+    }
+
+    /// Returns names of selected functions in address order.
+    /// Untyped symbols, e.g. labels of hand-written assembly
+    /// routines, are only selected by their exact name.
+    /// Fails if a plain (non-wildcard) name doesn't exist
+    pub fn select(&self, objects: &ObjectStorage) -> Result<Vec<String>> {
+        let mut functions: Vec<(u32, String)> = Vec::new();
+
+        for object in objects.iter() {
+            let selected = match object.kind {
+                ObjectKind::Function => self.matches(&object.name),
+                ObjectKind::Unknown => self.patterns.contains(&object.name),
+                ObjectKind::Object { .. } => false,
+            };
+
+            if selected && !functions.iter().any(|(_, n)| *n == object.name) {
+                functions.push((object.address, object.name.clone()));
+            }
+        }
+
+        for pattern in &self.patterns {
+            if !is_glob(pattern) && !functions.iter().any(|(_, n)| n == pattern) {
+                return Err(Error::Selection(format!("Function not found: {:}", pattern)));
+            }
+        }
+
+        functions.sort();
+        Ok(functions.into_iter().map(|(_, n)| n).collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rustc_serialize::json::Json;
+    use object_storage::ObjectStorage;
+    use selection::{glob_match, FunctionSelection};
+
+    #[test]
+    fn test_glob_1() {
+        assert!(glob_match("sdk_rom_i2c_writeReg", "sdk_rom_i2c_writeReg"));
+        assert!(!glob_match("sdk_rom_i2c_writeReg", "sdk_rom_i2c_readReg"));
+    }
+
+    #[test]
+    fn test_glob_2() {
+        assert!(glob_match("sdk_*", "sdk_rom_i2c_writeReg"));
+        assert!(glob_match("*i2c*", "sdk_rom_i2c_writeReg"));
+        assert!(glob_match("sdk_rom_i2c_????Reg", "sdk_rom_i2c_readReg"));
+        assert!(glob_match("*", ""));
+        assert!(!glob_match("sdk_*_read", "sdk_rom_i2c_readReg"));
+        assert!(!glob_match("?", ""));
+    }
+
+    #[test]
+    fn test_select_1() {
+        let json = Json::from_str(r#"[
+            {"name": "memcpy_asm", "type": "NOTYPE", "vaddr": 256, "size": 0},
+            {"name": "main", "type": "FUNC", "vaddr": 512, "size": 16},
+            {"name": "table", "type": "OBJECT", "vaddr": 768, "size": 4}
+        ]"#).unwrap();
+        let mut objects = ObjectStorage::default();
+        objects.from_json(json).unwrap();
+
+        let mut selection = FunctionSelection::new();
+        selection.add_pattern("m*");
+        assert_eq!(selection.select(&objects).unwrap(), vec!["main"]);
+
+        selection.add_pattern("memcpy_asm");
+        assert_eq!(selection.select(&objects).unwrap(), vec!["memcpy_asm", "main"]);
+
+        selection.add_pattern("table");
+        assert!(selection.select(&objects).is_err());
+    }
+}
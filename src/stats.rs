@@ -0,0 +1,243 @@
+use std::vec::Vec;
+use std::collections::BTreeMap;
+
+use rustc_serialize::json::{Json, ToJson};
+
+use error::Error;
+use function::Function;
+
+/// Pipeline stage an instruction got stuck at
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug)]
+pub enum Stage {
+    Decode,
+    Parse,
+    Translate,
+}
+
+pub struct FunctionStats {
+    pub name: String,
+    pub address: u32,
+    pub instructions: usize,
+    pub untranslated: usize,
+    /// Error which prevented reading the function at all
+    pub error: Option<String>,
+}
+
+pub struct OpcodeStats {
+    pub stage: Stage,
+    pub mnemonic: String,
+    pub count: usize,
+    /// Functions the opcode occurs in, sorted
+    pub functions: Vec<String>,
+}
+
+/// Translation coverage of a set of functions
+#[derive(Default)]
+pub struct Coverage {
+    pub functions: Vec<FunctionStats>,
+    opcodes: BTreeMap<(Stage, String), OpcodeStats>,
+}
+
+impl Stage {
+    fn of(error: &Error) -> Stage {
+        match *error.cause() {
+            Error::Decode { .. } => Stage::Decode,
+            Error::UnknownMnemonic(_) |
+            Error::BadOperand(_) |
+            Error::MissingOperand => Stage::Parse,
+            _ => Stage::Translate,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match *self {
+            Stage::Decode => "decode",
+            Stage::Parse => "parse",
+            Stage::Translate => "translate",
+        }
+    }
+}
+
+/// Mnemonic of a failed instruction, undecoded bytes are grouped together
+fn mnemonic(error: &Error) -> String {
+    match *error {
+        _ if Stage::of(error) == Stage::Decode => "(undecoded)".to_string(),
+        Error::Instruction { ref text, .. } => text.split(' ').next().unwrap_or("").to_string(),
+        _ => String::new()
+    }
+}
+
+impl Coverage {
+    pub fn new() -> Coverage {
+        Default::default()
+    }
+
+    /// Records a function translated in lenient mode along
+    /// with the placeholders emitted for it
+    pub fn add_function(&mut self, function: &Function, address: u32, placeholders: &[Error]) {
+        for placeholder in placeholders {
+            let stage = Stage::of(placeholder);
+            let mnemonic = mnemonic(placeholder);
+            let entry = self.opcodes.entry((stage, mnemonic.clone())).or_insert(OpcodeStats {
+                stage,
+                mnemonic,
+                count: 0,
+                functions: Vec::new(),
+            });
+
+            entry.count += 1;
+
+            if !entry.functions.contains(&function.name) {
+                entry.functions.push(function.name.clone());
+                entry.functions.sort();
+            }
+        }
+
+        self.functions.push(FunctionStats {
+            name: function.name.clone(),
+            address,
+            instructions: function.instructions.len(),
+            untranslated: placeholders.len(),
+            error: None,
+        });
+    }
+
+    /// Records a function which couldn't be read
+    pub fn add_failure(&mut self, name: &str, address: u32, error: &Error) {
+        self.functions.push(FunctionStats {
+            name: name.to_string(),
+            address,
+            instructions: 0,
+            untranslated: 0,
+            error: Some(error.to_string()),
+        });
+    }
+
+    /// Unsupported opcodes, most frequent first
+    pub fn opcodes(&self) -> Vec<&OpcodeStats> {
+        let mut result: Vec<&OpcodeStats> = self.opcodes.values().collect();
+        result.sort_by(|a, b| b.count.cmp(&a.count).then(a.stage.cmp(&b.stage)));
+        result
+    }
+
+    pub fn instructions(&self) -> usize {
+        self.functions.iter().map(|f| f.instructions).sum()
+    }
+
+    pub fn untranslated(&self) -> usize {
+        self.functions.iter().map(|f| f.untranslated).sum()
+    }
+
+    /// Formats the report as plain text tables. Only functions
+    /// which are not fully translated are listed
+    pub fn table(&self) -> String {
+        let instructions = self.instructions();
+        let translated = instructions - self.untranslated();
+        let complete = self.functions.iter()
+            .filter(|f| f.error.is_none() && f.untranslated == 0)
+            .count();
+        let percent = if instructions > 0 { 100.0 * translated as f64 / instructions as f64 } else { 100.0 };
+
+        let mut result = format!("Functions: {:}, fully translated: {:}\n", self.functions.len(), complete);
+        result.push_str(&format!("Instructions: {:}, translated: {:} ({:.1}%)\n", instructions, translated, percent));
+
+        result.push_str(&format!("\n{:<10} {:<12} {:>7} {:>10}\n", "stage", "mnemonic", "count", "functions"));
+
+        for opcode in self.opcodes() {
+            result.push_str(&format!("{:<10} {:<12} {:>7} {:>10}\n",
+                opcode.stage.name(), opcode.mnemonic, opcode.count, opcode.functions.len()));
+        }
+
+        result.push_str(&format!("\n{:<10} {:>12} {:>12}  {:}\n", "address", "instructions", "untranslated", "function"));
+
+        for function in &self.functions {
+            match function.error {
+                Some(ref e) => result.push_str(&format!("{:<10x} {:>12} {:>12}  {:} ({:})\n",
+                    function.address, "-", "-", function.name, e)),
+                None if function.untranslated > 0 => result.push_str(&format!("{:<10x} {:>12} {:>12}  {:}\n",
+                    function.address, function.instructions, function.untranslated, function.name)),
+                None => {}
+            }
+        }
+
+        result
+    }
+}
+
+impl ToJson for FunctionStats {
+    fn to_json(&self) -> Json {
+        let mut object = BTreeMap::new();
+
+        object.insert("name".to_string(), self.name.to_json());
+        object.insert("address".to_string(), self.address.to_json());
+        object.insert("instructions".to_string(), self.instructions.to_json());
+        object.insert("untranslated".to_string(), self.untranslated.to_json());
+        object.insert("error".to_string(), self.error.to_json());
+
+        Json::Object(object)
+    }
+}
+
+impl ToJson for OpcodeStats {
+    fn to_json(&self) -> Json {
+        let mut object = BTreeMap::new();
+
+        object.insert("stage".to_string(), self.stage.name().to_json());
+        object.insert("mnemonic".to_string(), self.mnemonic.to_json());
+        object.insert("count".to_string(), self.count.to_json());
+        object.insert("functions".to_string(), self.functions.to_json());
+
+        Json::Object(object)
+    }
+}
+
+impl ToJson for Coverage {
+    fn to_json(&self) -> Json {
+        let mut object = BTreeMap::new();
+        let opcodes: Vec<Json> = self.opcodes().iter().map(|o| o.to_json()).collect();
+
+        object.insert("instructions".to_string(), self.instructions().to_json());
+        object.insert("untranslated".to_string(), self.untranslated().to_json());
+        object.insert("opcodes".to_string(), Json::Array(opcodes));
+        object.insert("functions".to_string(), self.functions.to_json());
+
+        Json::Object(object)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use stats::{Coverage, Stage};
+    use error::Error;
+    use function::Function;
+    use assembly::Instruction;
+    use rustc_serialize::json::ToJson;
+
+    #[test]
+    fn test_coverage_1() {
+        let mut f = Function::new();
+        f.name = "f".to_string();
+        f.instructions = (0 .. 4).map(|_| Instruction::new()).collect();
+
+        let placeholders = vec![
+            Error::UnknownMnemonic("wsr".to_string()).at("f", 0x100, "wsr a2, sar"),
+            Error::Unsupported("mull".to_string()).at("f", 0x103, "mull a2, a2, a3"),
+            Error::Unsupported("mull".to_string()).at("f", 0x106, "mull a2, a2, a4"),
+        ];
+
+        let mut coverage = Coverage::new();
+        coverage.add_function(&f, 0x100, &placeholders);
+        coverage.add_failure("g", 0x200, &Error::Unresolved(0x200));
+
+        let opcodes = coverage.opcodes();
+        assert_eq!(opcodes.len(), 2);
+        assert_eq!(opcodes[0].mnemonic, "mull");
+        assert_eq!(opcodes[0].stage, Stage::Translate);
+        assert_eq!(opcodes[0].count, 2);
+        assert_eq!(opcodes[1].stage, Stage::Parse);
+
+        let json = coverage.to_json();
+        assert_eq!(json.find("untranslated").and_then(|j| j.as_u64()), Some(3));
+        assert!(coverage.table().contains("translate  mull               2          1"));
+    }
+}
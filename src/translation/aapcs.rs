@@ -0,0 +1,140 @@
+use std::vec::Vec;
+use std::collections::BTreeSet;
+
+use translation::xtensa_op::XtensaInstruction;
+
+/// ARM registers an AAPCS callee has to preserve
+pub const CALLEE_SAVED: [&str; 8] = [
+    "r4", "r5", "r6", "r7", "r8", "r9", "r10", "r11"
+];
+
+/// ARM registers a call may clobber
+pub const CALL_CLOBBERED: [&str; 6] = [
+    "r0", "r1", "r2", "r3", "r12", "lr"
+];
+
+/// Number of arguments AAPCS passes in registers
+pub const REGISTER_ARGS: usize = 4;
+
+/// call0 argument registers, a2..a7
+pub const CALL0_ARGS: [u8; 6] = [2, 3, 4, 5, 6, 7];
+
+/// Describes how a call0 function is adapted to AAPCS.
+/// Arguments a2..a5 and return values already land
+/// in r0..r3, the rest is fixed up on entry, return
+/// and around calls. call0 callee-saved a12..a15 are
+/// allocated to AAPCS callee-saved registers or spill
+/// slots, so calls preserve them without fixups
+#[derive(Default)]
+pub struct Call0Frame {
+    /// AAPCS callee-saved registers written by the function
+    /// or loaded with stack arguments, pushed in prologue
+    /// and popped before return
+    pub saved: Vec<String>,
+    /// Arguments passed on stack under AAPCS which the function
+    /// reads before writing, with their stack offsets on entry
+    pub stack_args: Vec<(u8, u32)>,
+}
+
+/// Returns AAPCS stack offset of a call0 argument register
+pub fn stack_arg_offset(reg: u8) -> Option<u32> {
+    CALL0_ARGS.iter()
+        .position(|&r| r == reg)
+        .and_then(|i| if i >= REGISTER_ARGS { Some(((i - REGISTER_ARGS) * 4) as u32) } else { None })
+}
+
+impl Call0Frame {
+    /// Scans function body in program order
+    pub fn analyze<F>(instructions: &[XtensaInstruction], arm_reg: F) -> Call0Frame
+    where F: Fn(u8) -> String {
+        let mut frame = Call0Frame::default();
+        let mut written = BTreeSet::<u8>::new();
+
+        for instruction in instructions {
+            for r in instruction.uses() {
+                if written.contains(&r) {
+                    continue;
+                }
+
+                if let Some(offset) = stack_arg_offset(r) {
+                    if !frame.stack_args.iter().any(|&(a, _)| a == r) {
+                        frame.stack_args.push((r, offset));
+                    }
+                }
+            }
+
+            written.extend(instruction.defs());
+        }
+
+        let loaded: Vec<u8> = frame.stack_args.iter().map(|&(r, _)| r).collect();
+
+        for r in written.iter().chain(&loaded) {
+            let reg = arm_reg(*r);
+
+            if CALLEE_SAVED.contains(&reg.as_str()) && !frame.saved.contains(&reg) {
+                frame.saved.push(reg);
+            }
+        }
+
+        // push/pop take registers in ascending order
+        frame.saved.sort_by_key(|r| r[1..].parse::<u32>().unwrap());
+        frame.stack_args.sort_by_key(|&(_, offset)| offset);
+
+        frame
+    }
+
+    /// Adds a register written by the translated code itself,
+    /// e.g. a scratch register, to the saved list if AAPCS
+    /// requires it to be preserved
+    pub fn save(&mut self, reg: String) {
+        if CALLEE_SAVED.contains(&reg.as_str()) && !self.saved.contains(&reg) {
+            self.saved.push(reg);
+            self.saved.sort_by_key(|r| r[1..].parse::<u32>().unwrap());
+        }
+    }
+
+    /// Stack argument registers a call has to push given the
+    /// registers written since the previous call. Both slots
+    /// are pushed to keep stack 8-byte aligned
+    pub fn call_stack_args(&self, pending: &BTreeSet<u8>) -> Vec<u8> {
+        let stack: Vec<u8> = CALL0_ARGS.iter()
+            .cloned()
+            .filter(|&r| stack_arg_offset(r).is_some())
+            .collect();
+
+        if stack.iter().any(|r| pending.contains(r)) {
+            stack
+        } else {
+            Vec::new()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use translation::aapcs::{Call0Frame, stack_arg_offset};
+    use translation::xtensa_op::XtensaInstruction;
+    use assembly::ParseInstruction;
+
+    fn arm_reg(r: u8) -> String {
+        format!("r{:}", r - 2)
+    }
+
+    #[test]
+    fn test_frame_1() {
+        let code: Vec<XtensaInstruction> = ["add a2, a2, a7", "movi a6, 1", "add a9, a6, a3", "ret"]
+            .iter()
+            .map(|s| {
+                let mut i = XtensaInstruction::new();
+                i.from_str(s).unwrap();
+                i
+            })
+            .collect();
+
+        let frame = Call0Frame::analyze(&code, arm_reg);
+
+        assert_eq!(frame.stack_args, vec![(7, 4)]);
+        assert_eq!(frame.saved, vec!["r4", "r5", "r7"]);
+        assert_eq!(stack_arg_offset(5), None);
+    }
+}
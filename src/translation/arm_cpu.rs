@@ -0,0 +1,160 @@
+use std::str::FromStr;
+
+/// ARM architecture the output is assembled for.
+/// Later architectures are supersets of earlier ones, except
+/// that M-profile cores lack ARM state and ARMv7-M lacks DSP
+#[derive(Clone, Copy, PartialEq, PartialOrd, Debug, Default)]
+pub enum ArmCpu {
+    Armv5te,
+    Armv6,
+    /// ARMv6 with Thumb-2 additions, e.g. ARM1156
+    Armv6t2,
+    #[default]
+    Armv7a,
+    /// ARMv7-A with virtualization extensions, e.g. Cortex-A7 and A15
+    Armv7ve,
+    /// Cortex-M3
+    Armv7m,
+    /// ARMv7-M with DSP extension, e.g. Cortex-M4
+    Armv7em,
+}
+
+/// Instruction set of the output
+#[derive(Clone, Copy, PartialEq, Debug, Default)]
+pub enum InstructionSet {
+    #[default]
+    Arm,
+    Thumb2,
+}
+
+impl ArmCpu {
+    /// Whether ssat and usat are available
+    pub fn has_saturate(&self) -> bool {
+        *self >= ArmCpu::Armv6
+    }
+
+    /// Whether uxth, sxth and friends are available
+    pub fn has_extend(&self) -> bool {
+        *self >= ArmCpu::Armv6
+    }
+
+    /// Whether ubfx, sbfx, bfi and bfc are available
+    pub fn has_bitfield(&self) -> bool {
+        *self >= ArmCpu::Armv6t2
+    }
+
+    /// Whether dmb is available
+    pub fn has_barrier(&self) -> bool {
+        *self >= ArmCpu::Armv7a
+    }
+
+    /// Whether udiv and sdiv are available
+    pub fn has_divide(&self) -> bool {
+        *self >= ArmCpu::Armv7ve
+    }
+
+    /// Whether smulbb and friends are available
+    pub fn has_dsp(&self) -> bool {
+        *self != ArmCpu::Armv7m
+    }
+
+    /// Whether the core can execute ARM (A32) code
+    pub fn has_arm_state(&self) -> bool {
+        *self < ArmCpu::Armv7m
+    }
+
+    /// Whether the core can execute Thumb-2 code
+    pub fn has_thumb2(&self) -> bool {
+        *self >= ArmCpu::Armv6t2
+    }
+
+    pub fn name(&self) -> &'static str {
+        match *self {
+            ArmCpu::Armv5te => "armv5te",
+            ArmCpu::Armv6 => "armv6",
+            ArmCpu::Armv6t2 => "armv6t2",
+            ArmCpu::Armv7a => "armv7-a",
+            ArmCpu::Armv7ve => "armv7ve",
+            ArmCpu::Armv7m => "armv7-m",
+            ArmCpu::Armv7em => "armv7e-m",
+        }
+    }
+}
+
+impl FromStr for ArmCpu {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<ArmCpu, String> {
+        match s {
+            "armv5te" => Ok(ArmCpu::Armv5te),
+            "armv6" => Ok(ArmCpu::Armv6),
+            "armv6t2" => Ok(ArmCpu::Armv6t2),
+            "armv7-a" => Ok(ArmCpu::Armv7a),
+            "armv7ve" => Ok(ArmCpu::Armv7ve),
+            "armv7-m" => Ok(ArmCpu::Armv7m),
+            "armv7e-m" => Ok(ArmCpu::Armv7em),
+            _ => Err(format!("Unknown CPU: {:}", s)),
+        }
+    }
+}
+
+impl InstructionSet {
+    /// Whether `value` fits the modified immediate field
+    /// of data processing instructions
+    pub fn is_immediate(&self, value: u32) -> bool {
+        match *self {
+            // 8 bits rotated right by an even amount
+            InstructionSet::Arm => (0 .. 16).any(|r| value.rotate_left(2 * r) <= 0xff),
+            // 8 bits shifted left, or a byte repeated in every
+            // halfword or every byte
+            InstructionSet::Thumb2 => {
+                let low = value & 0xff;
+                let high = (value >> 8) & 0xff;
+
+                value == 0 || value >> value.trailing_zeros() <= 0xff ||
+                    value == low * 0x0001_0001 || value == high * 0x0100_0100 ||
+                    value == low * 0x0101_0101
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use translation::arm_cpu::{ArmCpu, InstructionSet};
+
+    #[test]
+    fn test_cpu_1() {
+        let cpu = "armv6".parse::<ArmCpu>().unwrap();
+
+        assert!(cpu.has_saturate());
+        assert!(!cpu.has_bitfield());
+        assert!(ArmCpu::default().has_bitfield());
+        assert_eq!(ArmCpu::Armv7a.name().parse::<ArmCpu>(), Ok(ArmCpu::Armv7a));
+        assert!("armv4".parse::<ArmCpu>().is_err());
+        assert!(!ArmCpu::default().has_divide());
+        assert!(ArmCpu::Armv7ve.has_divide());
+
+        let cpu = "armv7-m".parse::<ArmCpu>().unwrap();
+
+        assert!(cpu.has_divide() && cpu.has_bitfield());
+        assert!(!cpu.has_dsp() && !cpu.has_arm_state());
+        assert!(ArmCpu::Armv7em.has_dsp());
+        assert!(!ArmCpu::Armv6.has_thumb2());
+    }
+
+    #[test]
+    fn test_immediate_1() {
+        let arm = InstructionSet::Arm;
+        let thumb = InstructionSet::Thumb2;
+
+        assert!(arm.is_immediate(0xff) && thumb.is_immediate(0xff));
+        assert!(arm.is_immediate(0xf000000f) && !thumb.is_immediate(0xf000000f));
+        assert!(thumb.is_immediate(0x1fe0) && !arm.is_immediate(0x1fe0));
+        assert!(thumb.is_immediate(0x00ab00ab) && !arm.is_immediate(0x00ab00ab));
+        assert!(thumb.is_immediate(0xab00ab00));
+        assert!(thumb.is_immediate(0xabababab));
+        assert!(!arm.is_immediate(0x101) && !thumb.is_immediate(0x101));
+        assert!(!thumb.is_immediate(0x00ab00ac));
+    }
+}
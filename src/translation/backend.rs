@@ -0,0 +1,75 @@
+use std::str::FromStr;
+use std::collections::BTreeSet;
+
+use object_storage::ObjectStorage;
+use function::Function;
+#[cfg(test)]
+use function::fixture;
+use error::{Error, Result};
+
+/// Architecture of the output
+#[derive(Clone, Copy, PartialEq, Debug, Default)]
+pub enum Target {
+    /// 32-bit ARM or Thumb-2, see `xtensa_arm`
+    #[default]
+    Arm,
+    /// A64, see `xtensa_aarch64`
+    Aarch64,
+    /// C source, see `xtensa_c`
+    C,
+}
+
+impl Target {
+    pub fn name(&self) -> &'static str {
+        match *self {
+            Target::Arm => "arm",
+            Target::Aarch64 => "aarch64",
+            Target::C => "c",
+        }
+    }
+}
+
+impl FromStr for Target {
+    type Err = String;
+
+    fn from_str(s: &str) -> ::std::result::Result<Target, String> {
+        match s {
+            "arm" => Ok(Target::Arm),
+            "aarch64" => Ok(Target::Aarch64),
+            "c" => Ok(Target::C),
+            _ => Err(format!("Unknown target: {:}", s)),
+        }
+    }
+}
+
+/// Translator of xtensa functions to one output architecture
+pub trait Backend {
+    /// Translates a function. Errors carry the location
+    /// of the failed instruction
+    fn translate(&mut self, function: &mut Function, objects: &mut ObjectStorage) -> Result<Function>;
+
+    /// Addresses of functions called from the last translated function
+    fn referenced_objects(&self) -> &BTreeSet<u32>;
+
+    /// Errors replaced by placeholders so far
+    fn placeholders(&mut self) -> &mut Vec<Error>;
+
+    /// File scope declarations the translated functions need,
+    /// for output which isn't assembly
+    fn declarations(&self) -> Vec<String> {
+        Vec::new()
+    }
+}
+
+/// Test helper: translates `fixture(code)` and returns
+/// the text of each output instruction
+#[cfg(test)]
+pub fn translate_fixture(backend: &mut dyn Backend, code: &[&str]) -> Vec<String> {
+    let mut f = fixture(code);
+    let mut objects = ObjectStorage::default();
+
+    backend.translate(&mut f, &mut objects).unwrap()
+        .instructions.into_iter()
+        .map(|i| i.opcode)
+        .collect()
+}
@@ -0,0 +1,439 @@
+use std::fmt;
+use std::vec::Vec;
+
+use translation::xtensa_op::{SAR, BR, SCOMPARE1, LCOUNT};
+
+/// Storage of the IR. xtensa state, including SAR, BR,
+/// SCOMPARE1 and the loop counter, is numbered as in
+/// `xtensa_op`. Temporaries only live within one lifted instruction
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug)]
+pub enum Reg {
+    Xtensa(u8),
+    Temp(u8),
+}
+
+/// Operand: register or 32-bit immediate
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Value {
+    Reg(Reg),
+    Imm(i32),
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum BinOp {
+    Add,
+    Sub,
+    /// Low word of the product
+    Mul,
+    And,
+    Or,
+    Xor,
+    /// Shifts by 32 or more give 0, or the sign for `Asr`
+    Shl,
+    Lsr,
+    Asr,
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum UnaryOp {
+    /// Count of leading zeros, 32 for 0
+    Clz,
+    /// Count of leading bits equal to the sign bit, excluding it
+    Cls,
+}
+
+/// Width of a memory access
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Width {
+    Byte,
+    Half,
+    Word,
+}
+
+/// How `Compare` sets the flags
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum CompareKind {
+    /// From `a - b`
+    Cmp,
+    /// From `a & b`, only zero is meaningful
+    Test,
+    /// From `a & !b`, only zero is meaningful
+    TestClear,
+}
+
+/// Condition on the flags
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Cond {
+    Eq,
+    Ne,
+    Lt,
+    Ge,
+    Gt,
+    Le,
+    /// Unsigned conditions
+    Ltu,
+    Geu,
+    Gtu,
+    Leu,
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub enum CallTarget {
+    Function { name: String, address: u32 },
+    Reg(Reg),
+}
+
+/// Operation of the IR. Flags are explicit state: `Compare`
+/// sets them, `Select` and conditional `Branch` read them.
+/// Targets may clobber them lowering other operations,
+/// see `keeps_flags`
+#[derive(Clone, PartialEq, Debug)]
+pub enum Op {
+    Mov { dst: Reg, src: Value },
+    /// dst = a op b
+    Binary { op: BinOp, dst: Reg, a: Value, b: Value },
+    /// dst = (a << shift) + b
+    AddShifted { dst: Reg, a: Reg, shift: u8, b: Reg },
+    Unary { op: UnaryOp, dst: Reg, src: Reg },
+    /// Bit field of `width` bits at `shift`, zero or sign extended
+    Extract { dst: Reg, src: Reg, shift: u8, width: u8, signed: bool },
+    /// Signed saturation to `bits` bits
+    Saturate { dst: Reg, src: Reg, bits: u8 },
+    /// High word of the 64-bit product
+    MulHigh { dst: Reg, a: Reg, b: Reg, signed: bool },
+    /// Product of the low halfwords
+    Mul16 { dst: Reg, a: Reg, b: Reg, signed: bool },
+    /// Quotient or remainder, division by zero gives 0
+    Div { dst: Reg, a: Reg, b: Reg, signed: bool, remainder: bool },
+    /// Low word of {high, low} >> amount
+    FunnelShift { dst: Reg, high: Reg, low: Reg, amount: Value },
+    /// Ordered loads are followed and ordered stores preceded by a barrier
+    Load { dst: Reg, base: Reg, offset: u32, width: Width, signed: bool, ordered: bool },
+    Store { src: Reg, base: Reg, offset: u32, width: Width, ordered: bool },
+    /// Stores `reg` to the word if it equals `compare`,
+    /// `reg` gets the old value
+    CompareSwap { reg: Reg, base: Reg, offset: u32, compare: Reg },
+    /// Word at a symbol, special registers kept in memory
+    LoadGlobal { dst: Reg, symbol: String },
+    StoreGlobal { src: Reg, symbol: String },
+    ExchangeGlobal { reg: Reg, symbol: String },
+    Compare { kind: CompareKind, a: Reg, b: Value },
+    /// dst = src if the condition holds
+    Select { cond: Cond, dst: Reg, src: Reg },
+    /// Unconditional if `cond` is None
+    Branch { cond: Option<Cond>, target: u32 },
+    /// Decrements the loop counter, branches to the
+    /// loop body unless it reaches zero
+    LoopBack { target: u32 },
+    /// Window rotation is 0 for call0
+    Call { target: CallTarget, rotation: u8 },
+    /// Call of a runtime function implementing what the IR can't express
+    Intrinsic { name: String, dst: Option<Reg>, args: Vec<Value> },
+    /// Windowed function entry allocating `frame` bytes
+    Entry { frame: u32 },
+    Return { windowed: bool },
+    /// Orders memory accesses
+    Barrier,
+    Nop,
+}
+
+impl Reg {
+    /// Number of an xtensa register, None for temporaries
+    pub fn xtensa(&self) -> Option<u8> {
+        match *self {
+            Reg::Xtensa(n) => Some(n),
+            Reg::Temp(_) => None,
+        }
+    }
+}
+
+impl Value {
+    pub fn reg(&self) -> Option<Reg> {
+        match *self {
+            Value::Reg(r) => Some(r),
+            Value::Imm(_) => None,
+        }
+    }
+}
+
+impl Cond {
+    pub fn name(&self) -> &'static str {
+        match *self {
+            Cond::Eq => "eq",
+            Cond::Ne => "ne",
+            Cond::Lt => "lt",
+            Cond::Ge => "ge",
+            Cond::Gt => "gt",
+            Cond::Le => "le",
+            Cond::Ltu => "ltu",
+            Cond::Geu => "geu",
+            Cond::Gtu => "gtu",
+            Cond::Leu => "leu",
+        }
+    }
+
+    /// Condition holding exactly when this one doesn't
+    pub fn inverse(&self) -> Cond {
+        match *self {
+            Cond::Eq => Cond::Ne,
+            Cond::Ne => Cond::Eq,
+            Cond::Lt => Cond::Ge,
+            Cond::Ge => Cond::Lt,
+            Cond::Gt => Cond::Le,
+            Cond::Le => Cond::Gt,
+            Cond::Ltu => Cond::Geu,
+            Cond::Geu => Cond::Ltu,
+            Cond::Gtu => Cond::Leu,
+            Cond::Leu => Cond::Gtu,
+        }
+    }
+}
+
+impl Width {
+    pub fn bytes(&self) -> u32 {
+        match *self {
+            Width::Byte => 1,
+            Width::Half => 2,
+            Width::Word => 4,
+        }
+    }
+}
+
+impl Op {
+    /// Registers read by the operation. Calling convention
+    /// registers of calls are not included
+    pub fn uses(&self) -> Vec<Reg> {
+        let values = |v: &[Value]| v.iter().filter_map(|v| v.reg()).collect::<Vec<Reg>>();
+
+        match *self {
+            Op::Mov { src, .. } => values(&[src]),
+            Op::Binary { a, b, .. } => values(&[a, b]),
+            Op::AddShifted { a, b, .. } |
+            Op::MulHigh { a, b, .. } |
+            Op::Mul16 { a, b, .. } |
+            Op::Div { a, b, .. } => vec![a, b],
+            Op::Unary { src, .. } |
+            Op::Extract { src, .. } |
+            Op::Saturate { src, .. } => vec![src],
+            Op::FunnelShift { high, low, amount, .. } => {
+                let mut regs = vec![high, low];
+                regs.extend(amount.reg());
+                regs
+            }
+            Op::Load { base, .. } => vec![base],
+            Op::Store { src, base, .. } => vec![src, base],
+            Op::CompareSwap { reg, base, compare, .. } => vec![reg, base, compare],
+            Op::StoreGlobal { src, .. } => vec![src],
+            Op::ExchangeGlobal { reg, .. } => vec![reg],
+            Op::Compare { a, b, .. } => { let mut regs = vec![a]; regs.extend(b.reg()); regs }
+            // Destination is kept if the condition fails
+            Op::Select { dst, src, .. } => vec![dst, src],
+            Op::LoopBack { .. } => vec![Reg::Xtensa(LCOUNT)],
+            Op::Call { target: CallTarget::Reg(r), .. } => vec![r],
+            Op::Intrinsic { ref args, .. } => values(args),
+            _ => Vec::new(),
+        }
+    }
+
+    /// Whether every target lowers the operation without
+    /// touching the flags, i.e. without a compare or a call
+    pub fn keeps_flags(&self) -> bool {
+        !matches!(*self,
+            Op::Saturate { .. } |
+            Op::Div { .. } |
+            Op::CompareSwap { .. } |
+            Op::Compare { .. } |
+            Op::LoopBack { .. } |
+            Op::Call { .. } |
+            Op::Intrinsic { .. } |
+            Op::Entry { .. } |
+            Op::Return { .. })
+    }
+
+    /// Registers written by the operation
+    pub fn defs(&self) -> Vec<Reg> {
+        match *self {
+            Op::Mov { dst, .. } |
+            Op::Binary { dst, .. } |
+            Op::AddShifted { dst, .. } |
+            Op::Unary { dst, .. } |
+            Op::Extract { dst, .. } |
+            Op::Saturate { dst, .. } |
+            Op::MulHigh { dst, .. } |
+            Op::Mul16 { dst, .. } |
+            Op::Div { dst, .. } |
+            Op::FunnelShift { dst, .. } |
+            Op::Load { dst, .. } |
+            Op::LoadGlobal { dst, .. } |
+            Op::Select { dst, .. } => vec![dst],
+            Op::CompareSwap { reg, .. } |
+            Op::ExchangeGlobal { reg, .. } => vec![reg],
+            Op::LoopBack { .. } => vec![Reg::Xtensa(LCOUNT)],
+            // callN copies the return value back to a(N+2)
+            Op::Call { rotation, .. } if rotation > 0 => vec![Reg::Xtensa(rotation + 2)],
+            Op::Intrinsic { dst, .. } => dst.into_iter().collect(),
+            _ => Vec::new(),
+        }
+    }
+}
+
+impl fmt::Display for Reg {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Reg::Xtensa(r @ 0 ..= 15) => write!(f, "a{:}", r),
+            Reg::Xtensa(SAR) => write!(f, "sar"),
+            Reg::Xtensa(SCOMPARE1) => write!(f, "scompare1"),
+            Reg::Xtensa(LCOUNT) => write!(f, "lcount"),
+            Reg::Xtensa(BR) => write!(f, "br"),
+            Reg::Xtensa(r) => write!(f, "x{:}", r),
+            Reg::Temp(t) => write!(f, "t{:}", t),
+        }
+    }
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Value::Reg(r) => write!(f, "{:}", r),
+            Value::Imm(i) if !(-9 ..= 9).contains(&i) => write!(f, "0x{:x}", i as u32),
+            Value::Imm(i) => write!(f, "{:}", i),
+        }
+    }
+}
+
+impl fmt::Display for Op {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let sign = |signed: bool| if signed { "s" } else { "u" };
+
+        match *self {
+            Op::Mov { dst, src } => write!(f, "{:} = {:}", dst, src),
+            Op::Binary { op, dst, a, b } => {
+                let operator = match op {
+                    BinOp::Add => "+",
+                    BinOp::Sub => "-",
+                    BinOp::Mul => "*",
+                    BinOp::And => "&",
+                    BinOp::Or => "|",
+                    BinOp::Xor => "^",
+                    BinOp::Shl => "<<",
+                    BinOp::Lsr => ">>",
+                    BinOp::Asr => ">>s",
+                };
+
+                write!(f, "{:} = {:} {:} {:}", dst, a, operator, b)
+            }
+            Op::AddShifted { dst, a, shift, b } => write!(f, "{:} = ({:} << {:}) + {:}", dst, a, shift, b),
+            Op::Unary { op, dst, src } => write!(f, "{:} = {:}({:})", dst,
+                if op == UnaryOp::Clz { "clz" } else { "cls" }, src),
+            Op::Extract { dst, src, shift, width, signed } =>
+                write!(f, "{:} = extract{:}({:}, {:}, {:})", dst, sign(signed), src, shift, width),
+            Op::Saturate { dst, src, bits } => write!(f, "{:} = saturate({:}, {:})", dst, src, bits),
+            Op::MulHigh { dst, a, b, signed } => write!(f, "{:} = mulh{:}({:}, {:})", dst, sign(signed), a, b),
+            Op::Mul16 { dst, a, b, signed } => write!(f, "{:} = mul16{:}({:}, {:})", dst, sign(signed), a, b),
+            Op::Div { dst, a, b, signed, remainder } =>
+                write!(f, "{:} = {:}{:}({:}, {:})", dst, if remainder { "rem" } else { "div" }, sign(signed), a, b),
+            Op::FunnelShift { dst, high, low, amount } =>
+                write!(f, "{:} = funnel({:}, {:}, {:})", dst, high, low, amount),
+            Op::Load { dst, base, offset, width, signed, ordered } =>
+                write!(f, "{:} = load{:}{:}{:} [{:} + 0x{:x}]", dst, 8 * width.bytes(), sign(signed),
+                    if ordered { ".acquire" } else { "" }, base, offset),
+            Op::Store { src, base, offset, width, ordered } =>
+                write!(f, "store{:}{:} [{:} + 0x{:x}] = {:}", 8 * width.bytes(),
+                    if ordered { ".release" } else { "" }, base, offset, src),
+            Op::CompareSwap { reg, base, offset, compare } =>
+                write!(f, "{:} = cas [{:} + 0x{:x}], {:}, {:}", reg, base, offset, compare, reg),
+            Op::LoadGlobal { dst, ref symbol } => write!(f, "{:} = [{:}]", dst, symbol),
+            Op::StoreGlobal { src, ref symbol } => write!(f, "[{:}] = {:}", symbol, src),
+            Op::ExchangeGlobal { reg, ref symbol } => write!(f, "{:} <-> [{:}]", reg, symbol),
+            Op::Compare { kind, a, b } => {
+                let operator = match kind {
+                    CompareKind::Cmp => "cmp",
+                    CompareKind::Test => "test",
+                    CompareKind::TestClear => "testclear",
+                };
+
+                write!(f, "flags = {:} {:}, {:}", operator, a, b)
+            }
+            Op::Select { cond, dst, src } => write!(f, "if {:} {:} = {:}", cond.name(), dst, src),
+            Op::Branch { cond: Some(cond), target } => write!(f, "if {:} goto loc_{:x}", cond.name(), target),
+            Op::Branch { cond: None, target } => write!(f, "goto loc_{:x}", target),
+            Op::LoopBack { target } => write!(f, "if --lcount goto loc_{:x}", target),
+            Op::Call { ref target, rotation } => {
+                match *target {
+                    CallTarget::Function { ref name, .. } => write!(f, "call{:} {:}", rotation, name),
+                    CallTarget::Reg(r) => write!(f, "call{:} *{:}", rotation, r),
+                }
+            }
+            Op::Intrinsic { ref name, dst, ref args } => {
+                let args: Vec<String> = args.iter().map(|a| a.to_string()).collect();
+
+                match dst {
+                    Some(dst) => write!(f, "{:} = {:}({:})", dst, name, args.join(", ")),
+                    None => write!(f, "{:}({:})", name, args.join(", ")),
+                }
+            }
+            Op::Entry { frame } => write!(f, "entry {:}", frame),
+            Op::Return { windowed } => write!(f, "{:}", if windowed { "retw" } else { "ret" }),
+            Op::Barrier => write!(f, "barrier"),
+            Op::Nop => write!(f, "nop"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use translation::ir::{Reg, Value, BinOp, Cond, CallTarget, CompareKind, Width, Op};
+    use translation::xtensa_op::{SAR, BR, LCOUNT};
+
+    #[test]
+    fn test_uses_1() {
+        let a = |r| Reg::Xtensa(r);
+
+        assert_eq!(Op::Mov { dst: a(2), src: Value::Imm(1) }.uses(), vec![]);
+        assert_eq!(Op::Binary { op: BinOp::Add, dst: a(2), a: Value::Reg(a(3)), b: Value::Imm(4) }.uses(), vec![a(3)]);
+        assert_eq!(Op::Store { src: a(2), base: a(1), offset: 8, width: Width::Word, ordered: false }.uses(), vec![a(2), a(1)]);
+        assert_eq!(Op::Select { cond: Cond::Eq, dst: a(2), src: Reg::Temp(0) }.uses(), vec![a(2), Reg::Temp(0)]);
+        assert_eq!(Op::LoopBack { target: 0x10 }.uses(), vec![a(LCOUNT)]);
+        assert_eq!(Op::Call { target: CallTarget::Reg(a(8)), rotation: 8 }.uses(), vec![a(8)]);
+        // Arguments are not included
+        assert_eq!(Op::Call { target: CallTarget::Function { name: "f".to_string(), address: 0 }, rotation: 0 }.uses(), vec![]);
+    }
+
+    #[test]
+    fn test_defs_1() {
+        let a = |r| Reg::Xtensa(r);
+
+        assert_eq!(Op::Load { dst: a(2), base: a(1), offset: 0, width: Width::Byte, signed: true, ordered: false }.defs(), vec![a(2)]);
+        assert_eq!(Op::CompareSwap { reg: a(2), base: a(3), offset: 0, compare: a(4) }.defs(), vec![a(2)]);
+        assert_eq!(Op::Compare { kind: CompareKind::Cmp, a: a(2), b: Value::Imm(0) }.defs(), vec![]);
+        assert_eq!(Op::LoopBack { target: 0x10 }.defs(), vec![a(LCOUNT)]);
+        assert_eq!(Op::Call { target: CallTarget::Reg(a(8)), rotation: 8 }.defs(), vec![a(10)]);
+        assert_eq!(Op::Call { target: CallTarget::Reg(a(8)), rotation: 0 }.defs(), vec![]);
+        assert_eq!(Op::Intrinsic { name: "f".to_string(), dst: None, args: vec![Value::Reg(a(2))] }.defs(), vec![]);
+    }
+
+    #[test]
+    fn test_keeps_flags_1() {
+        assert!(Op::Mov { dst: Reg::Xtensa(2), src: Value::Imm(0x12345678) }.keeps_flags());
+        assert!(Op::Select { cond: Cond::Lt, dst: Reg::Xtensa(2), src: Reg::Xtensa(3) }.keeps_flags());
+        assert!(Op::Branch { cond: None, target: 0 }.keeps_flags());
+        assert!(!Op::Compare { kind: CompareKind::Test, a: Reg::Xtensa(2), b: Value::Imm(1) }.keeps_flags());
+        assert!(!Op::LoopBack { target: 0 }.keeps_flags());
+        assert!(!Op::Return { windowed: false }.keeps_flags());
+    }
+
+    #[test]
+    fn test_display_1() {
+        let a = |r| Reg::Xtensa(r);
+
+        assert_eq!(a(SAR).to_string(), "sar");
+        assert_eq!(a(BR).to_string(), "br");
+        assert_eq!(Value::Imm(-9).to_string(), "-9");
+        assert_eq!(Value::Imm(-10).to_string(), "0xfffffff6");
+        assert_eq!(Op::Binary { op: BinOp::Xor, dst: a(2), a: Value::Reg(a(3)), b: Value::Imm(16) }.to_string(), "a2 = a3 ^ 0x10");
+        assert_eq!(Op::Load { dst: Reg::Temp(0), base: a(1), offset: 8, width: Width::Half, signed: true, ordered: false }.to_string(),
+            "t0 = load16s [a1 + 0x8]");
+        assert_eq!(Op::Branch { cond: Some(Cond::Geu), target: 0x40 }.to_string(), "if geu goto loc_40");
+        assert_eq!(Op::Call { target: CallTarget::Reg(a(8)), rotation: 8 }.to_string(), "call8 *a8");
+    }
+}
@@ -1,3 +1,15 @@
 pub mod xtensa_arm;
+pub mod xtensa_aarch64;
+pub mod xtensa_c;
 pub mod xtensa_operand;
 pub mod xtensa_op;
+pub mod xtensa_lift;
+pub mod ir;
+pub mod optimize;
+pub mod xtensa_decode;
+pub mod xtensa_abi;
+pub mod aapcs;
+pub mod regalloc;
+pub mod arm_cpu;
+pub mod xtensa_sr;
+pub mod backend;
@@ -0,0 +1,734 @@
+use std::vec::Vec;
+use std::collections::{BTreeMap, BTreeSet};
+
+use translation::xtensa_op::{SCOMPARE1, LCOUNT};
+use translation::ir::{Op, Reg, Value, BinOp, UnaryOp, CompareKind, Cond, CallTarget};
+use cfg::Cfg;
+
+/// Pseudo register standing for the flags in liveness
+const FLAGS: Reg = Reg::Xtensa(LCOUNT + 1);
+
+/// What the flags hold
+#[derive(Clone, PartialEq, Debug)]
+enum Flags {
+    /// Compare done at run time
+    Set(CompareKind, Reg, Value),
+    /// Compare of constants, evaluated here instead
+    Known(CompareKind, i32, i32),
+}
+
+/// What holds at a point of the function on every path to it
+#[derive(Clone, Default, PartialEq, Debug)]
+struct Facts {
+    /// Registers holding a constant or a copy of another register
+    values: BTreeMap<Reg, Value>,
+    /// Registers holding `a & b`
+    masks: BTreeMap<Reg, (Reg, Value)>,
+    flags: Option<Flags>,
+}
+
+impl Facts {
+    /// Constant or register holding the same value
+    fn value(&self, value: Value) -> Value {
+        match value {
+            Value::Reg(r) => self.values.get(&r).cloned().unwrap_or(value),
+            Value::Imm(_) => value,
+        }
+    }
+
+    /// Register holding the same value
+    fn reg(&self, r: Reg) -> Reg {
+        match self.values.get(&r) {
+            Some(&Value::Reg(s)) => s,
+            _ => r,
+        }
+    }
+
+    fn constant(&self, value: Value) -> Option<i32> {
+        match self.value(value) {
+            Value::Imm(i) => Some(i),
+            Value::Reg(_) => None,
+        }
+    }
+
+    /// Forgets everything a write to `r` invalidates
+    fn kill(&mut self, r: Reg) {
+        self.values.remove(&r);
+        self.values.retain(|_, v| *v != Value::Reg(r));
+        self.masks.remove(&r);
+        self.masks.retain(|_, &mut (a, b)| a != r && b != Value::Reg(r));
+
+        if let Some(Flags::Set(_, a, b)) = self.flags {
+            if a == r || b == Value::Reg(r) {
+                self.flags = None;
+            }
+        }
+    }
+
+    /// Forgets everything involving registers `keep` rejects
+    fn retain<F>(&mut self, keep: F) where F: Fn(Reg) -> bool {
+        let keep_value = |v: &Value| v.reg().into_iter().all(&keep);
+
+        self.values.retain(|&r, v| keep(r) && keep_value(v));
+        self.masks.retain(|&r, &mut (a, ref b)| keep(r) && keep(a) && keep_value(b));
+
+        if let Some(Flags::Set(_, a, ref b)) = self.flags.clone() {
+            if !keep(a) || !keep_value(b) {
+                self.flags = None;
+            }
+        }
+    }
+
+    /// Facts holding on both paths
+    fn meet(&self, other: &Facts) -> Facts {
+        Facts {
+            values: self.values.iter()
+                .filter(|&(r, v)| other.values.get(r) == Some(v))
+                .map(|(&r, &v)| (r, v))
+                .collect(),
+            masks: self.masks.iter()
+                .filter(|&(r, m)| other.masks.get(r) == Some(m))
+                .map(|(&r, &m)| (r, m))
+                .collect(),
+            flags: if self.flags == other.flags { self.flags.clone() } else { None },
+        }
+    }
+}
+
+/// Condition an operation reads the flags with
+fn condition(op: &Op) -> Option<Cond> {
+    match *op {
+        Op::Select { cond, .. } |
+        Op::Branch { cond: Some(cond), .. } => Some(cond),
+        _ => None,
+    }
+}
+
+/// Evaluates a condition on a compare of constants,
+/// None if the compare doesn't define it
+fn holds(kind: CompareKind, a: i32, b: i32, cond: Cond) -> Option<bool> {
+    let bits = match kind {
+        CompareKind::Cmp => return Some(match cond {
+            Cond::Eq => a == b,
+            Cond::Ne => a != b,
+            Cond::Lt => a < b,
+            Cond::Ge => a >= b,
+            Cond::Gt => a > b,
+            Cond::Le => a <= b,
+            Cond::Ltu => (a as u32) < (b as u32),
+            Cond::Geu => (a as u32) >= (b as u32),
+            Cond::Gtu => (a as u32) > (b as u32),
+            Cond::Leu => (a as u32) <= (b as u32),
+        }),
+        CompareKind::Test => a & b,
+        CompareKind::TestClear => a & !b,
+    };
+
+    match cond {
+        Cond::Eq => Some(bits == 0),
+        Cond::Ne => Some(bits != 0),
+        _ => None,
+    }
+}
+
+/// Result of a binary operation on constants
+fn evaluate(op: BinOp, a: i32, b: i32) -> i32 {
+    let amount = b as u32;
+
+    match op {
+        BinOp::Add => a.wrapping_add(b),
+        BinOp::Sub => a.wrapping_sub(b),
+        BinOp::Mul => a.wrapping_mul(b),
+        BinOp::And => a & b,
+        BinOp::Or => a | b,
+        BinOp::Xor => a ^ b,
+        BinOp::Shl => if amount < 32 { a << amount } else { 0 },
+        BinOp::Lsr => if amount < 32 { ((a as u32) >> amount) as i32 } else { 0 },
+        BinOp::Asr => a >> amount.min(31),
+    }
+}
+
+/// Whether a call with window rotation `rotation` preserves a
+/// register: a1 and a12..a15 for call0, a1..a(N-1) for windowed
+/// calls. The return address in a0 is overwritten
+fn preserved(r: Reg, rotation: u8) -> bool {
+    match r {
+        Reg::Xtensa(1) => true,
+        Reg::Xtensa(n) if rotation == 0 => (12 .. 16).contains(&n),
+        Reg::Xtensa(n) => (2 .. rotation).contains(&n),
+        Reg::Temp(_) => false,
+    }
+}
+
+/// Replaces operands by constants or registers holding the
+/// same value. Constants only go where the IR takes them
+fn substitute(facts: &Facts, op: &Op) -> Op {
+    let reg = |r: Reg| facts.reg(r);
+    let copy = |v: Value| match v {
+        Value::Reg(r) => Value::Reg(facts.reg(r)),
+        Value::Imm(_) => v,
+    };
+
+    match *op {
+        Op::Mov { dst, src } => Op::Mov { dst, src: facts.value(src) },
+        Op::Binary { op, dst, a, b } => {
+            let a = match op {
+                // Shifted operand stays a register
+                BinOp::Shl | BinOp::Lsr | BinOp::Asr => copy(a),
+                _ => facts.value(a),
+            };
+
+            Op::Binary { op, dst, a, b: facts.value(b) }
+        }
+        Op::AddShifted { dst, a, shift, b } => Op::AddShifted { dst, a: reg(a), shift, b: reg(b) },
+        Op::Unary { op, dst, src } => Op::Unary { op, dst, src: reg(src) },
+        Op::Extract { dst, src, shift, width, signed } =>
+            Op::Extract { dst, src: reg(src), shift, width, signed },
+        Op::Saturate { dst, src, bits } => Op::Saturate { dst, src: reg(src), bits },
+        Op::MulHigh { dst, a, b, signed } => Op::MulHigh { dst, a: reg(a), b: reg(b), signed },
+        Op::Mul16 { dst, a, b, signed } => Op::Mul16 { dst, a: reg(a), b: reg(b), signed },
+        Op::Div { dst, a, b, signed, remainder } =>
+            Op::Div { dst, a: reg(a), b: reg(b), signed, remainder },
+        Op::FunnelShift { dst, high, low, amount } => {
+            // SAR may hold up to 63, larger amounts are left to run time
+            let amount = match facts.constant(amount) {
+                Some(k) if (0 ..= 32).contains(&k) => Value::Imm(k),
+                _ => copy(amount),
+            };
+
+            Op::FunnelShift { dst, high: reg(high), low: reg(low), amount }
+        }
+        Op::Load { dst, base, offset, width, signed, ordered } =>
+            Op::Load { dst, base: reg(base), offset, width, signed, ordered },
+        Op::Store { src, base, offset, width, ordered } =>
+            Op::Store { src: reg(src), base: reg(base), offset, width, ordered },
+        Op::CompareSwap { reg: r, base, offset, compare } =>
+            Op::CompareSwap { reg: r, base: reg(base), offset, compare: reg(compare) },
+        Op::StoreGlobal { src, ref symbol } => Op::StoreGlobal { src: reg(src), symbol: symbol.clone() },
+        Op::Compare { kind, a, b } => Op::Compare { kind, a: reg(a), b: facts.value(b) },
+        Op::Select { cond, dst, src } => Op::Select { cond, dst, src: reg(src) },
+        Op::Call { target: CallTarget::Reg(r), rotation } =>
+            Op::Call { target: CallTarget::Reg(reg(r)), rotation },
+        Op::Intrinsic { ref name, dst, ref args } =>
+            Op::Intrinsic { name: name.clone(), dst, args: args.iter().map(|&a| copy(a)).collect() },
+        ref op => op.clone(),
+    }
+}
+
+/// Simplifies a binary operation with at most one constant operand
+fn simplify(op: BinOp, dst: Reg, a: Value, b: Value) -> Op {
+    let mov = |src: Value| Op::Mov { dst, src };
+
+    match (op, a, b) {
+        // Constant goes second where the order doesn't matter
+        (BinOp::Add, Value::Imm(_), _) |
+        (BinOp::Mul, Value::Imm(_), _) |
+        (BinOp::And, Value::Imm(_), _) |
+        (BinOp::Or, Value::Imm(_), _) |
+        (BinOp::Xor, Value::Imm(_), _) => simplify(op, dst, b, a),
+        (BinOp::Add, _, Value::Imm(0)) |
+        (BinOp::Sub, _, Value::Imm(0)) |
+        (BinOp::Or, _, Value::Imm(0)) |
+        (BinOp::Xor, _, Value::Imm(0)) |
+        (BinOp::And, _, Value::Imm(-1)) |
+        (BinOp::Mul, _, Value::Imm(1)) |
+        (BinOp::Shl, _, Value::Imm(0)) |
+        (BinOp::Lsr, _, Value::Imm(0)) |
+        (BinOp::Asr, _, Value::Imm(0)) => mov(a),
+        (BinOp::And, _, Value::Imm(0)) |
+        (BinOp::Mul, _, Value::Imm(0)) => mov(Value::Imm(0)),
+        (BinOp::Shl, _, Value::Imm(k)) |
+        (BinOp::Lsr, _, Value::Imm(k)) if k as u32 >= 32 => mov(Value::Imm(0)),
+        (BinOp::Asr, _, Value::Imm(k)) if k as u32 >= 32 => Op::Binary { op, dst, a, b: Value::Imm(31) },
+        // Negative constants are subtracted instead
+        (BinOp::Add, _, Value::Imm(k)) if k < 0 && k != i32::MIN =>
+            Op::Binary { op: BinOp::Sub, dst, a, b: Value::Imm(-k) },
+        (BinOp::Sub, _, Value::Imm(k)) if k < 0 && k != i32::MIN =>
+            Op::Binary { op: BinOp::Add, dst, a, b: Value::Imm(-k) },
+        _ => Op::Binary { op, dst, a, b },
+    }
+}
+
+/// Evaluates operations on constants and simplifies
+/// ones with constant operands
+fn fold(facts: &Facts, op: Op) -> Op {
+    let constant = |r: Reg| facts.constant(Value::Reg(r));
+    let mov = |dst: Reg, value: i32| Op::Mov { dst, src: Value::Imm(value) };
+
+    match op {
+        Op::Binary { op, dst, a, b } => match (facts.constant(a), facts.constant(b)) {
+            (Some(x), Some(y)) => mov(dst, evaluate(op, x, y)),
+            _ => simplify(op, dst, a, b),
+        },
+        Op::AddShifted { dst, a, shift, b } => match (constant(a), constant(b)) {
+            (Some(x), Some(y)) => mov(dst, (x << shift).wrapping_add(y)),
+            (Some(x), None) => simplify(BinOp::Add, dst, Value::Reg(b), Value::Imm(x << shift)),
+            _ => op,
+        },
+        Op::Unary { op: kind, dst, src } => match constant(src) {
+            Some(x) if kind == UnaryOp::Clz => mov(dst, x.leading_zeros() as i32),
+            // Sign bits become zeros
+            Some(x) => mov(dst, (x ^ (x >> 31)).leading_zeros() as i32 - 1),
+            None => op,
+        },
+        Op::Extract { dst, src, shift, width, signed } => match constant(src) {
+            Some(x) => {
+                // Field is moved to the top, then back down
+                let top = (x as u32) << (32 - shift - width);
+
+                mov(dst, if signed { (top as i32) >> (32 - width) } else { (top >> (32 - width)) as i32 })
+            }
+            None => op,
+        },
+        Op::Saturate { dst, src, bits } => match constant(src) {
+            Some(x) => mov(dst, x.max(-1 << (bits - 1)).min((1 << (bits - 1)) - 1)),
+            None => op,
+        },
+        Op::FunnelShift { dst, high, low, amount: Value::Imm(k) } => match (constant(high), constant(low)) {
+            _ if k == 0 => Op::Mov { dst, src: facts.value(Value::Reg(low)) },
+            _ if k == 32 => Op::Mov { dst, src: facts.value(Value::Reg(high)) },
+            (Some(x), Some(y)) => mov(dst, ((((x as u32 as u64) << 32) | y as u32 as u64) >> k) as u32 as i32),
+            _ => op,
+        },
+        _ => op,
+    }
+}
+
+/// Outcome of a condition if the flags come from a compare of constants
+fn known(facts: &Facts, cond: Cond) -> Option<bool> {
+    match facts.flags {
+        Some(Flags::Known(kind, a, b)) => holds(kind, a, b, cond),
+        _ => None,
+    }
+}
+
+/// Rewrites a compare read by the conditions in `rest`. Compares
+/// of constants are evaluated, zero tests of masked values test
+/// the mask instead and compares the flags already hold are dropped
+fn compare(facts: &mut Facts, kind: CompareKind, a: Reg, b: Value, rest: &[Op]) -> Option<Op> {
+    let readers: Vec<Cond> = rest.iter()
+        .take_while(|op| op.keeps_flags())
+        .filter_map(condition)
+        .collect();
+
+    if let (Some(x), Some(y)) = (facts.constant(Value::Reg(a)), facts.constant(b)) {
+        if readers.iter().all(|&cond| holds(kind, x, y, cond).is_some()) {
+            facts.flags = Some(Flags::Known(kind, x, y));
+            return None;
+        }
+    }
+
+    let zero_test = kind == CompareKind::Cmp && b == Value::Imm(0) &&
+        readers.iter().all(|&cond| cond == Cond::Eq || cond == Cond::Ne);
+
+    let (kind, a, b) = match facts.masks.get(&a) {
+        Some(&(x, mask)) if zero_test => (CompareKind::Test, x, mask),
+        _ => (kind, a, b),
+    };
+    let flags = Some(Flags::Set(kind, a, b));
+
+    if facts.flags == flags {
+        return None;
+    }
+
+    facts.flags = flags;
+    Some(Op::Compare { kind, a, b })
+}
+
+/// Updates facts to after an operation
+fn transfer(facts: &mut Facts, op: &Op) {
+    if !op.keeps_flags() {
+        facts.flags = None;
+    }
+
+    match *op {
+        Op::Call { rotation, .. } => facts.retain(|r| preserved(r, rotation)),
+        Op::Entry { .. } => *facts = Facts::default(),
+        _ => (),
+    }
+
+    for r in op.defs() {
+        facts.kill(r);
+    }
+
+    match *op {
+        Op::Mov { dst, src } => {
+            facts.values.insert(dst, src);
+        }
+        Op::Binary { op: BinOp::And, dst, a: Value::Reg(a), b } if a != dst && b != Value::Reg(dst) => {
+            facts.masks.insert(dst, (a, b));
+        }
+        Op::Extract { dst, src, shift, width: 1, signed: false } if src != dst => {
+            facts.masks.insert(dst, (src, Value::Imm(1 << shift)));
+        }
+        _ => (),
+    }
+}
+
+/// Rewrites one operation with the facts holding before it and
+/// updates them to after it. `rest` are the operations following
+/// it in the instruction. None if the operation isn't needed
+fn step(facts: &mut Facts, op: &Op, rest: &[Op]) -> Option<Op> {
+    let mut op = fold(facts, substitute(facts, op));
+
+    match op {
+        // Destination already holds the value
+        Op::Mov { dst, src } if facts.value(Value::Reg(dst)) == src => return None,
+        Op::Select { dst, src, .. } if dst == src => return None,
+        Op::Select { cond, dst, src } => match known(facts, cond) {
+            Some(true) => return step(facts, &Op::Mov { dst, src: Value::Reg(src) }, rest),
+            Some(false) => return None,
+            None => (),
+        },
+        Op::Branch { cond: Some(cond), target } => match known(facts, cond) {
+            Some(true) => op = Op::Branch { cond: None, target },
+            Some(false) => return None,
+            None => (),
+        },
+        Op::Compare { kind, a, b } => return compare(facts, kind, a, b, rest),
+        _ => (),
+    }
+
+    transfer(facts, &op);
+    Some(op)
+}
+
+/// Rewrites the operations of one instruction
+fn rewrite(facts: &mut Facts, ops: &[Op]) -> Vec<Op> {
+    let mut result = Vec::<Op>::new();
+
+    for (n, op) in ops.iter().enumerate() {
+        result.extend(step(facts, op, &ops[n + 1 ..]));
+    }
+
+    // Temporaries don't outlive the instruction
+    for r in ops.iter().flat_map(|op| op.defs()) {
+        if r.xtensa().is_none() {
+            facts.kill(r);
+        }
+    }
+
+    result
+}
+
+/// Propagates constants and copies along the control-flow graph,
+/// then rewrites every instruction with what holds before it
+fn propagate(code: &mut [Option<Vec<Op>>], cfg: &Cfg) {
+    // Blocks without predecessors may still be reached,
+    // e.g. through jump tables
+    let mut entry: Vec<Option<Facts>> = cfg.blocks.iter()
+        .enumerate()
+        .map(|(b, block)| if b == 0 || block.predecessors.is_empty() { Some(Facts::default()) } else { None })
+        .collect();
+    let mut changed = true;
+
+    while changed {
+        changed = false;
+
+        for (b, block) in cfg.blocks.iter().enumerate() {
+            let mut facts = match entry[b] {
+                Some(ref facts) => facts.clone(),
+                None => continue
+            };
+
+            for ops in &code[block.start .. block.end] {
+                match *ops {
+                    Some(ref ops) => { rewrite(&mut facts, ops); }
+                    None => facts = Facts::default(),
+                }
+            }
+
+            for &s in &block.successors {
+                let merged = match entry[s] {
+                    Some(ref old) => old.meet(&facts),
+                    None => facts.clone(),
+                };
+
+                if entry[s].as_ref() != Some(&merged) {
+                    entry[s] = Some(merged);
+                    changed = true;
+                }
+            }
+        }
+    }
+
+    for (b, block) in cfg.blocks.iter().enumerate() {
+        let mut facts = entry[b].clone().unwrap_or_default();
+
+        for ops in &mut code[block.start .. block.end] {
+            match *ops {
+                Some(ref mut ops) => *ops = rewrite(&mut facts, ops),
+                None => facts = Facts::default(),
+            }
+        }
+    }
+}
+
+/// Registers which may be live where control leaves the
+/// function or passes through code without IR
+fn everything() -> BTreeSet<Reg> {
+    (0 ..= LCOUNT).map(Reg::Xtensa).collect()
+}
+
+/// Registers an operation reads, including the flags
+/// and the ones calls and returns pass on
+fn live_uses(op: &Op) -> BTreeSet<Reg> {
+    let mut regs: BTreeSet<Reg> = match *op {
+        Op::Call { .. } => everything(),
+        // Return address, stack pointer, return values and
+        // registers call0 callers expect to be preserved
+        Op::Return { windowed } => {
+            let kept: &[u8] = if windowed { &[] } else { &[12, 13, 14, 15] };
+
+            (0 .. 6).chain(kept.iter().cloned()).chain(Some(SCOMPARE1)).map(Reg::Xtensa).collect()
+        }
+        _ => op.uses().into_iter().collect(),
+    };
+
+    if condition(op).is_some() {
+        regs.insert(FLAGS);
+    }
+
+    regs
+}
+
+/// Registers an operation writes, including the flags
+fn live_defs(op: &Op) -> Vec<Reg> {
+    let mut regs = op.defs();
+
+    if !op.keeps_flags() {
+        regs.push(FLAGS);
+    }
+
+    regs
+}
+
+/// Whether an operation has no effect besides its destinations
+fn is_pure(op: &Op) -> bool {
+    matches!(*op,
+        Op::Mov { .. } |
+        Op::Binary { .. } |
+        Op::AddShifted { .. } |
+        Op::Unary { .. } |
+        Op::Extract { .. } |
+        Op::Saturate { .. } |
+        Op::MulHigh { .. } |
+        Op::Mul16 { .. } |
+        Op::Div { .. } |
+        Op::FunnelShift { .. } |
+        Op::Compare { .. } |
+        Op::Select { .. } |
+        Op::Nop)
+}
+
+/// Backward liveness through one instruction
+fn transfer_live(ops: &Option<Vec<Op>>, live: &mut BTreeSet<Reg>) {
+    match *ops {
+        Some(ref ops) => {
+            for op in ops.iter().rev() {
+                for r in live_defs(op) {
+                    live.remove(&r);
+                }
+
+                live.extend(live_uses(op));
+            }
+        }
+        None => *live = everything(),
+    }
+}
+
+/// Registers live at the end of each block
+fn live_out(code: &[Option<Vec<Op>>], cfg: &Cfg) -> Vec<BTreeSet<Reg>> {
+    let mut live_in = vec![BTreeSet::<Reg>::new(); cfg.blocks.len()];
+    let mut live_out = live_in.clone();
+    let mut changed = true;
+
+    while changed {
+        changed = false;
+
+        for (b, block) in cfg.blocks.iter().enumerate().rev() {
+            let returns = code[block.last()].iter().flatten().any(|op| matches!(*op, Op::Return { .. }));
+
+            // Code outside the function may read anything
+            let mut live = if !block.exits.is_empty() || block.successors.is_empty() && !returns {
+                everything()
+            } else {
+                BTreeSet::new()
+            };
+
+            for &s in &block.successors {
+                live.extend(live_in[s].iter().cloned());
+            }
+
+            live_out[b] = live.clone();
+
+            for ops in code[block.start .. block.end].iter().rev() {
+                transfer_live(ops, &mut live);
+            }
+
+            if live != live_in[b] {
+                live_in[b] = live;
+                changed = true;
+            }
+        }
+    }
+
+    live_out
+}
+
+/// Whether the flags are read after each instruction
+/// before being set again
+pub fn flags_live_out(code: &[Option<Vec<Op>>], cfg: &Cfg) -> Vec<bool> {
+    let live_out = live_out(code, cfg);
+    let mut result = vec![false; code.len()];
+
+    for (b, block) in cfg.blocks.iter().enumerate() {
+        let mut live = live_out[b].clone();
+
+        for n in (block.start .. block.end).rev() {
+            result[n] = live.contains(&FLAGS);
+            transfer_live(&code[n], &mut live);
+        }
+    }
+
+    result
+}
+
+/// Operations of an instruction reading the flags `ops[n]` sets,
+/// None if later instructions may read them too. `live_out`
+/// tells whether the flags are read after the instruction
+pub fn flag_readers(ops: &[Op], n: usize, live_out: bool) -> Option<Vec<usize>> {
+    let mut readers = Vec::<usize>::new();
+
+    for (i, op) in ops.iter().enumerate().skip(n + 1) {
+        if condition(op).is_some() {
+            readers.push(i);
+        }
+
+        if !op.keeps_flags() {
+            return Some(readers);
+        }
+    }
+
+    if live_out { None } else { Some(readers) }
+}
+
+/// Removes operations whose results are never read. Repeats
+/// until nothing changes, as removals make more values dead
+fn eliminate_dead(code: &mut [Option<Vec<Op>>], cfg: &Cfg) {
+    let mut changed = true;
+
+    while changed {
+        changed = false;
+        let live_out = live_out(code, cfg);
+
+        for (b, block) in cfg.blocks.iter().enumerate() {
+            let mut live = live_out[b].clone();
+
+            for ops in code[block.start .. block.end].iter_mut().rev() {
+                let ops = match *ops {
+                    Some(ref mut ops) => ops,
+                    None => {
+                        live = everything();
+                        continue;
+                    }
+                };
+                let mut kept = Vec::<Op>::new();
+
+                for op in ops.drain(..).rev() {
+                    let defs = live_defs(&op);
+
+                    if is_pure(&op) && !defs.iter().any(|r| live.contains(r)) {
+                        changed = true;
+                        continue;
+                    }
+
+                    for r in &defs {
+                        live.remove(r);
+                    }
+
+                    live.extend(live_uses(&op));
+                    kept.push(op);
+                }
+
+                kept.reverse();
+                *ops = kept;
+            }
+        }
+    }
+}
+
+/// Optimizes the IR of a function, given as the operations of each
+/// instruction: propagates constants and copies, evaluates and fuses
+/// compares, then removes dead code. None stands for instructions
+/// without IR, which may read and write anything
+pub fn optimize(code: &mut [Option<Vec<Op>>], cfg: &Cfg) {
+    propagate(code, cfg);
+    eliminate_dead(code, cfg);
+}
+
+#[cfg(test)]
+mod tests {
+    use translation::optimize::optimize;
+    use translation::xtensa_lift::Lifter;
+    use translation::xtensa_abi::XtensaAbi;
+    use translation::xtensa_sr::SrMap;
+    use object_storage::ObjectStorage;
+    use function::fixture;
+
+    /// Lifts call0 code placed at 0x100, optimizes it and
+    /// returns IR text of each instruction
+    fn optimized(code: &[&str]) -> Vec<String> {
+        let objects = ObjectStorage::default();
+        let mut lifted = Lifter::new(XtensaAbi::Call0, SrMap::default())
+            .lift_function(&fixture(code), &objects, false)
+            .unwrap();
+
+        optimize(&mut lifted.code, &lifted.cfg);
+
+        lifted.code.into_iter()
+            .map(|ops| ops.unwrap().iter().map(|op| op.to_string()).collect::<Vec<String>>().join("; "))
+            .collect()
+    }
+
+    #[test]
+    fn test_optimize_1() {
+        let code = [
+            "movi a8, 5", "add a2, a2, a8", "mov a9, a2", "addi a3, a9, -1",
+            "movi a8, 5", "slli a4, a8, 4", "ret",
+        ];
+
+        assert_eq!(optimized(&code), vec![
+            "", "a2 = a2 + 5", "", "a3 = a2 - 1", "", "a4 = 0x50", "ret",
+        ]);
+    }
+
+    #[test]
+    fn test_optimize_2() {
+        let code = [
+            "movi a8, 0", "beqz a8, 0x109", "movi a2, 1",
+            "extui a9, a3, 4, 1", "beqz a9, 0x112", "bnez a9, 0x100", "ret",
+        ];
+
+        assert_eq!(optimized(&code), vec![
+            "", "goto loc_109", "a2 = 1",
+            "", "flags = test a3, 0x10; if eq goto loc_112", "if ne goto loc_100", "ret",
+        ]);
+    }
+
+    #[test]
+    fn test_optimize_3() {
+        let code = ["movi a12, 0x100", "movi a6, 0x100", "callx0 a9", "add a2, a12, a6", "ret"];
+
+        assert_eq!(optimized(&code), vec![
+            "a12 = 0x100", "a6 = 0x100", "call0 *a9", "a2 = a6 + 0x100", "ret",
+        ]);
+
+        let code = ["movi a2, 0", "loopnez a3, 0x10c", "addi a2, a2, 1", "addi a2, a2, 1", "ret"];
+
+        assert_eq!(optimized(&code), vec![
+            "a2 = 0", "lcount = a3; flags = cmp a3, 0; if eq goto loc_10c",
+            "a2 = a2 + 1", "a2 = a2 + 1; if --lcount goto loc_106", "ret",
+        ]);
+    }
+}
@@ -0,0 +1,317 @@
+use std::vec::Vec;
+use std::collections::BTreeMap;
+
+use cfg::Cfg;
+use translation::xtensa_op::{XtensaInstruction, LCOUNT};
+
+/// Fixed register mapping. a0 and a1 are return address
+/// and stack pointer, a2..a7 are call0 argument and return
+/// registers and have to stay where AAPCS expects them
+const FIXED: [(u8, &str); 8] = [
+    (0, "lr"), (1, "sp"),
+    (2, "r0"), (3, "r1"), (4, "r2"), (5, "r3"), (6, "r4"), (7, "r5"),
+];
+
+/// Registers available to the rest of xtensa registers
+const POOL: [&str; 6] = ["r6", "r7", "r8", "r9", "r10", "r11"];
+
+/// Pool used when some registers have to be spilled:
+/// r10 becomes second scratch register, r11 points to spill slots
+const POOL_SPILL: [&str; 4] = ["r6", "r7", "r8", "r9"];
+
+const SCRATCH: &str = "r12";
+const SCRATCH_SPILL: &str = "r10";
+const FRAME_POINTER: &str = "r11";
+
+#[derive(Clone, PartialEq, Debug)]
+pub enum Location {
+    Reg(String),
+    /// Offset of a spill slot relative to the frame pointer
+    Spill(u32),
+    /// Register not referenced by the function
+    Unused,
+}
+
+/// Maps xtensa registers of a single function to ARM registers
+/// or spill slots
+#[derive(Default)]
+pub struct RegisterMap {
+    locations: BTreeMap<u8, Location>,
+    /// Registers not holding any xtensa register, available
+    /// to spill reloads and multi-instruction sequences
+    pub scratch: Vec<String>,
+    /// Register pointing to spill slots, if any
+    pub frame_pointer: Option<String>,
+    /// Spill area size in bytes
+    pub spill_size: u32,
+}
+
+/// Live range of a register in instruction indices
+#[derive(Clone, Copy)]
+struct Interval {
+    reg: u8,
+    start: usize,
+    end: usize,
+}
+
+/// Computes live intervals in program order from the registers
+/// each instruction accesses. An interval overlapping a loop is
+/// extended to the whole loop since the value may be carried
+/// along the back edge. Loop counter is read at the end of a
+/// zero-overhead loop body. Intervals of registers set before
+/// a windowed call are extended to it since they may be arguments
+fn intervals(regs: &[Vec<u8>], args: &[Vec<u8>], cfg: &Cfg) -> Vec<Interval> {
+    let mut ranges = BTreeMap::<u8, (usize, usize)>::new();
+
+    for (i, accessed) in regs.iter().enumerate() {
+        for &r in accessed {
+            let range = ranges.entry(r).or_insert((i, i));
+            range.0 = range.0.min(i);
+            range.1 = range.1.max(i);
+        }
+    }
+
+    for (i, call_args) in args.iter().enumerate() {
+        for r in call_args {
+            if let Some(range) = ranges.get_mut(r) {
+                if range.0 < i {
+                    range.1 = range.1.max(i);
+                }
+            }
+        }
+    }
+
+    for l in &cfg.loops {
+        let range = ranges.entry(LCOUNT).or_insert((l.start, l.start));
+        range.1 = range.1.max(l.end - 1);
+    }
+
+    let loops: Vec<(usize, usize)> = cfg.back_edges()
+        .iter()
+        .map(|e| (cfg.blocks[e.to].start, cfg.blocks[e.from].last()))
+        .collect();
+
+    let mut changed = true;
+
+    while changed {
+        changed = false;
+
+        for range in ranges.values_mut() {
+            for &(head, tail) in &loops {
+                let overlaps = range.0 <= tail && range.1 >= head;
+
+                if overlaps && (range.0 > head || range.1 < tail) {
+                    range.0 = range.0.min(head);
+                    range.1 = range.1.max(tail);
+                    changed = true;
+                }
+            }
+        }
+    }
+
+    ranges.into_iter()
+        .map(|(reg, (start, end))| Interval { reg, start, end })
+        .collect()
+}
+
+/// Linear scan over intervals sorted by start. Returns
+/// assigned registers and spilled registers
+fn linear_scan(intervals: &[Interval], pool: &[&str]) -> (BTreeMap<u8, String>, Vec<u8>) {
+    let mut assigned = BTreeMap::<u8, String>::new();
+    let mut spilled = Vec::<u8>::new();
+    let mut active = Vec::<(Interval, String)>::new();
+    let mut free: Vec<String> = pool.iter().map(|r| r.to_string()).collect();
+
+    let mut sorted = intervals.to_vec();
+    sorted.sort_by_key(|i| (i.start, i.reg));
+
+    for interval in sorted {
+        let mut expired = Vec::<String>::new();
+
+        active.retain(|(a, reg)| {
+            if a.end < interval.start {
+                expired.push(reg.clone());
+                false
+            } else {
+                true
+            }
+        });
+
+        free.extend(expired);
+        free.sort_by_key(|r| pool.iter().position(|p| p == r));
+
+        if !free.is_empty() {
+            let reg = free.remove(0);
+            assigned.insert(interval.reg, reg.clone());
+            active.push((interval, reg));
+            continue;
+        }
+
+        // Spill whichever lives longer
+        let furthest = (0 .. active.len()).max_by_key(|&i| active[i].0.end);
+
+        match furthest {
+            Some(i) if active[i].0.end > interval.end => {
+                let (victim, reg) = active.remove(i);
+
+                assigned.remove(&victim.reg);
+                spilled.push(victim.reg);
+                assigned.insert(interval.reg, reg.clone());
+                active.push((interval, reg));
+            }
+            _ => spilled.push(interval.reg),
+        }
+    }
+
+    (assigned, spilled)
+}
+
+impl RegisterMap {
+    /// Allocates registers for a function, `cfg` is used
+    /// to find loops
+    pub fn allocate(instructions: &[XtensaInstruction], cfg: &Cfg) -> RegisterMap {
+        let regs: Vec<Vec<u8>> = instructions.iter()
+            .map(|i| i.uses().into_iter().chain(i.defs()).collect())
+            .collect();
+
+        RegisterMap::allocate_regs(&regs, instructions, cfg)
+    }
+
+    /// Allocates from the registers each instruction accesses,
+    /// for code which doesn't map to instructions one-to-one
+    pub fn allocate_regs(regs: &[Vec<u8>], instructions: &[XtensaInstruction], cfg: &Cfg) -> RegisterMap {
+        let mut map = RegisterMap::default();
+        let args: Vec<Vec<u8>> = instructions.iter().map(|i| i.window_args()).collect();
+        let intervals: Vec<Interval> = intervals(regs, &args, cfg)
+            .into_iter()
+            .filter(|i| !FIXED.iter().any(|&(r, _)| r == i.reg))
+            .collect();
+
+        let (mut assigned, mut spilled) = linear_scan(&intervals, &POOL);
+        map.scratch = vec![SCRATCH.to_string()];
+
+        if !spilled.is_empty() {
+            let (a, s) = linear_scan(&intervals, &POOL_SPILL);
+            assigned = a;
+            spilled = s;
+            map.scratch = vec![SCRATCH.to_string(), SCRATCH_SPILL.to_string()];
+            map.frame_pointer = Some(FRAME_POINTER.to_string());
+        }
+
+        for &(r, reg) in FIXED.iter() {
+            map.locations.insert(r, Location::Reg(reg.to_string()));
+        }
+
+        for (r, reg) in assigned {
+            map.locations.insert(r, Location::Reg(reg));
+        }
+
+        spilled.sort();
+
+        for r in spilled {
+            map.locations.insert(r, Location::Spill(map.spill_size));
+            map.spill_size += 4;
+        }
+
+        // Keep stack 8-byte aligned
+        map.spill_size = (map.spill_size + 7) & !7;
+
+        map
+    }
+
+    pub fn location(&self, reg: u8) -> Location {
+        self.locations.get(&reg).cloned().unwrap_or(Location::Unused)
+    }
+
+    /// Returns ARM register holding an xtensa register,
+    /// None if it is spilled or unused
+    pub fn reg_name(&self, reg: u8) -> Option<String> {
+        match self.location(reg) {
+            Location::Reg(r) => Some(r),
+            _ => None
+        }
+    }
+
+    /// Registers the allocation itself writes, apart from
+    /// the ones holding xtensa registers
+    pub fn reserved(&self) -> Vec<String> {
+        let mut result = self.scratch.clone();
+        result.extend(self.frame_pointer.clone());
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use cfg::Cfg;
+    use translation::regalloc::{RegisterMap, Location};
+    use translation::xtensa_op::XtensaInstruction;
+    use assembly::ParseInstruction;
+
+    fn parse(code: &[&str]) -> Vec<XtensaInstruction> {
+        code.iter()
+            .map(|s| {
+                let mut i = XtensaInstruction::new();
+                i.from_str(s).unwrap();
+                i
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_regalloc_1() {
+        let code = parse(&["movi a15, 1", "mov a8, a15", "movi a9, 2", "add a2, a8, a9", "ret"]);
+        let cfg = Cfg::from_instructions(&code, &[0, 3, 6, 9, 12]);
+        let map = RegisterMap::allocate(&code, &cfg);
+
+        assert_eq!(map.location(2), Location::Reg("r0".to_string()));
+        assert_eq!(map.location(15), Location::Reg("r6".to_string()));
+        assert_eq!(map.location(8), Location::Reg("r7".to_string()));
+        // a15 is dead by the time a9 is defined
+        assert_eq!(map.location(9), Location::Reg("r6".to_string()));
+        assert_eq!(map.location(10), Location::Unused);
+        assert_eq!(map.spill_size, 0);
+    }
+
+    #[test]
+    fn test_regalloc_2() {
+        let code = parse(&[
+            "movi a8, 1", "movi a9, 1", "movi a10, 1", "movi a11, 1",
+            "movi a12, 1", "movi a13, 1", "movi a14, 1",
+            "add a8, a8, a9", "add a10, a10, a11", "add a12, a12, a13",
+            "add a14, a14, a8", "add a2, a14, a10", "add a2, a2, a12", "ret",
+        ]);
+        let offsets: Vec<u32> = (0 .. code.len() as u32).map(|i| i * 3).collect();
+        let map = RegisterMap::allocate(&code, &Cfg::from_instructions(&code, &offsets));
+
+        let spilled = (8 .. 15)
+            .filter(|&r| matches!(map.location(r), Location::Spill(_)))
+            .count();
+
+        assert_eq!(spilled, 3);
+        assert_eq!(map.spill_size, 16);
+        assert_eq!(map.frame_pointer, Some("r11".to_string()));
+        assert_eq!(map.scratch, vec!["r12", "r10"]);
+    }
+
+    #[test]
+    fn test_regalloc_3() {
+        // a8 is live around the loop, a9 is not live after it
+        let code = parse(&["movi a8, 0", "movi a9, 1", "add a8, a8, a9", "bnez a9, 0x6", "mov a2, a8", "ret"]);
+        let cfg = Cfg::from_instructions(&code, &[0, 3, 6, 9, 12, 15]);
+        let map = RegisterMap::allocate(&code, &cfg);
+
+        assert!(map.location(8) != map.location(9));
+    }
+
+    #[test]
+    fn test_regalloc_4() {
+        // a11 is an argument of callx8 and must not share a register with a8
+        let code = parse(&["entry a1, 32", "movi a11, 3", "movi a8, 5", "callx8 a8", "retw"]);
+        let cfg = Cfg::from_instructions(&code, &[0, 3, 6, 9, 12]);
+        let map = RegisterMap::allocate(&code, &cfg);
+
+        assert!(map.location(11) != map.location(8));
+        assert_eq!(map.location(10), Location::Reg("r8".to_string()));
+    }
+}
@@ -0,0 +1,886 @@
+use std::vec::Vec;
+use std::collections::BTreeSet;
+
+use object_storage::ObjectStorage;
+use assembly::{Instruction, InstructionArch};
+use translation::xtensa_op::{SAR, BR, SCOMPARE1, LCOUNT};
+use translation::xtensa_sr::SrMap;
+use translation::xtensa_abi::XtensaAbi;
+use translation::xtensa_lift::Lifter;
+use translation::optimize::{optimize, flags_live_out, flag_readers};
+use translation::ir::{Op, Reg, Value, BinOp, UnaryOp, Width, CompareKind, Cond, CallTarget};
+use translation::backend::Backend;
+use function::Function;
+use error::{Error, Result};
+
+/// AArch64 registers of a0..a15. Argument, caller-saved and
+/// callee-saved groups of call0 map to the same groups of
+/// AAPCS64, the stack pointer a1 to callee-saved w23. a0 and
+/// a1 hold 32-bit xtensa addresses like any other register,
+/// the host return address stays in x30
+const REGISTERS: [u8; 16] = [6, 23, 0, 1, 2, 3, 4, 5, 9, 10, 11, 12, 19, 20, 21, 22];
+
+/// Host address of xtensa address 0, which memory accesses
+/// are relative to. Callee-saved, loaded in the prologue
+const MEM_BASE: &str = "x28";
+
+/// Global pointer the memory base is loaded from
+const MEM_BASE_SYMBOL: &str = "xt_mem_base";
+
+/// BR, holding boolean registers b0..b15 as bits
+const BR_REG: u8 = 7;
+
+/// Loop counter. It's caller-saved, so loop bodies can't contain calls
+const LOOP_COUNTER: u8 = 8;
+
+const SAR_REG: u8 = 13;
+const SCOMPARE1_REG: u8 = 14;
+
+/// Register holding the IR temporary, which only
+/// lives within one instruction
+const TEMP_REG: u8 = 15;
+
+/// Scratch registers, AAPCS64 intra-procedure-call registers
+const SCRATCH: [&str; 2] = ["w16", "w17"];
+
+/// Registers saved around runtime helper calls: every AAPCS64
+/// caller-saved register holding xtensa state
+const CALL_CLOBBERED: [&str; 16] = [
+    "x0", "x1", "x2", "x3", "x4", "x5", "x6", "x7",
+    "x8", "x9", "x10", "x11", "x12", "x13", "x14", "x15",
+];
+
+#[derive(Default)]
+pub struct Translator {
+    /// Addresses of functions called from
+    /// the last translated function
+    pub referenced_objects: BTreeSet<u32>,
+    pub abi: XtensaAbi,
+    /// How special and user registers of the target are accessed
+    pub special_regs: SrMap,
+    /// Replace untranslatable instructions with
+    /// placeholders instead of failing
+    pub lenient: bool,
+    /// Function placeholders call, `udf` is used if not set
+    pub placeholder_helper: Option<String>,
+    /// Run the IR optimization passes over each function
+    pub optimize: bool,
+    /// Errors replaced by placeholders so far
+    pub placeholders: Vec<Error>,
+}
+
+fn reg_number(r: Reg) -> u8 {
+    match r {
+        Reg::Xtensa(n @ 0 ..= 15) => REGISTERS[n as usize],
+        Reg::Xtensa(SAR) => SAR_REG,
+        Reg::Xtensa(BR) => BR_REG,
+        Reg::Xtensa(SCOMPARE1) => SCOMPARE1_REG,
+        Reg::Xtensa(LCOUNT) => LOOP_COUNTER,
+        Reg::Temp(0) => TEMP_REG,
+        _ => panic!("Unknown/unsupported register referenced: {:}", r)
+    }
+}
+
+/// A64 condition code of an IR condition
+fn a64_condition(cond: Cond) -> &'static str {
+    match cond {
+        Cond::Eq => "eq",
+        Cond::Ne => "ne",
+        Cond::Lt => "lt",
+        Cond::Ge => "ge",
+        Cond::Gt => "gt",
+        Cond::Le => "le",
+        Cond::Ltu => "lo",
+        Cond::Geu => "hs",
+        Cond::Gtu => "hi",
+        Cond::Leu => "ls",
+    }
+}
+
+/// Whether add, sub and cmp take the constant as immediate:
+/// 12 bits, optionally shifted left by 12
+fn is_arith_immediate(value: u32) -> bool {
+    value <= 0xfff || (value & 0xfff == 0 && value <= 0xfff000)
+}
+
+/// Whether and, orr and tst take the constant as immediate: a
+/// rotated run of ones, repeated in elements of 2 to 32 bits
+fn is_logical_immediate(value: u32) -> bool {
+    if value == 0 || value == !0 {
+        return false;
+    }
+
+    let mut size = 32;
+
+    // Smallest element the value repeats
+    while size > 2 {
+        let half = size / 2;
+        let mask = (1u32 << half) - 1;
+
+        if value & mask != (value >> half) & mask {
+            break;
+        }
+
+        size = half;
+    }
+
+    let mask = if size == 32 { !0 } else { (1u32 << size) - 1 };
+    let element = value & mask;
+    // Rotate the run so that it doesn't wrap around
+    let rotated = (0 .. size)
+        .map(|k| if k == 0 { element } else { ((element >> k) | (element << (size - k))) & mask })
+        .find(|e| e & 1 == 1 && e & (1 << (size - 1)) == 0);
+
+    match rotated {
+        Some(e) => (e + 1) & e == 0,
+        None => false,
+    }
+}
+
+impl Translator {
+    /// 32-bit view of an IR register
+    fn w(&self, r: Reg) -> String {
+        format!("w{:}", reg_number(r))
+    }
+
+    /// 64-bit view of an IR register
+    fn x(&self, r: Reg) -> String {
+        format!("x{:}", reg_number(r))
+    }
+
+    /// Register holding an operand, constants are loaded to `scratch` first
+    fn load_operand(&self, value: Value, scratch: &str, lines: &mut Vec<String>) -> String {
+        match value {
+            Value::Reg(r) => self.w(r),
+            Value::Imm(i) => {
+                lines.push(self.emit_mov_imm(scratch, i));
+                scratch.to_string()
+            }
+        }
+    }
+
+    /// Emit constant load, constants movz can't hold
+    /// are loaded from the literal pool
+    fn emit_mov_imm(&self, dst: &str, value: i32) -> String {
+        if (0 ..= 0xffff).contains(&value) {
+            format!("mov {:}, #{:}", dst, value)
+        } else {
+            format!("ldr {:}, =0x{:x}", dst, value as u32)
+        }
+    }
+
+    /// Emit add of a signed constant. Constants the 12-bit
+    /// immediate field can't hold are added in two parts,
+    /// or loaded first if they don't fit 24 bits
+    fn emit_add_imm(&self, dst: &str, src: &str, value: i32) -> String {
+        let (opcode, magnitude) = if value < 0 { ("sub", value.wrapping_neg() as u32) } else { ("add", value as u32) };
+
+        if is_arith_immediate(magnitude) {
+            return format!("{:} {:}, {:}, #0x{:x}", opcode, dst, src, magnitude);
+        }
+
+        if magnitude > 0xffffff {
+            return format!("ldr {:}, =0x{:x}\n\t{:} {:}, {:}, {:}", SCRATCH[0], magnitude, opcode, dst, src, SCRATCH[0]);
+        }
+
+        format!("{:} {:}, {:}, #0x{:x}\n\t{:} {:}, {:}, #0x{:x}",
+            opcode, dst, src, magnitude & 0xfff000, opcode, dst, dst, magnitude & 0xfff)
+    }
+
+    fn emit_mov(&self, dst: Reg, src: Reg) -> String {
+        format!("mov {:}, {:}", self.w(dst), self.w(src))
+    }
+
+    fn emit_binary(&self, op: BinOp, dst: Reg, a: Value, b: Value) -> String {
+        let opcode = match op {
+            BinOp::Add => "add",
+            BinOp::Sub => "sub",
+            BinOp::Mul => "mul",
+            BinOp::And => "and",
+            BinOp::Or => "orr",
+            BinOp::Xor => "eor",
+            BinOp::Shl => "lsl",
+            BinOp::Lsr => "lsr",
+            BinOp::Asr => "asr",
+        };
+
+        let r1 = self.w(dst);
+
+        match (op, a, b) {
+            (BinOp::Shl, _, Value::Reg(amount)) |
+            (BinOp::Lsr, _, Value::Reg(amount)) |
+            (BinOp::Asr, _, Value::Reg(amount)) => self.emit_shift_reg(op, dst, a, amount),
+            (BinOp::Add, Value::Reg(r), Value::Imm(i)) => self.emit_add_imm(&r1, &self.w(r), i),
+            (BinOp::Sub, Value::Reg(r), Value::Imm(i)) => self.emit_add_imm(&r1, &self.w(r), i.wrapping_neg()),
+            // Immediate can only be the second operand
+            (BinOp::Sub, Value::Imm(0), Value::Reg(r)) => format!("neg {:}, {:}", r1, self.w(r)),
+            (BinOp::Sub, Value::Imm(i), Value::Reg(r)) =>
+                format!("neg {:}, {:}\n\t{:}", r1, self.w(r), self.emit_add_imm(&r1, &r1, i)),
+            (BinOp::And, Value::Reg(r), Value::Imm(i)) |
+            (BinOp::Or, Value::Reg(r), Value::Imm(i)) |
+            (BinOp::Xor, Value::Reg(r), Value::Imm(i)) if is_logical_immediate(i as u32) => match i {
+                0 ..= 0xff => format!("{:} {:}, {:}, #{:}", opcode, r1, self.w(r), i),
+                _ => format!("{:} {:}, {:}, #0x{:x}", opcode, r1, self.w(r), i as u32),
+            },
+            // Shifts by 32 or more leave nothing or the sign
+            (BinOp::Shl, Value::Reg(_), Value::Imm(k)) |
+            (BinOp::Lsr, Value::Reg(_), Value::Imm(k)) if k >= 32 => format!("mov {:}, wzr", r1),
+            (BinOp::Asr, Value::Reg(r), Value::Imm(k)) if k >= 32 => format!("asr {:}, {:}, #31", r1, self.w(r)),
+            (BinOp::Shl, Value::Reg(r), Value::Imm(k)) |
+            (BinOp::Lsr, Value::Reg(r), Value::Imm(k)) |
+            (BinOp::Asr, Value::Reg(r), Value::Imm(k)) => format!("{:} {:}, {:}, #{:}", opcode, r1, self.w(r), k),
+            _ => {
+                let mut lines = Vec::<String>::new();
+                let r2 = self.load_operand(a, SCRATCH[0], &mut lines);
+                let r3 = self.load_operand(b, SCRATCH[1], &mut lines);
+
+                lines.push(format!("{:} {:}, {:}, {:}", opcode, r1, r2, r3));
+                lines.join("\n\t")
+            }
+        }
+    }
+
+    /// Emit shift by a register. The shift is done in 64 bits
+    /// where shifting by 32 is still meaningful
+    fn emit_shift_reg(&self, op: BinOp, dst: Reg, a: Value, amount: Reg) -> String {
+        let mut lines = Vec::<String>::new();
+        let r2 = self.load_operand(a, SCRATCH[0], &mut lines);
+        let (t, xt) = (SCRATCH[0], "x16");
+        let amount = self.x(amount);
+
+        match op {
+            // Bits above the low word don't reach it
+            BinOp::Shl => {
+                let x2 = match a {
+                    Value::Reg(r) => self.x(r),
+                    Value::Imm(_) => xt.to_string(),
+                };
+
+                lines.push(format!("lsl {:}, {:}, {:}", xt, x2, amount));
+            }
+            BinOp::Lsr => {
+                lines.push(format!("mov {:}, {:}", t, r2));
+                lines.push(format!("lsr {:}, {:}, {:}", xt, xt, amount));
+            }
+            BinOp::Asr => {
+                lines.push(format!("sxtw {:}, {:}", xt, r2));
+                lines.push(format!("asr {:}, {:}, {:}", xt, xt, amount));
+            }
+            _ => panic!()
+        }
+
+        lines.push(format!("mov {:}, {:}", self.w(dst), t));
+        lines.join("\n\t")
+    }
+
+    /// Emit host address of xtensa address `base` to x17
+    fn emit_address(&self, base: Reg) -> String {
+        format!("add x17, {:}, {:}, uxtw", MEM_BASE, self.w(base))
+    }
+
+    /// Emit opcodes: load/store operations. Every xtensa offset
+    /// fits the scaled immediate field. Ordered loads get a
+    /// barrier after and ordered stores before the access
+    fn emit_load_store(&self, op: &Op) -> String {
+        let (r1, base, offset, width, signed, ordered, store) = match *op {
+            Op::Load { dst, base, offset, width, signed, ordered } => (dst, base, offset, width, signed, ordered, false),
+            Op::Store { src, base, offset, width, ordered } => (src, base, offset, width, false, ordered, true),
+            _ => panic!()
+        };
+
+        let opcode = match (width, signed, store) {
+            (Width::Word, _, false) => "ldr",
+            (Width::Half, false, false) => "ldrh",
+            (Width::Half, true, false) => "ldrsh",
+            (Width::Byte, false, false) => "ldrb",
+            (Width::Byte, true, false) => "ldrsb",
+            (Width::Word, _, true) => "str",
+            (Width::Half, _, true) => "strh",
+            (Width::Byte, _, true) => "strb",
+        };
+
+        let access = format!("{:}\n\t{:} {:}, [x17, #0x{:x}]", self.emit_address(base), opcode, self.w(r1), offset);
+
+        match (ordered, store) {
+            (true, false) => format!("{:}\n\tdmb ish", access),
+            (true, true) => format!("dmb ish\n\t{:}", access),
+            _ => access
+        }
+    }
+
+    /// Emit compare and swap. Translated code is assumed to
+    /// run on a single core. The old value is always stored
+    /// back if the comparison fails
+    fn emit_compare_swap(&self, reg: Reg, base: Reg, offset: u32, compare: Reg) -> String {
+        let r1 = self.w(reg);
+        let compare = self.w(compare);
+        let old = SCRATCH[0];
+
+        // x17 holds the address, the value stored goes to reg before the old value does
+        format!("{:}\n\tldr {:}, [x17, #0x{:x}]\n\tcmp {:}, {:}\n\tcsel {:}, {:}, {:}, eq\n\t\
+                 str {:}, [x17, #0x{:x}]\n\tmov {:}, {:}",
+            self.emit_address(base), old, offset, old, compare, r1, r1, old, r1, offset, r1, old)
+    }
+
+    /// Emit flags setup. B4CONST and B4CONSTU values fit the
+    /// immediate field, 32768 and 65536 as shifted ones.
+    /// Negative constants are compared with cmn
+    fn emit_compare(&self, kind: CompareKind, a: Reg, b: Value) -> String {
+        match (kind, b) {
+            (CompareKind::Cmp, Value::Imm(i)) if i < 0 && is_arith_immediate(i.wrapping_neg() as u32) =>
+                format!("cmn {:}, #{:}", self.w(a), i.wrapping_neg()),
+            (CompareKind::Cmp, Value::Imm(i)) if is_arith_immediate(i as u32) => format!("cmp {:}, #{:}", self.w(a), i),
+            (CompareKind::Test, Value::Imm(i)) if is_logical_immediate(i as u32) =>
+                format!("tst {:}, #0x{:x}", self.w(a), i as u32),
+            _ => {
+                let mut lines = Vec::<String>::new();
+                let r1 = self.w(a);
+                let r2 = self.load_operand(b, SCRATCH[0], &mut lines);
+
+                lines.push(match kind {
+                    CompareKind::Cmp => format!("cmp {:}, {:}", r1, r2),
+                    CompareKind::Test => format!("tst {:}, {:}", r1, r2),
+                    CompareKind::TestClear => format!("bics wzr, {:}, {:}", r1, r2),
+                });
+                lines.join("\n\t")
+            }
+        }
+    }
+
+    /// Emit compare and branch as one cbz, cbnz, tbz or tbnz,
+    /// None if there is none for the condition. The sign is
+    /// tested as bit 31
+    fn emit_test_branch(&self, kind: CompareKind, a: Reg, b: Value, cond: Cond, target: u32) -> Option<String> {
+        let r1 = self.w(a);
+        let op = match (kind, b, cond) {
+            (CompareKind::Cmp, Value::Imm(0), Cond::Eq) => format!("cbz {:}", r1),
+            (CompareKind::Cmp, Value::Imm(0), Cond::Ne) => format!("cbnz {:}", r1),
+            (CompareKind::Cmp, Value::Imm(0), Cond::Lt) => format!("tbnz {:}, #31", r1),
+            (CompareKind::Cmp, Value::Imm(0), Cond::Ge) => format!("tbz {:}, #31", r1),
+            (CompareKind::Test, Value::Imm(i), Cond::Eq) |
+            (CompareKind::Test, Value::Imm(i), Cond::Ne) if (i as u32).is_power_of_two() => {
+                let opcode = if cond == Cond::Eq { "tbz" } else { "tbnz" };
+
+                format!("{:} {:}, #{:}", opcode, r1, i.trailing_zeros())
+            }
+            _ => return None
+        };
+
+        Some(format!("{:}, loc_{:x}", op, target))
+    }
+
+    /// Emit call0/callx0. The return address goes to
+    /// x30, a0 isn't written. Direct calls add the
+    /// callee to referenced objects
+    fn emit_call(&mut self, target: &CallTarget) -> String {
+        match *target {
+            CallTarget::Function { ref name, address } => {
+                self.referenced_objects.insert(address);
+                format!("bl {:}", name)
+            }
+            CallTarget::Reg(r) => format!("blr {:}", self.x(r)),
+        }
+    }
+
+    /// Emit funnel shift: low word of {high, low} >> amount
+    fn emit_funnel_shift(&self, dst: Reg, high: Reg, low: Reg, amount: Value) -> String {
+        let r1 = self.w(dst);
+        let t = SCRATCH[0];
+
+        match amount {
+            Value::Imm(32) => format!("mov {:}, {:}", r1, self.w(high)),
+            Value::Imm(k) => format!("extr {:}, {:}, {:}, #{:}", r1, self.w(high), self.w(low), k),
+            Value::Reg(sar) => format!("mov {:}, {:}\n\torr x16, x16, {:}, lsl #32\n\tlsr x16, x16, {:}\n\tmov {:}, {:}",
+                t, self.w(low), self.x(high), self.x(sar), r1, t),
+        }
+    }
+
+    /// Emit signed saturation to `bits` bits,
+    /// as min and max with the bounds
+    fn emit_saturate(&self, dst: Reg, src: Reg, bits: u8) -> String {
+        let r1 = self.w(dst);
+        let r2 = self.w(src);
+        let (bound, t) = (SCRATCH[0], SCRATCH[1]);
+
+        format!("mov {:}, #0x{:x}\n\tcmp {:}, {:}\n\tcsel {:}, {:}, {:}, gt\n\t\
+                 mvn {:}, {:}\n\tcmp {:}, {:}\n\tcsel {:}, {:}, {:}, lt",
+            bound, (1u32 << (bits - 1)) - 1, r2, bound, t, bound, r2, bound, bound, t, bound, r1, bound, t)
+    }
+
+    /// Emit 16x16 bit multiply
+    fn emit_mul16(&self, dst: Reg, a: Reg, b: Reg, signed: bool) -> String {
+        let opcode = if signed { "sxth" } else { "uxth" };
+        let (t, u) = (SCRATCH[0], SCRATCH[1]);
+
+        format!("{:} {:}, {:}\n\t{:} {:}, {:}\n\tmul {:}, {:}, {:}",
+            opcode, t, self.w(a), opcode, u, self.w(b), self.w(dst), t, u)
+    }
+
+    /// Emit high word of 32x32 bit multiply. It's shifted down
+    /// logically, so the upper half of the destination is
+    /// cleared as with any 32-bit write
+    fn emit_mul_high(&self, dst: Reg, a: Reg, b: Reg, signed: bool) -> String {
+        let opcode = if signed { "smull" } else { "umull" };
+        let x1 = self.x(dst);
+
+        format!("{:} {:}, {:}, {:}\n\tlsr {:}, {:}, #32", opcode, x1, self.w(a), self.w(b), x1, x1)
+    }
+
+    /// Emit division or remainder. Division by zero gives 0
+    /// instead of raising an exception
+    fn emit_div(&self, dst: Reg, a: Reg, b: Reg, signed: bool, remainder: bool) -> String {
+        let divide = if signed { "sdiv" } else { "udiv" };
+        let r1 = self.w(dst);
+        let r2 = self.w(a);
+        let r3 = self.w(b);
+
+        if !remainder {
+            return format!("{:} {:}, {:}, {:}", divide, r1, r2, r3);
+        }
+
+        let t = SCRATCH[0];
+        format!("{:} {:}, {:}, {:}\n\tmsub {:}, {:}, {:}, {:}", divide, t, r2, r3, r1, t, r3, r2)
+    }
+
+    /// Emit zero-overhead loop back edge. Counter 0
+    /// means 2^32 iterations, as with `loop`
+    fn emit_loop_end(&self, body: u32) -> String {
+        let counter = self.w(Reg::Xtensa(LCOUNT));
+
+        format!("subs {:}, {:}, #1\n\tb.ne loc_{:x}", counter, counter, body)
+    }
+
+    /// Emit AAPCS64 call of a runtime helper. Registers the helper
+    /// may clobber are saved, arguments held in them are read back
+    /// from the saved copies. Arguments may also be `#immediate`.
+    /// The 32-bit result goes to `result`
+    fn emit_helper_call(&self, helper: &str, args: &[&str], result: Option<&str>) -> String {
+        let slot = |r: &str| CALL_CLOBBERED.iter()
+            .position(|&c| r.starts_with('w') && c[1 ..] == r[1 ..])
+            .map(|i| 8 * i);
+        let size = 8 * CALL_CLOBBERED.len();
+        let pairs: Vec<(usize, &[&str])> = CALL_CLOBBERED.chunks(2).enumerate().map(|(i, p)| (16 * i, p)).collect();
+        let mut lines = Vec::<String>::new();
+
+        for &(offset, pair) in &pairs {
+            lines.push(match offset {
+                0 => format!("stp {:}, {:}, [sp, #-{:}]!", pair[0], pair[1], size),
+                _ => format!("stp {:}, {:}, [sp, #{:}]", pair[0], pair[1], offset),
+            });
+        }
+
+        for (i, src) in args.iter().enumerate() {
+            lines.push(match slot(src) {
+                Some(offset) => format!("ldr w{:}, [sp, #{:}]", i, offset),
+                None => format!("mov w{:}, {:}", i, src),
+            });
+        }
+
+        lines.push(format!("bl {:}", helper));
+
+        if let Some(dst) = result {
+            lines.push(match slot(dst) {
+                // Saved copy is restored as a whole, so its upper half is cleared too
+                Some(offset) => format!("mov w0, w0\n\tstr x0, [sp, #{:}]", offset),
+                None => format!("mov {:}, w0", dst),
+            });
+        }
+
+        for &(offset, pair) in pairs.iter().rev() {
+            lines.push(match offset {
+                0 => format!("ldp {:}, {:}, [sp], #{:}", pair[0], pair[1], size),
+                _ => format!("ldp {:}, {:}, [sp, #{:}]", pair[0], pair[1], offset),
+            });
+        }
+
+        lines.join("\n\t")
+    }
+
+    /// Emit access to a special register kept in a global word
+    fn emit_global(&self, op: &Op) -> String {
+        let (r1, symbol, read, write) = match *op {
+            Op::LoadGlobal { dst, ref symbol } => (dst, symbol, true, false),
+            Op::StoreGlobal { src, ref symbol } => (src, symbol, false, true),
+            Op::ExchangeGlobal { reg, ref symbol } => (reg, symbol, true, true),
+            _ => panic!()
+        };
+
+        let r1 = self.w(r1);
+        let mut lines = vec![format!("ldr x16, ={:}", symbol)];
+
+        if read {
+            lines.push(format!("ldr {:}, [x16]", SCRATCH[1]));
+        }
+
+        if write {
+            lines.push(format!("str {:}, [x16]", r1));
+        }
+
+        if read {
+            lines.push(format!("mov {:}, {:}", r1, SCRATCH[1]));
+        }
+
+        lines.join("\n\t")
+    }
+
+    /// Emit intrinsic as a runtime helper call
+    fn emit_intrinsic(&self, name: &str, dst: Option<Reg>, args: &[Value]) -> String {
+        let args: Vec<String> = args.iter()
+            .map(|&a| match a {
+                Value::Reg(r) => self.w(r),
+                Value::Imm(i) => format!("#{:}", i),
+            })
+            .collect();
+        let args: Vec<&str> = args.iter().map(|a| a.as_str()).collect();
+        let dst = dst.map(|d| self.w(d));
+
+        self.emit_helper_call(name, &args, dst.as_deref())
+    }
+
+    /// Emit prologue: saves the return address and the
+    /// previous memory base, which `ret` restores
+    fn emit_prologue(&self) -> String {
+        format!("stp {:}, x30, [sp, #-16]!\n\tldr {:}, ={:}\n\tldr {:}, [{:}]",
+            MEM_BASE, MEM_BASE, MEM_BASE_SYMBOL, MEM_BASE, MEM_BASE)
+    }
+
+    /// Emit marker for an instruction which failed to translate
+    fn emit_placeholder(&self, text: &str, error: &Error) -> String {
+        let trap = match self.placeholder_helper {
+            Some(ref helper) => format!("bl {:}", helper),
+            None => "udf #0".to_string(),
+        };
+
+        format!("// untranslated: {:} ({:})\n\t{:}", text, error.cause(), trap)
+    }
+
+    /// Emit one IR operation, empty if it needs no code
+    fn emit_op(&mut self, op: &Op) -> String {
+        match *op {
+            Op::Mov { dst, src: Value::Imm(i) } => self.emit_mov_imm(&self.w(dst), i),
+            Op::Mov { dst, src: Value::Reg(src) } => self.emit_mov(dst, src),
+            Op::Binary { op, dst, a, b } => self.emit_binary(op, dst, a, b),
+            Op::AddShifted { dst, a, shift, b } =>
+                format!("add {:}, {:}, {:}, lsl #{:}", self.w(dst), self.w(b), self.w(a), shift),
+            // cls counts redundant sign bits just like nsa
+            Op::Unary { op, dst, src } =>
+                format!("{:} {:}, {:}", if op == UnaryOp::Clz { "clz" } else { "cls" }, self.w(dst), self.w(src)),
+            Op::Extract { dst, src, shift, width, signed } =>
+                format!("{:} {:}, {:}, #{:}, #{:}", if signed { "sbfx" } else { "ubfx" }, self.w(dst), self.w(src), shift, width),
+            Op::Saturate { dst, src, bits } => self.emit_saturate(dst, src, bits),
+            Op::MulHigh { dst, a, b, signed } => self.emit_mul_high(dst, a, b, signed),
+            Op::Mul16 { dst, a, b, signed } => self.emit_mul16(dst, a, b, signed),
+            Op::Div { dst, a, b, signed, remainder } => self.emit_div(dst, a, b, signed, remainder),
+            Op::FunnelShift { dst, high, low, amount } => self.emit_funnel_shift(dst, high, low, amount),
+            Op::Load { .. } |
+            Op::Store { .. } => self.emit_load_store(op),
+            Op::CompareSwap { reg, base, offset, compare } => self.emit_compare_swap(reg, base, offset, compare),
+            Op::LoadGlobal { .. } |
+            Op::StoreGlobal { .. } |
+            Op::ExchangeGlobal { .. } => self.emit_global(op),
+            Op::Compare { kind, a, b } => self.emit_compare(kind, a, b),
+            Op::Select { cond, dst, src } =>
+                format!("csel {:}, {:}, {:}, {:}", self.w(dst), self.w(src), self.w(dst), a64_condition(cond)),
+            Op::Branch { cond: Some(cond), target } => format!("b.{:} loc_{:x}", a64_condition(cond), target),
+            Op::Branch { cond: None, target } => format!("b loc_{:x}", target),
+            Op::LoopBack { target } => self.emit_loop_end(target),
+            Op::Call { ref target, .. } => self.emit_call(target),
+            Op::Intrinsic { ref name, dst, ref args } => self.emit_intrinsic(name, dst, args),
+            Op::Return { windowed: false } => format!("ldp {:}, x30, [sp], #16\n\tret", MEM_BASE),
+            // The lifter rejects windowed code under call0
+            Op::Entry { .. } |
+            Op::Return { windowed: true } => panic!("Windowed operation in call0 code: {:}", op),
+            Op::Barrier => String::new(),
+            Op::Nop => "nop".to_string(),
+        }
+    }
+
+    /// Emit IR operations of one instruction. A compare read only
+    /// by the branch following it becomes part of the branch, two
+    /// selects of one destination on inverse conditions one csel.
+    /// `flags_live` tells whether later instructions read the flags
+    fn emit_ops(&mut self, ops: &[Op], flags_live: bool) -> Vec<String> {
+        let mut lines = Vec::<String>::new();
+        let mut n = 0;
+
+        while n < ops.len() {
+            let fused = match (&ops[n], ops.get(n + 1)) {
+                (&Op::Compare { kind, a, b }, Some(&Op::Branch { cond: Some(cond), target }))
+                        if flag_readers(ops, n, flags_live) == Some(vec![n + 1]) =>
+                    self.emit_test_branch(kind, a, b, cond, target),
+                (&Op::Select { cond, dst, src }, Some(&Op::Select { cond: other, dst: other_dst, src: other_src }))
+                        if other_dst == dst && other == cond.inverse() =>
+                    Some(format!("csel {:}, {:}, {:}, {:}", self.w(dst), self.w(src), self.w(other_src), a64_condition(cond))),
+                _ => None,
+            };
+
+            let line = match fused {
+                Some(line) => {
+                    n += 1;
+                    line
+                }
+                None => self.emit_op(&ops[n]),
+            };
+
+            if !line.is_empty() {
+                lines.push(line);
+            }
+
+            n += 1;
+        }
+
+        lines
+    }
+
+    pub fn new() -> Translator {
+        Default::default()
+    }
+
+    pub fn with_abi(abi: XtensaAbi) -> Translator {
+        Translator { abi, ..Default::default() }
+    }
+
+    /// Lifts a call0 function to IR and emits A64 assembly.
+    /// xtensa registers live in fixed `w` registers, the
+    /// prologue only sets up the memory base
+    pub fn translate(&mut self, function: &mut Function, objects: &mut ObjectStorage) -> Result<Function> {
+        let mut result = Function::new();
+
+        if self.abi != XtensaAbi::Call0 {
+            return Err(Error::Abi("aarch64 output supports call0 ABI only".to_string()));
+        }
+
+        self.referenced_objects.clear();
+
+        let mut lifted = Lifter::new(self.abi, self.special_regs.clone())
+            .lift_function(function, objects, self.lenient)?;
+
+        // Loop instructions whose body contains a call
+        let call_loops: Vec<usize> = lifted.cfg.loops.iter()
+            .filter(|l| lifted.instructions[l.start + 1 .. l.end].iter().any(|i| i.is_call()))
+            .map(|l| l.start)
+            .collect();
+
+        for n in call_loops {
+            let instruction = &function.instructions[n];
+            let e = Error::Unsupported("call inside zero-overhead loop".to_string())
+                .at(&function.name, instruction.offset, &instruction.opcode);
+
+            lifted.reject(n, e, self.lenient)?;
+        }
+
+        if self.optimize {
+            optimize(&mut lifted.code, &lifted.cfg);
+        }
+
+        let flags_live = flags_live_out(&lifted.code, &lifted.cfg);
+
+        for (n, instruction) in function.instructions.iter().enumerate() {
+            let mut result_instruction = Instruction::new();
+
+            let lines = match lifted.code[n] {
+                Some(ref ops) => self.emit_ops(ops, flags_live[n]),
+                None => {
+                    let e = lifted.errors[&n].clone();
+                    let mut lines = vec![self.emit_placeholder(&instruction.opcode, &e)];
+
+                    self.placeholders.push(e);
+                    lines.extend(self.emit_ops(&lifted.loop_end_after(n), flags_live[n]));
+
+                    lines
+                }
+            };
+
+            result_instruction.opcode = lines.join("\n\t");
+            result_instruction.offset = instruction.offset;
+            result_instruction.referenced = lifted.cfg.is_branch_target(instruction.offset);
+            result_instruction.arch = InstructionArch::Aarch64;
+            result.instructions.push(result_instruction);
+        }
+
+        let mut prologue = Instruction::new();
+
+        prologue.opcode = self.emit_prologue();
+        prologue.offset = function.instructions.first().map_or(0, |i| i.offset);
+        prologue.arch = InstructionArch::Aarch64;
+        result.instructions.insert(0, prologue);
+
+        result.name = function.name.clone();
+        Ok(result)
+    }
+}
+
+impl Backend for Translator {
+    fn translate(&mut self, function: &mut Function, objects: &mut ObjectStorage) -> Result<Function> {
+        Translator::translate(self, function, objects)
+    }
+
+    fn referenced_objects(&self) -> &BTreeSet<u32> {
+        &self.referenced_objects
+    }
+
+    fn placeholders(&mut self) -> &mut Vec<Error> {
+        &mut self.placeholders
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use translation::xtensa_aarch64::Translator;
+    use translation::xtensa_abi::XtensaAbi;
+    use translation::xtensa_sr::SrMap;
+    use object_storage::ObjectStorage;
+    use translation::backend::translate_fixture;
+    use function::fixture;
+
+    const PROLOGUE: &str = "stp x28, x30, [sp, #-16]!\n\tldr x28, =xt_mem_base\n\tldr x28, [x28]";
+    const RET: &str = "ldp x28, x30, [sp], #16\n\tret";
+
+    /// Translates `fixture(code)` without the prologue
+    fn translate(code: &[&str]) -> Vec<String> {
+        let mut lines = translate_fixture(&mut Translator::new(), code);
+
+        assert_eq!(lines.remove(0), PROLOGUE);
+        lines
+    }
+
+    #[test]
+    fn test_aarch64_basic_1() {
+        let code = [
+            "addi a1, a1, -32", "s32i a0, a1, 28", "mov a12, a1", "add a2, a3, a12",
+            "addmi a8, a2, 0x7f00", "l16si a3, a12, 6", "movi a4, -1", "l32i a0, a1, 28", "ret",
+        ];
+
+        // a1 and a0 are xtensa addresses and values, memory is at x28
+        assert_eq!(translate_fixture(&mut Translator::new(), &code), vec![
+            PROLOGUE,
+            "sub w23, w23, #0x20",
+            "add x17, x28, w23, uxtw\n\tstr w6, [x17, #0x1c]",
+            "mov w19, w23",
+            "add w0, w1, w19",
+            "add w9, w0, #0x7000\n\tadd w9, w9, #0xf00",
+            "add x17, x28, w19, uxtw\n\tldrsh w1, [x17, #0x6]",
+            "ldr w2, =0xffffffff",
+            "add x17, x28, w23, uxtw\n\tldr w6, [x17, #0x1c]",
+            RET,
+        ]);
+    }
+
+    #[test]
+    fn test_aarch64_branch_1() {
+        let code = [
+            "beqz a2, 0x100", "bltz a3, 0x100", "bbsi a4, 25, 0x100", "bltu a2, a3, 0x100",
+            "bnall a2, a3, 0x100", "bgeui a2, 32768, 0x100", "bnei a2, -1, 0x100",
+        ];
+
+        assert_eq!(translate(&code), vec![
+            "cbz w0, loc_100",
+            "tbnz w1, #31, loc_100",
+            "tbnz w2, #25, loc_100",
+            "cmp w0, w1\n\tb.lo loc_100",
+            "bics wzr, w1, w0\n\tb.ne loc_100",
+            "cmp w0, #32768\n\tb.hs loc_100",
+            "cmn w0, #1\n\tb.ne loc_100",
+        ]);
+    }
+
+    #[test]
+    fn test_aarch64_sar_1() {
+        assert_eq!(translate(&["ssai 8", "src a2, a3, a2", "srl a3, a3", "ssl a4", "sll a2, a2", "sra a3, a3"]), vec![
+            "mov w13, #8",
+            "extr w0, w1, w0, #8",
+            "lsr w1, w1, #8",
+            "and w13, w2, #31\n\tneg w13, w13\n\tadd w13, w13, #0x20",
+            "neg w15, w13\n\tadd w15, w15, #0x20\n\tlsl x16, x0, x15\n\tmov w0, w16",
+            "sxtw x16, w1\n\tasr x16, x16, x13\n\tmov w1, w16",
+        ]);
+    }
+
+    #[test]
+    fn test_aarch64_temp_1() {
+        assert_eq!(translate(&["bbc a2, a3, 0x100", "xsr.sar a2", "movi a3, 0x12345"]), vec![
+            "and w15, w1, #31\n\tmov w16, w0\n\tlsr x16, x16, x15\n\tmov w15, w16\n\ttbz w15, #0, loc_100",
+            "mov w15, w13\n\tand w13, w0, #63\n\tmov w0, w15",
+            "ldr w1, =0x12345",
+        ]);
+    }
+
+    #[test]
+    fn test_aarch64_ops_1() {
+        let code = [
+            "min a2, a3, a4", "moveqz a2, a3, a4", "movt a2, a3, b2", "clamps a2, a3, 7",
+            "muluh a2, a3, a4", "rems a8, a2, a3", "nsa a2, a3", "s32c1i a2, a3, 4",
+        ];
+
+        assert_eq!(translate(&code), vec![
+            "cmp w1, w2\n\tcsel w0, w1, w2, lt",
+            "cmp w2, #0\n\tcsel w0, w1, w0, eq",
+            "tst w7, #0x4\n\tcsel w0, w1, w0, ne",
+            "mov w16, #0x7f\n\tcmp w1, w16\n\tcsel w17, w16, w1, gt\n\t\
+                mvn w16, w16\n\tcmp w17, w16\n\tcsel w0, w16, w17, lt",
+            "umull x0, w1, w2\n\tlsr x0, x0, #32",
+            "sdiv w16, w0, w1\n\tmsub w9, w16, w1, w0",
+            "cls w0, w1",
+            "add x17, x28, w1, uxtw\n\tldr w16, [x17, #0x4]\n\tcmp w16, w14\n\t\
+                csel w0, w0, w16, eq\n\tstr w0, [x17, #0x4]\n\tmov w0, w16",
+        ]);
+    }
+
+    #[test]
+    fn test_aarch64_loop_1() {
+        let code = ["movi a2, 0", "loopgtz a3, 0x10c", "l8ui a8, a4, 0", "add a2, a2, a8", "ret"];
+        let mut f = fixture(&code);
+        let mut objects = ObjectStorage::default();
+        let result = Translator::new().translate(&mut f, &mut objects).unwrap();
+
+        assert_eq!(result.instructions[2].opcode, "mov w8, w1\n\tcmp w8, #0\n\tb.le loc_10c");
+        assert_eq!(result.instructions[4].opcode, "add w0, w0, w9\n\tsubs w8, w8, #1\n\tb.ne loc_106");
+        assert!(result.instructions[3].referenced);
+
+        let mut f = fixture(&["loop a3, 0x109", "callx0 a8", "nop", "ret"]);
+        assert!(Translator::new().translate(&mut f, &mut objects).is_err());
+    }
+
+    #[test]
+    fn test_aarch64_special_reg_1() {
+        let mut f = fixture(&["rsr.ps a8", "xsr.excsave1 a2", "wsr.sar a3", "ret"]);
+        let mut objects = ObjectStorage::default();
+        let mut translator = Translator::new();
+
+        translator.special_regs = SrMap::parse("excsave1 slot XT_EXCSAVE1").unwrap();
+
+        let result = translator.translate(&mut f, &mut objects).unwrap();
+
+        // x30 is saved by the prologue
+        assert_eq!(result.instructions[1].opcode, "stp x0, x1, [sp, #-128]!\n\t\
+            stp x2, x3, [sp, #16]\n\tstp x4, x5, [sp, #32]\n\tstp x6, x7, [sp, #48]\n\t\
+            stp x8, x9, [sp, #64]\n\tstp x10, x11, [sp, #80]\n\tstp x12, x13, [sp, #96]\n\t\
+            stp x14, x15, [sp, #112]\n\tbl __xt_rsr_ps\n\tmov w0, w0\n\tstr x0, [sp, #72]\n\t\
+            ldp x14, x15, [sp, #112]\n\tldp x12, x13, [sp, #96]\n\tldp x10, x11, [sp, #80]\n\t\
+            ldp x8, x9, [sp, #64]\n\tldp x6, x7, [sp, #48]\n\tldp x4, x5, [sp, #32]\n\t\
+            ldp x2, x3, [sp, #16]\n\tldp x0, x1, [sp], #128");
+        assert_eq!(result.instructions[2].opcode,
+            "ldr x16, =XT_EXCSAVE1\n\tldr w17, [x16]\n\tstr w0, [x16]\n\tmov w0, w17");
+        assert_eq!(result.instructions[3].opcode, "and w13, w1, #63");
+
+        let mut f = fixture(&["entry a1, 32", "retw"]);
+        assert!(translator.translate(&mut f, &mut objects).is_err());
+        assert!(Translator::with_abi(XtensaAbi::Windowed).translate(&mut f, &mut objects).is_err());
+    }
+
+    #[test]
+    fn test_aarch64_optimize_1() {
+        let mut f = fixture(&["movi a8, 0x1234", "add a2, a2, a8", "mov a9, a2", "beqz a9, 0x112", "blt a2, a3, 0x112", "bge a2, a3, 0x112", "ret"]);
+        let mut objects = ObjectStorage::default();
+        let mut translator = Translator::new();
+
+        translator.optimize = true;
+        let result: Vec<String> = translator.translate(&mut f, &mut objects).unwrap()
+            .instructions.into_iter()
+            .map(|i| i.opcode)
+            .collect();
+
+        assert_eq!(result, vec![PROLOGUE, "", "add w0, w0, #0x1000\n\tadd w0, w0, #0x234", "", "cbz w0, loc_112",
+                                "cmp w0, w1\n\tb.lt loc_112", "b.ge loc_112", RET]);
+    }
+}
@@ -0,0 +1,56 @@
+use std::str::FromStr;
+
+/// xtensa calling convention used by the input
+#[derive(Clone, Copy, PartialEq, Debug, Default)]
+pub enum XtensaAbi {
+    /// Fixed register file, return address in a0
+    #[default]
+    Call0,
+    /// Register windows rotated by callN/entry/retw
+    Windowed,
+}
+
+impl XtensaAbi {
+    /// Detects ABI from the contents of the `.xtensa.info`
+    /// section emitted by the xtensa toolchain.
+    /// `ABI=0` stands for windowed, `ABI=2` for call0
+    pub fn from_info(info: &str) -> Option<XtensaAbi> {
+        for token in info.split(|c: char| c.is_whitespace() || c == '\0') {
+            match token {
+                "ABI=0" => return Some(XtensaAbi::Windowed),
+                "ABI=2" => return Some(XtensaAbi::Call0),
+                _ => {}
+            }
+        }
+
+        None
+    }
+}
+
+impl FromStr for XtensaAbi {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<XtensaAbi, String> {
+        match s {
+            "call0" => Ok(XtensaAbi::Call0),
+            "windowed" => Ok(XtensaAbi::Windowed),
+            _ => Err(format!("Unknown ABI: {:}", s)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use translation::xtensa_abi::XtensaAbi;
+
+    #[test]
+    fn test_abi_info_1() {
+        let info = "USE_ABSOLUTE_LITERALS=0\nABI=0\n";
+        assert_eq!(XtensaAbi::from_info(info), Some(XtensaAbi::Windowed));
+
+        let info = "USE_ABSOLUTE_LITERALS=0\nABI=2\n\0";
+        assert_eq!(XtensaAbi::from_info(info), Some(XtensaAbi::Call0));
+
+        assert_eq!(XtensaAbi::from_info(""), None);
+    }
+}
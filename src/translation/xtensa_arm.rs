@@ -1,261 +1,1501 @@
 use std::vec::Vec;
-use std::collections::BTreeSet;
+use std::collections::{BTreeSet, BTreeMap};
 
-use object_storage::{Object, ObjectKind, ObjectStorage};
-use assembly::{Instruction, InstructionKind, InstructionArch, ParseInstruction, Operand};
-use translation::xtensa_op::{XtensaOpcode, XtensaInstruction};
-use translation::xtensa_operand::XtensaOperand;
+use object_storage::ObjectStorage;
+use assembly::{Instruction, InstructionArch};
+use translation::xtensa_op::{XtensaInstruction, LCOUNT};
+use translation::xtensa_sr::SrMap;
+use translation::xtensa_abi::XtensaAbi;
+use translation::xtensa_lift::Lifter;
+use translation::optimize::optimize;
+use translation::ir::{Op, Reg, Value, BinOp, UnaryOp, Width, CompareKind, Cond, CallTarget};
+use translation::arm_cpu::{ArmCpu, InstructionSet};
+use translation::aapcs::{Call0Frame, stack_arg_offset, CALL_CLOBBERED};
+use translation::regalloc::{RegisterMap, Location};
+use translation::backend::Backend;
 use function::Function;
-use r2pipe::R2Pipe;
+use error::{Error, Result};
 
 #[derive(Default)]
 pub struct Translator {
+    /// Addresses of functions called from
+    /// the last translated function
     pub referenced_objects: BTreeSet<u32>,
     pub functions: Vec<Function>,
+    pub abi: XtensaAbi,
+    /// Architecture of the output
+    pub cpu: ArmCpu,
+    /// Instruction set of the output
+    pub isa: InstructionSet,
+    /// How special and user registers of the target are accessed
+    pub special_regs: SrMap,
+    /// Replace untranslatable instructions with
+    /// placeholders instead of failing
+    pub lenient: bool,
+    /// Function placeholders call, `udf` is used if not set
+    pub placeholder_helper: Option<String>,
+    /// Run the IR optimization passes over each function
+    pub optimize: bool,
+    /// Errors replaced by placeholders so far
+    pub placeholders: Vec<Error>,
+    /// Stack frame size allocated by `entry`
+    /// in the current function
+    frame_size: u32,
+    /// AAPCS adaptation of the current call0 function
+    frame: Call0Frame,
+    /// Stack argument registers written since the last call
+    pending_args: BTreeSet<u8>,
+    /// Register allocation of the current function
+    registers: RegisterMap,
+    /// Scratch registers holding spilled operands
+    /// of the current instruction
+    bound: BTreeMap<u8, String>,
+    /// ARM registers holding temporaries
+    /// of the current instruction
+    temps: BTreeMap<u8, String>,
 }
 
-macro_rules! branch {
-    ($x:expr, $y:ident) => ({
-        let (op_str, ref_addr) = $x;
-        $y.insert(ref_addr);
-        op_str
-    })
+/// Condition codes emitted by the translator, with their inverses
+const CONDITIONS: [(&str, &str); 7] = [
+    ("eq", "ne"), ("hs", "lo"), ("mi", "pl"), ("vs", "vc"),
+    ("hi", "ls"), ("ge", "lt"), ("gt", "le"),
+];
+
+/// Mnemonics the translator emits with a condition suffix
+const CONDITIONAL: [&str; 17] = [
+    "ldrh", "ldrb", "strh", "strb", "mov", "mvn", "add", "sub",
+    "rsb", "and", "orr", "eor", "lsl", "lsr", "asr", "ldr", "str",
+];
+
+/// ARM condition code of an IR condition
+fn arm_condition(cond: Cond) -> &'static str {
+    match cond {
+        Cond::Eq => "eq",
+        Cond::Ne => "ne",
+        Cond::Lt => "lt",
+        Cond::Ge => "ge",
+        Cond::Gt => "gt",
+        Cond::Le => "le",
+        Cond::Ltu => "lo",
+        Cond::Geu => "hs",
+        Cond::Gtu => "hi",
+        Cond::Leu => "ls",
+    }
+}
+
+fn inverse_condition(condition: &str) -> Option<&'static str> {
+    CONDITIONS.iter()
+        .filter_map(|&(a, b)| if a == condition { Some(b) } else if b == condition { Some(a) } else { None })
+        .next()
+}
+
+/// Returns condition of a conditionally executed instruction.
+/// Conditional branches don't count, they need no IT block
+fn condition_of(line: &str) -> Option<&str> {
+    let mnemonic = line.split(' ').next().unwrap_or("");
+
+    CONDITIONAL.iter()
+        .filter(|base| mnemonic.starts_with(*base))
+        .map(|base| &mnemonic[base.len() ..])
+        .find(|suffix| inverse_condition(suffix).is_some())
+}
+
+/// Prefixes runs of conditional instructions with IT blocks,
+/// as Thumb-2 requires. A block holds up to four instructions
+/// of one condition and its inverse
+fn insert_it_blocks(text: &str) -> String {
+    let lines: Vec<&str> = text.split("\n\t").collect();
+    let mut result = Vec::<String>::new();
+    let mut i = 0;
+
+    while i < lines.len() {
+        let first = match condition_of(lines[i]) {
+            Some(condition) => condition,
+            None => {
+                result.push(lines[i].to_string());
+                i += 1;
+                continue;
+            }
+        };
+
+        let mut mask = String::new();
+        let mut end = i + 1;
+
+        while end < lines.len() && end - i < 4 {
+            match condition_of(lines[end]) {
+                Some(c) if c == first => mask.push('t'),
+                Some(c) if Some(c) == inverse_condition(first) => mask.push('e'),
+                _ => break
+            }
+
+            end += 1;
+        }
+
+        result.push(format!("it{:} {:}", mask, first));
+        result.extend(lines[i .. end].iter().map(|l| l.to_string()));
+        i = end;
+    }
+
+    result.join("\n\t")
 }
 
 impl Translator {
     fn arm_reg(&self, xtensa_reg: u8) -> String {
-        let result = match xtensa_reg {
-            0 => "lr",
-            1 => "sp",
-            2 => "r0",
-            3 => "r1",
-            4 => "r2",
-            5 => "r3",
-            6 => "r4",
-            7 => "r5",
-            8 => "r6",
-            9 => "r7",
-            10 => "r8",
-            11 => "r9",
-            12 => "r10",
-            13 => "r11",
-            14 => "r12",
-            _ => { panic!("Unknown/unsupported xtensa register referenced: {:}", xtensa_reg); }
-        };
-        result.to_string()
+        match self.registers.location(xtensa_reg) {
+            Location::Reg(r) => r,
+            Location::Spill(_) => match self.bound.get(&xtensa_reg) {
+                Some(r) => r.clone(),
+                None => panic!("Spilled xtensa register is not loaded: {:}", xtensa_reg)
+            },
+            Location::Unused => { panic!("Unknown/unsupported xtensa register referenced: {:}", xtensa_reg); }
+        }
     }
 
-    /// Emit opcodes: 3 register operands
-    fn emit_r3(&self, instruction: &XtensaInstruction) -> String {
-        let opcode = match instruction.opcode {
-            XtensaOpcode::Add => "add",
-            XtensaOpcode::Sub => "sub",
-            XtensaOpcode::And => "and",
-            XtensaOpcode::Or => "orr",
-            _ => panic!()
-        };
+    fn frame_pointer(&self) -> String {
+        self.registers.frame_pointer.clone().unwrap()
+    }
 
-        let r1 = self.arm_reg(instruction.operands[0].get_reg());
-        let r2 = self.arm_reg(instruction.operands[1].get_reg());
-        let r3 = self.arm_reg(instruction.operands[2].get_reg());
+    /// Emits a copy of an xtensa register to an ARM register.
+    /// Returns None if the register is never used
+    fn read_reg(&self, dst: &str, xtensa_reg: u8) -> Option<String> {
+        match self.registers.location(xtensa_reg) {
+            Location::Reg(r) => Some(format!("mov {:}, {:}", dst, r)),
+            Location::Spill(slot) => Some(format!("ldr {:}, [{:}, #{:}]", dst, self.frame_pointer(), slot)),
+            Location::Unused => None,
+        }
+    }
 
-        format!("{:} {:}, {:}, {:}", opcode, r1, r2, r3)
+    /// Binds spilled operands of an instruction, given as the registers
+    /// it reads and writes, to scratch registers. Returns reload
+    /// sequence to put before the instruction and spill sequence
+    /// to put after it
+    fn bind_spilled(&mut self, uses: &[u8], defs: &[u8]) -> Result<(Vec<String>, Vec<String>)> {
+        let mut reload = Vec::<String>::new();
+        let mut spill = Vec::<String>::new();
+        let mut free = self.registers.scratch.clone().into_iter();
+
+        self.bound.clear();
+
+        for &r in uses {
+            if let Location::Spill(slot) = self.registers.location(r) {
+                if self.bound.contains_key(&r) {
+                    continue;
+                }
+
+                let reg = match free.next() {
+                    Some(reg) => reg,
+                    None => return Err(Error::Unsupported("too many spilled operands".to_string()))
+                };
+
+                reload.push(format!("ldr {:}, [{:}, #{:}]", reg, self.frame_pointer(), slot));
+                self.bound.insert(r, reg);
+            }
+        }
+
+        for &r in defs {
+            if let Location::Spill(slot) = self.registers.location(r) {
+                // Destination is written after sources are read
+                let reg = match self.bound.get(&r) {
+                    Some(reg) => reg.clone(),
+                    None => self.registers.scratch[0].clone(),
+                };
+
+                spill.push(format!("str {:}, [{:}, #{:}]", reg, self.frame_pointer(), slot));
+                self.bound.insert(r, reg);
+            }
+        }
+
+        Ok((reload, spill))
     }
 
-    /// Emit opcodes: 2 register operands, 1 immediate
-    fn emit_r2_i1(&self, instruction: &XtensaInstruction) -> String {
-        let opcode = match instruction.opcode {
-            XtensaOpcode::Slri => "lsr",
-            XtensaOpcode::Slli => "lsl",
-            XtensaOpcode::Addi => "add",
-            XtensaOpcode::Srai => "asr",
-            _ => panic!()
-        };
+    /// ARM register holding an IR register
+    fn reg(&self, r: Reg) -> String {
+        match r {
+            Reg::Xtensa(n) => self.arm_reg(n),
+            Reg::Temp(n) => match self.temps.get(&n) {
+                Some(t) => t.clone(),
+                None => panic!("Temporary is not bound: t{:}", n)
+            },
+        }
+    }
 
-        let r1 = self.arm_reg(instruction.operands[0].get_reg());
-        let r2 = self.arm_reg(instruction.operands[1].get_reg());
-        let i1 = instruction.operands[2].get_imm();
+    /// ARM register holding an IR register, if it's bound yet
+    fn bound_reg(&self, r: Reg) -> Option<String> {
+        match r {
+            Reg::Xtensa(n) => Some(self.arm_reg(n)),
+            Reg::Temp(n) => self.temps.get(&n).cloned(),
+        }
+    }
 
-        format!("{:} {:}, {:}, #{:}", opcode, r1, r2, i1)
+    /// Second operand of a data processing instruction
+    fn operand(&self, value: Value) -> String {
+        match value {
+            Value::Reg(r) => self.reg(r),
+            Value::Imm(i) => format!("#{:}", i),
+        }
     }
 
-    /// Emit opcodes: load/store operations
-    fn emit_load_store(&self, instruction: &XtensaInstruction) -> String {
-        let opcode = match instruction.opcode {
-            XtensaOpcode::L32i => "ldr",
-            XtensaOpcode::L8ui => "ldrb",
-            XtensaOpcode::S32i => "str",
-            XtensaOpcode::S8i => "strb",
-            XtensaOpcode::L16si => "ldrsh",
+    /// Binds temporaries of a lifted instruction to ARM registers.
+    /// A temporary gets a scratch register, or a register the
+    /// instruction overwrites once the temporary is dead. Registers
+    /// read later or written while the temporary is live are skipped
+    fn bind_temps(&mut self, ops: &[Op]) -> Result<()> {
+        self.temps.clear();
+
+        for (i, op) in ops.iter().enumerate() {
+            for t in op.defs() {
+                let n = match t {
+                    Reg::Temp(n) if !self.temps.contains_key(&n) => n,
+                    _ => continue
+                };
+
+                let last = (i .. ops.len()).rev().find(|&j| ops[j].uses().contains(&t)).unwrap_or(i);
+                let busy: Vec<String> = ops[i + 1 ..].iter().flat_map(|op| op.uses())
+                    .chain(ops[.. last].iter().flat_map(|op| op.defs()))
+                    .filter_map(|r| self.bound_reg(r))
+                    .chain(self.temps.values().cloned())
+                    .collect();
+                let written: Vec<String> = ops[last ..].iter().flat_map(|op| op.defs())
+                    .filter_map(|r| self.bound_reg(r))
+                    .collect();
+
+                let reg = match self.registers.scratch.iter().chain(written.iter()).find(|r| !busy.contains(r)) {
+                    Some(r) => r.clone(),
+                    None => return Err(Error::Unsupported("no scratch register left".to_string()))
+                };
+
+                self.temps.insert(n, reg);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Emit load/store operations. Offsets the ARM immediate
+    /// field can't hold are added to the base first. Ordered
+    /// loads get a barrier after and ordered stores before the access
+    fn emit_load_store(&self, op: &Op) -> Result<String> {
+        let (r1, base, offset, width, signed, ordered, store) = match *op {
+            Op::Load { dst, base, offset, width, signed, ordered } => (dst, base, offset, width, signed, ordered, false),
+            Op::Store { src, base, offset, width, ordered } => (src, base, offset, width, false, ordered, true),
             _ => panic!()
         };
 
-        let r1 = self.arm_reg(instruction.operands[0].get_reg());
-        let r2 = self.arm_reg(instruction.operands[1].get_reg());
-        let i1 = instruction.operands[2].get_imm();
+        // Opcode, largest immediate offset
+        let (opcode, max_offset) = match (width, signed, store) {
+            (Width::Word, _, false) => ("ldr", 0xfff),
+            (Width::Half, false, false) => ("ldrh", 0xff),
+            (Width::Half, true, false) => ("ldrsh", 0xff),
+            (Width::Byte, false, false) => ("ldrb", 0xfff),
+            (Width::Byte, true, false) => ("ldrsb", 0xff),
+            (Width::Word, _, true) => ("str", 0xfff),
+            (Width::Half, _, true) => ("strh", 0xff),
+            (Width::Byte, _, true) => ("strb", 0xfff),
+        };
+
+        let r1 = self.reg(r1);
+        let r2 = self.reg(base);
+        let mut lines = Vec::<String>::new();
+
+        if ordered && store {
+            lines.extend(self.emit_barrier());
+        }
+
+        if offset <= max_offset {
+            lines.push(format!("{:} {:}, [{:}, #0x{:x}]", opcode, r1, r2, offset));
+        } else {
+            // A load can compute the address in its destination
+            let base = if store { self.scratch_reg(&[&r1, &r2])? } else { r1.clone() };
+
+            lines.push(format!("add {:}, {:}, #0x{:x}", base, r2, offset & !max_offset));
+            lines.push(format!("{:} {:}, [{:}, #0x{:x}]", opcode, r1, base, offset & max_offset));
+        }
+
+        if ordered && !store {
+            lines.extend(self.emit_barrier());
+        }
+
+        Ok(lines.join("\n\t"))
+    }
 
-        format!("{:} {:}, [{:}, #0x{:x}]", opcode, r1, r2, i1)
+    /// Emit memory barrier for acquire/release ordering.
+    /// Cores without dmb are assumed to be uniprocessor
+    fn emit_barrier(&self) -> Option<String> {
+        if self.cpu.has_barrier() {
+            Some("dmb ish".to_string())
+        } else {
+            None
+        }
     }
 
-    /// Emit branch bit set/bit clear
-    fn emit_branch_bit(&self, instruction: &XtensaInstruction) -> (String, u32) {
-        let opcode = match instruction.opcode {
-            XtensaOpcode::Bbci => "beq",
-            XtensaOpcode::Bbsi => "bne",
-            _ => panic!()
+    /// Emit compare and swap. Translated code is assumed to run
+    /// on a single core, so a plain load, compare and store is enough
+    fn emit_compare_swap(&self, reg: Reg, base: Reg, offset: u32, compare: Reg) -> Result<String> {
+        let r1 = self.reg(reg);
+        let r2 = self.reg(base);
+        let compare = self.reg(compare);
+        let t = self.scratch_reg(&[&r1, &r2, &compare])?;
+
+        Ok(format!("ldr {:}, [{:}, #0x{:x}]\n\tcmp {:}, {:}\n\tstreq {:}, [{:}, #0x{:x}]\n\tmov {:}, {:}",
+            t, r2, offset, t, compare, r1, r2, offset, r1, t))
+    }
+
+    /// Emit flags setup. All B4CONST and B4CONSTU values
+    /// are valid ARM immediates, -1 is compared with cmn.
+    /// Other constants may have to be loaded first
+    fn emit_compare(&self, kind: CompareKind, a: Reg, b: Value) -> Result<String> {
+        let r1 = self.reg(a);
+        let fits = match (kind, b) {
+            (CompareKind::Cmp, Value::Imm(i)) if i < 0 => self.isa.is_immediate(i.wrapping_neg() as u32),
+            (CompareKind::Cmp, Value::Imm(i)) |
+            (CompareKind::Test, Value::Imm(i)) => self.isa.is_immediate(i as u32),
+            _ => true,
         };
 
-        let r1 = self.arm_reg(instruction.operands[0].get_reg());
-        let bit = instruction.operands[1].get_imm();
-        let jt = instruction.operands[2].get_imm() as u32;
+        Ok(match (kind, b) {
+            (_, Value::Imm(i)) if !fits => {
+                let t = self.scratch_reg(&[&r1])?;
+                let opcode = if kind == CompareKind::Cmp { "cmp" } else { "tst" };
 
-        ( format!("tst {:}, #0x{:x}\n\t{:} loc_{:x}", r1, (1 << bit), opcode, jt), jt )
+                format!("ldr {:}, =0x{:x}\n\t{:} {:}, {:}", t, i as u32, opcode, r1, t)
+            }
+            (CompareKind::Cmp, Value::Imm(i)) if i < 0 => format!("cmn {:}, #{:}", r1, -i),
+            (CompareKind::Cmp, b) => format!("cmp {:}, {:}", r1, self.operand(b)),
+            // Bit masks are printed in hex
+            (CompareKind::Test, Value::Imm(_)) => format!("tst {:}, #{:}", r1, b),
+            (CompareKind::Test, b) => format!("tst {:}, {:}", r1, self.operand(b)),
+            (CompareKind::TestClear, b) => {
+                let t = self.scratch_reg(&[])?;
+                format!("bics {:}, {:}, {:}", t, r1, self.operand(b))
+            }
+        })
     }
 
-    /// Emit PC-relative load.
-    /// Replaces load address with actual data since
-    /// the load is performed from a read-only memory.
-    fn emit_load_relative(&self, instruction: &XtensaInstruction, pipe: &mut R2Pipe) -> String {
-        let reg = self.arm_reg(instruction.operands[0].get_reg());
-        let address = instruction.operands[1].get_imm() as u32;
-        let command = format!("pxwj 32 @ 0x{:x}", address);
-        let json_array = pipe.cmdj(&command).unwrap();
-        let array = json_array.as_array().unwrap();
-        let data = array[0].as_i64().unwrap() as u32;
+    /// Picks a scratch register not holding any of the
+    /// provided operands
+    fn scratch_reg(&self, used: &[&str]) -> Result<String> {
+        match self.registers.scratch.iter().find(|r| !used.contains(&r.as_str())) {
+            Some(r) => Ok(r.clone()),
+            None => Err(Error::Unsupported("no scratch register left".to_string()))
+        }
+    }
 
-        format!("ldr {:}, =0x{:x}", reg, data)
+    /// Picks a temporary for a sequence writing `dst` last:
+    /// `dst` itself unless the sequence still reads it
+    fn temp_reg(&self, dst: &str, used: &[&str]) -> Result<String> {
+        if used.contains(&dst) {
+            self.scratch_reg(used)
+        } else {
+            Ok(dst.to_string())
+        }
     }
 
     /// Emit function return
     fn emit_ret(&self) -> String {
-        "bx lr".to_string()
+        let mut lines = Vec::<String>::new();
+
+        if self.registers.spill_size > 0 {
+            lines.push(self.emit_imm_op("add", "sp", "sp", self.registers.spill_size));
+        }
+
+        let saved = self.saved_regs();
+
+        if !saved.is_empty() {
+            lines.push(format!("pop {{{:}}}", saved.join(", ")));
+        }
+
+        lines.push("bx lr".to_string());
+        lines.join("\n\t")
+    }
+
+    /// Emit add or sub of a constant. Constants the immediate
+    /// field can't hold are applied a byte at a time
+    fn emit_imm_op(&self, opcode: &str, dst: &str, src: &str, value: u32) -> String {
+        if self.isa.is_immediate(value) {
+            return format!("{:} {:}, {:}, #{:}", opcode, dst, src, value);
+        }
+
+        let mut lines = Vec::<String>::new();
+        let mut from = src;
+
+        for shift in (0 .. 32).step_by(8) {
+            let chunk = value & (0xff << shift);
+
+            if chunk != 0 {
+                lines.push(format!("{:} {:}, {:}, #{:}", opcode, dst, from, chunk));
+                from = dst;
+            }
+        }
+
+        lines.join("\n\t")
+    }
+
+    /// Emit spill area allocation
+    fn emit_spill_area(&self, lines: &mut Vec<String>) {
+        if self.registers.spill_size > 0 {
+            lines.push(self.emit_imm_op("sub", "sp", "sp", self.registers.spill_size));
+            lines.push(format!("mov {:}, sp", self.frame_pointer()));
+        }
+    }
+
+    /// Registers saved by the call0 prologue, padded
+    /// with r12 to keep stack 8-byte aligned
+    fn saved_regs(&self) -> Vec<String> {
+        let mut saved = self.frame.saved.clone();
+
+        if saved.len() % 2 == 1 {
+            saved.push("r12".to_string());
+        }
+
+        saved
+    }
+
+    /// Emit AAPCS prologue: save callee-saved registers,
+    /// allocate spill area and load arguments passed on stack
+    fn emit_prologue(&self) -> String {
+        let mut lines = Vec::<String>::new();
+
+        // Windowed functions allocate the spill area in entry
+        if self.abi != XtensaAbi::Call0 {
+            return String::new();
+        }
+
+        let saved = self.saved_regs();
+
+        if !saved.is_empty() {
+            lines.push(format!("push {{{:}}}", saved.join(", ")));
+        }
+
+        self.emit_spill_area(&mut lines);
+
+        let pushed = 4 * saved.len() as u32 + self.registers.spill_size;
+
+        for &(r, offset) in &self.frame.stack_args {
+            lines.push(format!("ldr {:}, [sp, #{:}]", self.arm_reg(r), pushed + offset));
+        }
+
+        lines.join("\n\t")
+    }
+
+    /// Wraps a call0/callx0 call with AAPCS fixups: pushes stack
+    /// arguments
+    fn wrap_call(&mut self, call: String) -> String {
+        if self.abi != XtensaAbi::Call0 {
+            return call;
+        }
+
+        let args: Vec<String> = self.frame.call_stack_args(&self.pending_args)
+            .into_iter()
+            .map(|r| self.arm_reg(r))
+            .collect();
+
+        self.pending_args.clear();
+
+        let mut lines = Vec::<String>::new();
+
+        if !args.is_empty() {
+            lines.push(format!("push {{{:}}}", args.join(", ")));
+        }
+
+        lines.push(call);
+
+        if !args.is_empty() {
+            lines.push(format!("add sp, sp, #{:}", 4 * args.len()));
+        }
+
+        lines.join("\n\t")
+    }
+
+    /// Emit constant load, small constants are moved directly
+    fn emit_mov_imm(&self, dst: Reg, value: i32) -> String {
+        let r1 = self.reg(dst);
+
+        if (0 ..= 0xff).contains(&value) {
+            format!("mov {:}, #{:}", r1, value)
+        } else {
+            format!("ldr {:}, =0x{:x}", r1, value as u32)
+        }
+    }
+
+    /// Emit call of any ABI. Direct calls add the callee
+    /// to referenced objects
+    fn emit_call(&mut self, target: &CallTarget, rotation: u8) -> String {
+        let (call, target_reg) = match *target {
+            CallTarget::Function { ref name, address } => {
+                self.referenced_objects.insert(address);
+                (format!("bl {:}", name), None)
+            }
+            CallTarget::Reg(r) if rotation == 0 => (format!("blx {:}", self.reg(r)), None),
+            CallTarget::Reg(r) => ("blx lr".to_string(), Some(self.reg(r))),
+        };
+
+        if rotation == 0 {
+            self.wrap_call(call)
+        } else {
+            self.emit_call_windowed(rotation, call, target_reg)
+        }
+    }
+
+    /// Emit windowed function prologue.
+    /// Return address is kept on stack so that
+    /// call sites don't need to preserve lr
+    fn emit_entry(&mut self, size: u32) -> String {
+        self.frame_size = size;
+
+        // r12 keeps the stack 8-byte aligned.
+        // Spill area goes above xtensa stack frame
+        let mut lines = vec!["push {r12, lr}".to_string()];
+        self.emit_spill_area(&mut lines);
+        lines.push(self.emit_imm_op("sub", "sp", "sp", size));
+        lines.join("\n\t")
+    }
+
+    /// Emit windowed function epilogue
+    fn emit_retw(&self) -> String {
+        let size = self.frame_size + self.registers.spill_size;
+
+        format!("{:}\n\tpop {{r12, pc}}", self.emit_imm_op("add", "sp", "sp", size))
+    }
+
+    /// Emit windowed call. Window rotation by N is modelled
+    /// by saving caller's a2..a(N-1), moving outgoing
+    /// arguments a(N+2).. to callee's a2.. and moving
+    /// callee's a2 back to caller's a(N+2) on return.
+    /// Indirect calls pass the target register in `target`
+    fn emit_call_windowed(&self, rotation: u8, call: String, target: Option<String>) -> String {
+        let mut lines = Vec::<String>::new();
+        let mut saved: Vec<String> = (2 .. rotation)
+            .filter_map(|r| self.registers.reg_name(r))
+            .collect();
+
+        // Callee's spill area is addressed through the same register
+        saved.extend(self.registers.frame_pointer.clone());
+        saved.sort_by_key(|r| r[1..].parse::<u32>().unwrap());
+
+        // Keep stack 8-byte aligned
+        if saved.len() % 2 == 1 {
+            saved.push("lr".to_string());
+        }
+
+        if !saved.is_empty() {
+            lines.push(format!("push {{{:}}}", saved.join(", ")));
+        }
+
+        // Target address goes to lr before it gets clobbered by argument moves
+        if let Some(target) = target {
+            lines.push(format!("mov lr, {:}", target));
+        }
+
+        // Sources are always above destinations, so ascending order is safe
+        for i in 0 .. 6 {
+            if rotation + 2 + i > 15 {
+                break;
+            }
+
+            let callee = self.arm_reg(2 + i);
+            lines.extend(self.read_reg(&callee, rotation + 2 + i));
+        }
+
+        lines.push(call);
+
+        // Return value is kept in scratch over the pop, which restores
+        // the frame pointer a spilled destination is stored through
+        let used = self.registers.location(rotation + 2) != Location::Unused;
+        let mut result = self.arm_reg(2);
+
+        if !saved.is_empty() {
+            if used {
+                let t = self.registers.scratch[0].clone();
+
+                lines.push(format!("mov {:}, {:}", t, result));
+                result = t;
+            }
+
+            lines.push(format!("pop {{{:}}}", saved.join(", ")));
+        }
+
+        if used && self.arm_reg(rotation + 2) != result {
+            lines.push(format!("mov {:}, {:}", self.arm_reg(rotation + 2), result));
+        }
+
+        lines.join("\n\t")
+    }
+
+    /// Emit two operand data processing. Constants the
+    /// immediate field can't hold are loaded first
+    fn emit_binary(&self, op: BinOp, dst: Reg, a: Value, b: Value) -> Result<String> {
+        let opcode = match op {
+            BinOp::Add => "add",
+            BinOp::Sub => "sub",
+            BinOp::Mul => return self.emit_mul(dst, a, b),
+            BinOp::And => "and",
+            BinOp::Or => "orr",
+            BinOp::Xor => "eor",
+            BinOp::Shl => "lsl",
+            BinOp::Lsr => "lsr",
+            BinOp::Asr => "asr",
+        };
+
+        let r1 = self.reg(dst);
+        let is_immediate = |i: i32| self.isa.is_immediate(i as u32);
+        let fits = match (op, b) {
+            // Assembler swaps these for sub, add and bic
+            (BinOp::Add, Value::Imm(i)) |
+            (BinOp::Sub, Value::Imm(i)) => is_immediate(i) || is_immediate(i.wrapping_neg()),
+            (BinOp::And, Value::Imm(i)) => is_immediate(i) || is_immediate(!i),
+            (BinOp::Or, Value::Imm(i)) |
+            (BinOp::Xor, Value::Imm(i)) => is_immediate(i),
+            _ => true,
+        };
+
+        Ok(match (op, a, b) {
+            // Immediate can only be the second operand
+            (BinOp::Sub, Value::Imm(i), Value::Reg(r)) if is_immediate(i) =>
+                format!("rsb {:}, {:}, #{:}", r1, self.reg(r), i),
+            (BinOp::Sub, Value::Imm(i), Value::Reg(r)) => {
+                let r2 = self.reg(r);
+                let t = self.temp_reg(&r1, &[&r2])?;
+
+                format!("ldr {:}, =0x{:x}\n\tsub {:}, {:}, {:}", t, i as u32, r1, t, r2)
+            }
+            (_, a, Value::Imm(i)) if !fits => {
+                let r2 = self.operand(a);
+                let t = self.temp_reg(&r1, &[&r2])?;
+
+                format!("ldr {:}, =0x{:x}\n\t{:} {:}, {:}, {:}", t, i as u32, opcode, r1, r2, t)
+            }
+            _ => format!("{:} {:}, {:}, {:}", opcode, r1, self.operand(a), self.operand(b)),
+        })
     }
 
-    /// Emit memory barrier
-    fn emit_memw(&self) -> String {
-        "".to_string()
+    /// Emit 32x32 bit multiply, low word of the result.
+    /// A constant factor is loaded first
+    fn emit_mul(&self, dst: Reg, a: Value, b: Value) -> Result<String> {
+        let r1 = self.reg(dst);
+        let mut r2 = self.operand(a);
+        let mut lines = Vec::<String>::new();
+        let mut r3 = match b {
+            Value::Imm(i) => {
+                let t = self.temp_reg(&r1, &[&r2])?;
+                lines.push(format!("ldr {:}, =0x{:x}", t, i as u32));
+                t
+            }
+            Value::Reg(r) => self.reg(r),
+        };
+
+        // ARMv5 requires destination and first operand to differ
+        if r1 == r2 {
+            ::std::mem::swap(&mut r2, &mut r3);
+        }
+
+        lines.push(format!("mul {:}, {:}, {:}", r1, r2, r3));
+        Ok(lines.join("\n\t"))
+    }
+
+    /// Emit high word of 32x32 bit multiply
+    fn emit_mul_high(&self, dst: Reg, a: Reg, b: Reg, signed: bool) -> Result<String> {
+        let r1 = self.reg(dst);
+        let mut r2 = self.reg(a);
+        let mut r3 = self.reg(b);
+        let opcode = if signed { "smull" } else { "umull" };
+        let low = self.scratch_reg(&[&r1, &r2, &r3])?;
+
+        // ARMv5 requires high word and first operand to differ
+        if r1 == r2 {
+            ::std::mem::swap(&mut r2, &mut r3);
+        }
+
+        if r1 == r2 && self.cpu < ArmCpu::Armv6 {
+            let high = self.scratch_reg(&[&r1, &low])?;
+            return Ok(format!("{:} {:}, {:}, {:}, {:}\n\tmov {:}, {:}", opcode, low, high, r2, r3, r1, high));
+        }
+
+        Ok(format!("{:} {:}, {:}, {:}, {:}", opcode, low, r1, r2, r3))
     }
 
-    fn emit_mov(&self, instruction: &XtensaInstruction) -> String {
-        let r1 = self.arm_reg(instruction.operands[0].get_reg());
-        let r2 = self.arm_reg(instruction.operands[1].get_reg());
+    /// Emit funnel shift: low word of {high, low} >> amount
+    fn emit_funnel_shift(&self, dst: Reg, high: Reg, low: Reg, amount: Value) -> Result<String> {
+        let r1 = self.reg(dst);
+        let high = self.reg(high);
+        let low = self.reg(low);
 
-        format!("mov {:}, {:}", r1, r2)
+        Ok(match amount {
+            Value::Imm(0) => format!("mov {:}, {:}", r1, low),
+            Value::Imm(k) => {
+                let t = self.temp_reg(&r1, &[&high])?;
+                format!("lsr {:}, {:}, #{:}\n\torr {:}, {:}, {:}, lsl #{:}", t, low, k, r1, t, high, 32 - k)
+            }
+            // Thumb-2 has no register-shifted operands
+            Value::Reg(sar) if self.isa == InstructionSet::Thumb2 => {
+                let sar = self.reg(sar);
+                let t = self.scratch_reg(&[&r1, &high, &low, &sar])?;
+                format!("rsb {:}, {:}, #32\n\tlsl {:}, {:}, {:}\n\tlsr {:}, {:}, {:}\n\torr {:}, {:}, {:}",
+                    t, sar, t, high, t, r1, low, sar, r1, r1, t)
+            }
+            Value::Reg(sar) => {
+                // Register shifts by 32 give 0, which covers SAR = 0 and SAR = 32
+                let sar = self.reg(sar);
+                let t = self.temp_reg(&r1, &[&high, &low, &sar])?;
+                format!("rsb {:}, {:}, #32\n\tlsl {:}, {:}, {:}\n\torr {:}, {:}, {:}, lsr {:}",
+                    t, sar, t, high, t, r1, t, low, sar)
+            }
+        })
     }
 
-    fn emit_movi(&self, instruction: &XtensaInstruction) -> String {
-        let r1 = self.arm_reg(instruction.operands[0].get_reg());
-        let i1 = instruction.operands[1].get_imm();
+    /// Emit bit field extract
+    fn emit_extract(&self, dst: Reg, src: Reg, shift: u8, width: u8, signed: bool) -> String {
+        let r1 = self.reg(dst);
+        let r2 = self.reg(src);
+        let (opcode, shift_right) = if signed { ("sbfx", "asr") } else { ("ubfx", "lsr") };
 
-        format!("ldr {:}, =0x{:x}", r1, i1 as u32)
+        if shift + width == 32 {
+            format!("{:} {:}, {:}, #{:}", shift_right, r1, r2, shift)
+        } else if self.cpu.has_bitfield() {
+            format!("{:} {:}, {:}, #{:}, #{:}", opcode, r1, r2, shift, width)
+        } else if !signed && width <= 8 && shift == 0 {
+            format!("and {:}, {:}, #0x{:x}", r1, r2, (1 << width) - 1)
+        } else if !signed && width <= 8 {
+            format!("lsr {:}, {:}, #{:}\n\tand {:}, {:}, #0x{:x}", r1, r2, shift, r1, r1, (1 << width) - 1)
+        } else {
+            // Mask is not an ARM immediate
+            format!("lsl {:}, {:}, #{:}\n\t{:} {:}, {:}, #{:}",
+                r1, r2, 32 - shift - width, shift_right, r1, r1, 32 - width)
+        }
     }
 
-    fn emit_reg_call(&self, instruction: &XtensaInstruction) -> String {
-        let r1 = self.arm_reg(instruction.operands[0].get_reg());
+    /// Emit normalization shift amount: leading zeros,
+    /// or redundant sign bits
+    fn emit_unary(&self, op: UnaryOp, dst: Reg, src: Reg) -> String {
+        let r1 = self.reg(dst);
+        let r2 = self.reg(src);
 
-        format!("blx {:}", r1)
+        match op {
+            UnaryOp::Clz => format!("clz {:}, {:}", r1, r2),
+            // Negative values are inverted so that sign bits become zeros
+            UnaryOp::Cls => format!("eor {:}, {:}, {:}, asr #31\n\tclz {:}, {:}\n\tsub {:}, {:}, #1",
+                r1, r2, r2, r1, r1, r1, r1),
+        }
     }
 
-    fn emit_call(&self, instruction: &XtensaInstruction, objects: &mut ObjectStorage) -> String {
-        let jt = instruction.operands[0].get_imm() as u32;
-        let ref object = match objects.get_object(jt) {
-            Some(o) => o,
-            None => panic!("Unable to resolve call address")
+    /// Emit signed saturation to `bits` bits
+    fn emit_saturate(&self, dst: Reg, src: Reg, bits: u8) -> Result<String> {
+        let r1 = self.reg(dst);
+        let r2 = self.reg(src);
+        let bit = bits - 1;
+
+        if self.cpu.has_saturate() {
+            return Ok(format!("ssat {:}, #{:}, {:}", r1, bits, r2));
+        }
+
+        // Value is in range if bits above `bit` all equal the sign,
+        // otherwise the result is the bound with the value's sign
+        let t = self.temp_reg(&r1, &[&r2])?;
+        let mut lines = vec![
+            format!("asr {:}, {:}, #{:}", t, r2, bit),
+            format!("teq {:}, {:}, asr #31", t, r2),
+            format!("mvnne {:}, #0", t),
+            format!("lsrne {:}, {:}, #{:}", t, t, 32 - bit),
+            format!("eorne {:}, {:}, {:}, asr #31", r1, t, r2),
+        ];
+
+        if r1 != r2 {
+            lines.push(format!("moveq {:}, {:}", r1, r2));
+        }
+
+        Ok(lines.join("\n\t"))
+    }
+
+    /// Emit zero-overhead loop back edge. Counter 0
+    /// means 2^32 iterations, as with `loop`
+    fn emit_loop_end(&self, body: u32) -> String {
+        match self.registers.location(LCOUNT) {
+            Location::Reg(r) => format!("subs {:}, {:}, #1\n\tbne loc_{:x}", r, r, body),
+            Location::Spill(slot) => {
+                let t = &self.registers.scratch[0];
+                let fp = self.frame_pointer();
+
+                format!("ldr {:}, [{:}, #{:}]\n\tsubs {:}, {:}, #1\n\tstr {:}, [{:}, #{:}]\n\tbne loc_{:x}",
+                    t, fp, slot, t, t, t, fp, slot, body)
+            }
+            Location::Unused => panic!("Loop counter is not allocated")
+        }
+    }
+
+    /// Emit 16x16 bit multiply
+    fn emit_mul16(&self, dst: Reg, a: Reg, b: Reg, signed: bool) -> Result<String> {
+        let r1 = self.reg(dst);
+        let r2 = self.reg(a);
+        let r3 = self.reg(b);
+
+        if signed && self.cpu.has_dsp() {
+            return Ok(format!("smulbb {:}, {:}, {:}", r1, r2, r3));
+        }
+
+        // First operand goes to a temporary, second one to the destination
+        let t = self.scratch_reg(&[&r1, &r3])?;
+        let (opcode, shift) = if signed { ("sxth", "asr") } else { ("uxth", "lsr") };
+        let extend = |dst: &str, src: &str| if self.cpu.has_extend() {
+            format!("{:} {:}, {:}", opcode, dst, src)
+        } else {
+            format!("lsl {:}, {:}, #16\n\t{:} {:}, {:}, #16", dst, src, shift, dst, dst)
+        };
+
+        Ok(format!("{:}\n\t{:}\n\tmul {:}, {:}, {:}", extend(&t, &r2), extend(&r1, &r3), r1, t, r1))
+    }
+
+    /// Emit division or remainder. Division by zero gives 0
+    /// instead of raising an exception
+    fn emit_div(&self, dst: Reg, a: Reg, b: Reg, signed: bool, remainder: bool) -> Result<String> {
+        let r1 = self.reg(dst);
+        let r2 = self.reg(a);
+        let r3 = self.reg(b);
+
+        let (divide, helper, result) = match (signed, remainder) {
+            (false, false) => ("udiv", "__aeabi_uidiv", "r0"),
+            (true, false) => ("sdiv", "__aeabi_idiv", "r0"),
+            (false, true) => ("udiv", "__aeabi_uidivmod", "r1"),
+            (true, true) => ("sdiv", "__aeabi_idivmod", "r1"),
         };
 
-        match object.kind {
-            ObjectKind::Function => {},
-            _ => panic!("Called object is not a function")
+        if self.cpu.has_divide() {
+            return Ok(if !remainder {
+                format!("{:} {:}, {:}, {:}", divide, r1, r2, r3)
+            } else {
+                let t = self.temp_reg(&r1, &[&r2, &r3])?;
+                format!("{:} {:}, {:}, {:}\n\tmls {:}, {:}, {:}, {:}", divide, t, r2, r3, r1, t, r3, r2)
+            });
         }
 
-        format!("bl {:}", object.name)
+        Ok(self.emit_helper_call(helper, &[&r2, &r3], Some((&r1, result))))
     }
 
-    fn emit_jmp(&self, instruction: &XtensaInstruction) -> (String, u32) {
-        let jt = instruction.operands[0].get_imm() as u32;
+    /// Emit AAPCS call of a runtime helper. Registers the helper
+    /// may clobber are saved, arguments held in them are read back
+    /// from the saved copies. Arguments may also be `#immediate`.
+    /// `result` is the destination and the register returning it
+    fn emit_helper_call(&self, helper: &str, args: &[&str], result: Option<(&str, &str)>) -> String {
+        let slot = |r: &str| CALL_CLOBBERED.iter().position(|&c| c == r).map(|i| 4 * i);
+        let mut lines = vec![format!("push {{{:}}}", CALL_CLOBBERED.join(", "))];
+
+        for (i, src) in args.iter().enumerate() {
+            lines.push(match slot(src) {
+                Some(offset) => format!("ldr r{:}, [sp, #{:}]", i, offset),
+                None => format!("mov r{:}, {:}", i, src),
+            });
+        }
+
+        lines.push(format!("bl {:}", helper));
 
-        ( format!("b loc_{:x}", jt), jt )
+        if let Some((dst, ret)) = result {
+            lines.push(match slot(dst) {
+                Some(offset) => format!("str {:}, [sp, #{:}]", ret, offset),
+                None => format!("mov {:}, {:}", dst, ret),
+            });
+        }
+
+        lines.push(format!("pop {{{:}}}", CALL_CLOBBERED.join(", ")));
+        lines.join("\n\t")
     }
 
-    fn emit_addx(&self, instruction: &XtensaInstruction) -> String {
-        let shift = match instruction.opcode {
-            XtensaOpcode::Addx2 => 1,
-            XtensaOpcode::Addx4 => 2,
-            XtensaOpcode::Addx8 => 3,
+    /// Emit access to a special register kept in a global word
+    fn emit_global(&self, op: &Op) -> Result<String> {
+        Ok(match *op {
+            Op::LoadGlobal { dst, ref symbol } => {
+                let r1 = self.reg(dst);
+                format!("ldr {:}, ={:}\n\tldr {:}, [{:}]", r1, symbol, r1, r1)
+            }
+            Op::StoreGlobal { src, ref symbol } => {
+                let r1 = self.reg(src);
+                let t = self.scratch_reg(&[&r1])?;
+                format!("ldr {:}, ={:}\n\tstr {:}, [{:}]", t, symbol, r1, t)
+            }
+            Op::ExchangeGlobal { reg, ref symbol } => {
+                // Old value is kept on the stack while the
+                // only scratch register holds the address
+                let r1 = self.reg(reg);
+                let t = self.scratch_reg(&[&r1])?;
+                format!("ldr {:}, ={:}\n\tldr {:}, [{:}]\n\tpush {{{:}}}\n\t\
+                         ldr {:}, ={:}\n\tstr {:}, [{:}]\n\tpop {{{:}}}",
+                        t, symbol, t, t, t, t, symbol, r1, t, r1)
+            }
             _ => panic!()
+        })
+    }
+
+    /// Emit intrinsic as a runtime helper call, result in r0
+    fn emit_intrinsic(&self, name: &str, dst: Option<Reg>, args: &[Value]) -> String {
+        let args: Vec<String> = args.iter().map(|&a| self.operand(a)).collect();
+        let args: Vec<&str> = args.iter().map(|a| a.as_str()).collect();
+        let dst = dst.map(|d| self.reg(d));
+
+        self.emit_helper_call(name, &args, dst.as_ref().map(|d| (d.as_str(), "r0")))
+    }
+
+    /// Emit marker for an instruction which failed to translate
+    fn emit_placeholder(&self, text: &str, error: &Error) -> String {
+        let trap = match self.placeholder_helper {
+            Some(ref helper) => format!("bl {:}", helper),
+            None => "udf #0".to_string(),
         };
 
-        let r1 = self.arm_reg(instruction.operands[0].get_reg());
-        let r2 = self.arm_reg(instruction.operands[1].get_reg());
-        let r3 = self.arm_reg(instruction.operands[2].get_reg());
+        format!("@ untranslated: {:} ({:})\n\t{:}", text, error.cause(), trap)
+    }
 
-        format!("add {:}, {:}, {:}, lsl #{:}", r1, r3, r2, shift)
+    /// Emit one IR operation, empty if it needs no code
+    fn emit_op(&mut self, op: &Op) -> Result<String> {
+        Ok(match *op {
+            Op::Mov { dst, src: Value::Imm(i) } => self.emit_mov_imm(dst, i),
+            Op::Mov { dst, src } => format!("mov {:}, {:}", self.reg(dst), self.operand(src)),
+            Op::Binary { op, dst, a, b } => self.emit_binary(op, dst, a, b)?,
+            Op::AddShifted { dst, a, shift, b } =>
+                format!("add {:}, {:}, {:}, lsl #{:}", self.reg(dst), self.reg(b), self.reg(a), shift),
+            Op::Unary { op, dst, src } => self.emit_unary(op, dst, src),
+            Op::Extract { dst, src, shift, width, signed } => self.emit_extract(dst, src, shift, width, signed),
+            Op::Saturate { dst, src, bits } => self.emit_saturate(dst, src, bits)?,
+            Op::MulHigh { dst, a, b, signed } => self.emit_mul_high(dst, a, b, signed)?,
+            Op::Mul16 { dst, a, b, signed } => self.emit_mul16(dst, a, b, signed)?,
+            Op::Div { dst, a, b, signed, remainder } => self.emit_div(dst, a, b, signed, remainder)?,
+            Op::FunnelShift { dst, high, low, amount } => self.emit_funnel_shift(dst, high, low, amount)?,
+            Op::Load { .. } |
+            Op::Store { .. } => self.emit_load_store(op)?,
+            Op::CompareSwap { reg, base, offset, compare } => self.emit_compare_swap(reg, base, offset, compare)?,
+            Op::LoadGlobal { .. } |
+            Op::StoreGlobal { .. } |
+            Op::ExchangeGlobal { .. } => self.emit_global(op)?,
+            Op::Compare { kind, a, b } => self.emit_compare(kind, a, b)?,
+            // Spilled operands may share a scratch register
+            Op::Select { dst, src, .. } if self.reg(dst) == self.reg(src) => String::new(),
+            Op::Select { cond, dst, src } =>
+                format!("mov{:} {:}, {:}", arm_condition(cond), self.reg(dst), self.reg(src)),
+            Op::Branch { cond: Some(cond), target } => format!("b{:} loc_{:x}", arm_condition(cond), target),
+            Op::Branch { cond: None, target } => format!("b loc_{:x}", target),
+            Op::LoopBack { target } => self.emit_loop_end(target),
+            Op::Call { ref target, rotation } => self.emit_call(target, rotation),
+            Op::Intrinsic { ref name, dst, ref args } => self.emit_intrinsic(name, dst, args),
+            Op::Entry { frame } => self.emit_entry(frame),
+            Op::Return { windowed: false } => self.emit_ret(),
+            Op::Return { windowed: true } => self.emit_retw(),
+            // Volatile accesses already stay in order
+            Op::Barrier => String::new(),
+            Op::Nop => "nop".to_string(),
+        })
     }
 
+    /// Emit IR operations of one instruction
+    fn emit_ops(&mut self, ops: &[Op]) -> Result<Vec<String>> {
+        let mut lines = Vec::<String>::new();
+
+        self.bind_temps(ops)?;
+
+        for op in ops {
+            let line = self.emit_op(op)?;
+
+            if !line.is_empty() {
+                lines.push(line);
+            }
+        }
+
+        Ok(lines)
+    }
+
+    /// Translates the lifted operations of an instruction.
+    /// A loop back edge at the end goes after the spill sequence
     fn translate_instruction(&mut self, i: &mut Instruction, xtensa_i: &XtensaInstruction,
-            refs: &mut BTreeSet<u32>, pipe: &mut R2Pipe, objects: &mut ObjectStorage) {
-        let op = match xtensa_i.opcode {
-            XtensaOpcode::Add |
-            XtensaOpcode::Sub |
-            XtensaOpcode::And |
-            XtensaOpcode::Or => { self.emit_r3(xtensa_i) }
-            XtensaOpcode::L32i |
-            XtensaOpcode::S32i |
-            XtensaOpcode::L8ui |
-            XtensaOpcode::S8i  |
-            XtensaOpcode::L16si => { self.emit_load_store(xtensa_i) }
-            XtensaOpcode::Addi |
-            XtensaOpcode::Slli |
-            XtensaOpcode::Slri |
-            XtensaOpcode::Srai => { self.emit_r2_i1(xtensa_i) }
-            XtensaOpcode::Bbci |
-            XtensaOpcode::Bbsi => { branch!(self.emit_branch_bit(xtensa_i), refs) }
-            XtensaOpcode::Addx2 |
-            XtensaOpcode::Addx4 |
-            XtensaOpcode::Addx8 => { self.emit_addx(xtensa_i) }
-            XtensaOpcode::Jmp => { branch!(self.emit_jmp(xtensa_i), refs) }
-            XtensaOpcode::L32r => { self.emit_load_relative(xtensa_i, pipe) }
-            XtensaOpcode::Ret => { self.emit_ret() }
-            XtensaOpcode::Memw => { self.emit_memw() }
-            XtensaOpcode::Mov => { self.emit_mov(xtensa_i) }
-            XtensaOpcode::Movi => { self.emit_movi(xtensa_i) }
-            XtensaOpcode::Callx0 => { self.emit_reg_call(xtensa_i) }
-            XtensaOpcode::Call0 => { self.emit_call(xtensa_i, objects) }
-            _ => { panic!("translate_instruction: opcode not supported: {:?}", xtensa_i.opcode) }
+            ops: &[Op]) -> Result<()> {
+        let split = match ops.last() {
+            Some(&Op::LoopBack { .. }) => ops.len() - 1,
+            _ => ops.len(),
+        };
+        let (body, back_edge) = ops.split_at(split);
+
+        // Optimized operations may access other registers than the instruction
+        let (reload, spill) = if self.optimize {
+            let uses: Vec<u8> = body.iter().flat_map(|op| op.uses()).filter_map(|r| r.xtensa()).collect();
+            let defs: Vec<u8> = body.iter().flat_map(|op| op.defs()).filter_map(|r| r.xtensa()).collect();
+
+            self.bind_spilled(&uses, &defs)?
+        } else {
+            self.bind_spilled(&xtensa_i.uses(), &xtensa_i.defs())?
         };
 
-        i.opcode = op;
+        let mut lines = reload;
+        lines.extend(self.emit_ops(body)?);
+        lines.extend(spill);
+        lines.extend(self.emit_ops(back_edge)?);
+        i.opcode = lines.join("\n\t");
+
+        if self.isa == InstructionSet::Thumb2 {
+            i.opcode = insert_it_blocks(&i.opcode);
+        }
+
+        Ok(())
     }
 
     pub fn new() -> Translator {
         Default::default()
     }
 
-    pub fn translate(&mut self, function: &mut Function, pipe: &mut R2Pipe, objects: &mut ObjectStorage) -> Function {
+    pub fn with_abi(abi: XtensaAbi) -> Translator {
+        Translator { abi, ..Default::default() }
+    }
+
+    /// Lifts the function to IR, maps xtensa registers to ARM
+    /// ones and emits assembly with a prologue adapting the
+    /// frame to AAPCS
+    pub fn translate(&mut self, function: &mut Function, objects: &mut ObjectStorage) -> Result<Function> {
         let mut result = Function::new();
-        let mut refs = BTreeSet::<u32>::new();
 
-        for instruction in &function.instructions {
-            let mut xtensa_instruction = XtensaInstruction::new();
+        self.referenced_objects.clear();
+        self.pending_args.clear();
+        self.frame_size = 0;
+
+        let mut lifted = Lifter::new(self.abi, self.special_regs.clone())
+            .lift_function(function, objects, self.lenient)?;
+        let xtensa_instructions = &lifted.instructions;
+        let cfg = &lifted.cfg;
+
+        if self.optimize {
+            optimize(&mut lifted.code, cfg);
+
+            // Optimized code may access fewer or other registers than the instructions
+            let regs: Vec<Vec<u8>> = lifted.code.iter().zip(xtensa_instructions)
+                .map(|(ops, instruction)| match *ops {
+                    Some(ref ops) => ops.iter()
+                        .flat_map(|op| op.uses().into_iter().chain(op.defs()))
+                        .filter_map(|r| r.xtensa())
+                        .collect(),
+                    None => instruction.uses().into_iter().chain(instruction.defs()).collect(),
+                })
+                .collect();
+
+            self.registers = RegisterMap::allocate_regs(&regs, xtensa_instructions, cfg);
+        } else {
+            self.registers = RegisterMap::allocate(xtensa_instructions, cfg);
+        }
+
+        self.frame = match self.abi {
+            XtensaAbi::Call0 => {
+                let registers = &self.registers;
+                let mut frame = Call0Frame::analyze(xtensa_instructions,
+                    |r| registers.reg_name(r).unwrap_or_default());
+
+                for reg in self.registers.reserved() {
+                    frame.save(reg);
+                }
+
+                // Arguments the optimized code doesn't read aren't loaded
+                frame.stack_args.retain(|&(r, _)| registers.location(r) != Location::Unused);
+                frame
+            }
+            XtensaAbi::Windowed => Call0Frame::default(),
+        };
+
+        for (n, instruction) in function.instructions.iter().enumerate() {
+            let xtensa_instruction = &xtensa_instructions[n];
             let mut result_instruction = Instruction::new();
 
-            xtensa_instruction.from_str(&instruction.opcode);
+            let translated = match lifted.code[n] {
+                Some(ref ops) => self.translate_instruction(&mut result_instruction, xtensa_instruction, ops)
+                    .map_err(|e| e.at(&function.name, instruction.offset, &instruction.opcode)),
+                None => Err(lifted.errors[&n].clone()),
+            };
+
+            if let Err(e) = translated {
+                if !self.lenient {
+                    return Err(e);
+                }
+
+                result_instruction.opcode = self.emit_placeholder(&instruction.opcode, &e);
+                self.placeholders.push(e);
+
+                for line in self.emit_ops(&lifted.loop_end_after(n))? {
+                    result_instruction.opcode.push_str("\n\t");
+                    result_instruction.opcode.push_str(&line);
+                }
+            }
 
-            self.translate_instruction(&mut result_instruction, &xtensa_instruction,
-                &mut refs, pipe, objects);
+            for r in xtensa_instruction.defs() {
+                if stack_arg_offset(r).is_some() {
+                    self.pending_args.insert(r);
+                }
+            }
 
             result_instruction.offset = instruction.offset;
+            result_instruction.referenced = cfg.is_branch_target(instruction.offset);
             result_instruction.arch = InstructionArch::Arm;
             result.instructions.push(result_instruction);
         }
 
-        for i in 0..function.instructions.len() {
-            if refs.contains(&function.instructions[i].offset) {
-                result.instructions[i].referenced = true;
-            }
+        let prologue = self.emit_prologue();
+
+        if !prologue.is_empty() {
+            let mut instruction = Instruction::new();
+
+            instruction.opcode = prologue;
+            instruction.offset = function.instructions.first().map_or(0, |i| i.offset);
+            instruction.arch = InstructionArch::Arm;
+            result.instructions.insert(0, instruction);
         }
 
         result.name = function.name.clone();
-        result
+        Ok(result)
+    }
+}
+
+impl Backend for Translator {
+    fn translate(&mut self, function: &mut Function, objects: &mut ObjectStorage) -> Result<Function> {
+        Translator::translate(self, function, objects)
+    }
+
+    fn referenced_objects(&self) -> &BTreeSet<u32> {
+        &self.referenced_objects
+    }
+
+    fn placeholders(&mut self) -> &mut Vec<Error> {
+        &mut self.placeholders
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use translation::xtensa_arm::{Translator, insert_it_blocks};
+    use translation::xtensa_abi::XtensaAbi;
+    use translation::arm_cpu::{ArmCpu, InstructionSet};
+    use translation::xtensa_sr::SrMap;
+    use object_storage::ObjectStorage;
+    use translation::backend::translate_fixture;
+    use function::fixture;
+
+    /// Translates a call0 function, returns instruction text
+    fn translate(code: &[&str], cpu: ArmCpu) -> Vec<String> {
+        translate_isa(code, cpu, InstructionSet::Arm)
+    }
+
+    fn translate_isa(code: &[&str], cpu: ArmCpu, isa: InstructionSet) -> Vec<String> {
+        let mut translator = Translator::new();
+
+        translator.cpu = cpu;
+        translator.isa = isa;
+        translate_fixture(&mut translator, code)
+    }
+
+    #[test]
+    fn test_lenient_1() {
+        let mut f = fixture(&["movi a2, 1", "rfe", "ret"]);
+        let mut objects = ObjectStorage::default();
+        let mut translator = Translator::new();
+
+        assert!(translator.translate(&mut f, &mut objects).is_err());
+
+        translator.lenient = true;
+        let result = translator.translate(&mut f, &mut objects).unwrap();
+
+        assert_eq!(result.instructions[1].opcode,
+            "@ untranslated: rfe (opcode not supported: rfe)\n\tudf #0");
+        assert_eq!(translator.placeholders.len(), 1);
+    }
+
+    #[test]
+    fn test_sar_1() {
+        let mut f = fixture(&[
+            "ssai 8", "src a2, a3, a2", "srl a3, a3",
+            "ssl a4", "sll a2, a2", "sra a3, a3", "ret",
+        ]);
+        let mut objects = ObjectStorage::default();
+        let result = Translator::new().translate(&mut f, &mut objects).unwrap();
+        let code: Vec<&str> = result.instructions.iter().map(|i| i.opcode.as_str()).collect();
+
+        assert_eq!(code[0], "push {r6, r12}");
+        assert_eq!(code[1], "mov r6, #8");
+        assert_eq!(code[2], "lsr r0, r0, #8\n\torr r0, r0, r1, lsl #24");
+        assert_eq!(code[3], "lsr r1, r1, #8");
+        assert_eq!(code[4], "and r6, r2, #31\n\trsb r6, r6, #32");
+        assert_eq!(code[5], "rsb r12, r6, #32\n\tlsl r0, r0, r12");
+        assert_eq!(code[6], "asr r1, r1, r6");
+    }
+
+    #[test]
+    fn test_extui_1() {
+        let code = ["extui a2, a3, 4, 8", "extui a2, a3, 0, 4", "extui a2, a3, 8, 12", "extui a2, a3, 20, 12"];
+
+        assert_eq!(translate(&code, ArmCpu::Armv7a), vec![
+            "ubfx r0, r1, #4, #8", "ubfx r0, r1, #0, #4", "ubfx r0, r1, #8, #12", "lsr r0, r1, #20",
+        ]);
+        assert_eq!(translate(&code, ArmCpu::Armv5te), vec![
+            "lsr r0, r1, #4\n\tand r0, r0, #0xff",
+            "and r0, r1, #0xf",
+            "lsl r0, r1, #12\n\tlsr r0, r0, #20",
+            "lsr r0, r1, #20",
+        ]);
+    }
+
+    #[test]
+    fn test_sext_1() {
+        assert_eq!(translate(&["sext a2, a3, 7"], ArmCpu::Armv7a), vec!["sbfx r0, r1, #0, #8"]);
+        assert_eq!(translate(&["sext a2, a3, 15"], ArmCpu::Armv6), vec!["lsl r0, r1, #16\n\tasr r0, r0, #16"]);
+    }
+
+    #[test]
+    fn test_nsa_1() {
+        assert_eq!(translate(&["nsau a2, a3", "nsa a2, a2"], ArmCpu::Armv5te), vec![
+            "clz r0, r1",
+            "eor r0, r0, r0, asr #31\n\tclz r0, r0\n\tsub r0, r0, #1",
+        ]);
+    }
+
+    #[test]
+    fn test_clamps_1() {
+        assert_eq!(translate(&["clamps a2, a3, 7"], ArmCpu::Armv6), vec!["ssat r0, #8, r1"]);
+        assert_eq!(translate(&["clamps a2, a2, 15"], ArmCpu::Armv5te), vec![
+            "asr r12, r0, #15\n\tteq r12, r0, asr #31\n\tmvnne r12, #0\n\t\
+                lsrne r12, r12, #17\n\teorne r0, r12, r0, asr #31",
+        ]);
+    }
+
+    #[test]
+    fn test_min_max_1() {
+        let code = ["min a2, a3, a4", "max a2, a2, a3", "minu a2, a3, a2", "maxu a2, a3, a4"];
+
+        assert_eq!(translate(&code, ArmCpu::Armv7a), vec![
+            "cmp r1, r2\n\tmovlt r0, r1\n\tmovge r0, r2",
+            "cmp r0, r1\n\tmovle r0, r1",
+            "cmp r1, r0\n\tmovlo r0, r1",
+            "cmp r1, r2\n\tmovhi r0, r1\n\tmovls r0, r2",
+        ]);
+    }
+
+    #[test]
+    fn test_mul_1() {
+        let code = ["mul16u a2, a3, a2", "mul16s a2, a3, a4", "mull a2, a2, a3", "muluh a2, a3, a4", "mulsh a8, a3, a4"];
+
+        assert_eq!(translate(&code, ArmCpu::Armv7a), vec![
+            "push {r6, r12}",
+            "uxth r12, r1\n\tuxth r0, r0\n\tmul r0, r12, r0",
+            "smulbb r0, r1, r2",
+            "mul r0, r1, r0",
+            "umull r12, r0, r1, r2",
+            "smull r12, r6, r1, r2",
+        ]);
+        assert_eq!(translate(&code[.. 1], ArmCpu::Armv5te), vec![
+            "lsl r12, r1, #16\n\tlsr r12, r12, #16\n\tlsl r0, r0, #16\n\tlsr r0, r0, #16\n\tmul r0, r12, r0",
+        ]);
+        assert_eq!(translate(&["muluh a2, a2, a3"], ArmCpu::Armv5te), vec!["umull r12, r0, r1, r0"]);
+    }
+
+    #[test]
+    fn test_div_1() {
+        let code = ["quou a2, a3, a4", "rems a8, a2, a3"];
+
+        assert_eq!(translate(&code, ArmCpu::Armv7ve), vec![
+            "push {r6, r12}",
+            "udiv r0, r1, r2",
+            "sdiv r6, r0, r1\n\tmls r6, r6, r1, r0",
+        ]);
+        assert_eq!(translate(&code, ArmCpu::Armv7a), vec![
+            "push {r6, r12}",
+            "push {r0, r1, r2, r3, r12, lr}\n\tldr r0, [sp, #4]\n\tldr r1, [sp, #8]\n\t\
+                bl __aeabi_uidiv\n\tstr r0, [sp, #0]\n\tpop {r0, r1, r2, r3, r12, lr}",
+            "push {r0, r1, r2, r3, r12, lr}\n\tldr r0, [sp, #0]\n\tldr r1, [sp, #4]\n\t\
+                bl __aeabi_idivmod\n\tmov r6, r1\n\tpop {r0, r1, r2, r3, r12, lr}",
+        ]);
+    }
+
+    #[test]
+    fn test_conditional_move_1() {
+        let code = [
+            "wsr.br a4", "moveqz a2, a3, a4", "movltz a3, a2, a3",
+            "movt a2, a4, b0", "movf a3, a2, b3", "movnez a2, a2, a3",
+        ];
+
+        // Boolean registers are bits of one BR register
+        assert_eq!(translate(&code, ArmCpu::Armv7a), vec![
+            "push {r6, r12}",
+            "ldr r6, =0xffff\n\tand r6, r2, r6",
+            "cmp r2, #0\n\tmoveq r0, r1",
+            "cmp r1, #0\n\tmovlt r1, r0",
+            "tst r6, #1\n\tmovne r0, r2",
+            "tst r6, #8\n\tmoveq r1, r0",
+            "",
+        ]);
+    }
+
+    #[test]
+    fn test_load_store_1() {
+        let code = [
+            "l16ui a2, a3, 0x1fe", "s16i a2, a3, 0x100", "l16si a2, a3, 0xfe",
+            "l8ui a2, a3, 0xff", "l32ai a2, a3, 0x3fc", "s32ri a2, a3, 0", "addmi a2, a3, -256",
+        ];
+
+        assert_eq!(translate(&code, ArmCpu::Armv7a), vec![
+            "add r0, r1, #0x100\n\tldrh r0, [r0, #0xfe]",
+            "add r12, r1, #0x100\n\tstrh r0, [r12, #0x0]",
+            "ldrsh r0, [r1, #0xfe]",
+            "ldrb r0, [r1, #0xff]",
+            "ldr r0, [r1, #0x3fc]\n\tdmb ish",
+            "dmb ish\n\tstr r0, [r1, #0x0]",
+            "add r0, r1, #-256",
+        ]);
+        assert_eq!(translate(&code[4 .. 5], ArmCpu::Armv6), vec!["ldr r0, [r1, #0x3fc]"]);
+    }
+
+    #[test]
+    fn test_s32c1i_1() {
+        assert_eq!(translate(&["s32c1i a2, a3, 4"], ArmCpu::Armv7a), vec![
+            "ldr r12, [r1, #0x4]\n\tcmp r12, r6\n\tstreq r0, [r1, #0x4]\n\tmov r0, r12",
+        ]);
+    }
+
+    #[test]
+    fn test_loop_1() {
+        let mut f = fixture(&["movi a2, 0", "loopgtz a3, 0x10c", "l8ui a8, a4, 0", "add a2, a2, a8", "ret"]);
+        let mut objects = ObjectStorage::default();
+        let result = Translator::new().translate(&mut f, &mut objects).unwrap();
+        let code: Vec<&str> = result.instructions.iter().map(|i| i.opcode.as_str()).collect();
+
+        assert_eq!(code[2], "mov r6, r1\n\tcmp r6, #0\n\tble loc_10c");
+        assert_eq!(code[4], "add r0, r0, r7\n\tsubs r6, r6, #1\n\tbne loc_106");
+        assert!(result.instructions[3].referenced);
+        assert!(result.instructions[5].referenced);
+
+        let mut f = fixture(&["loop a3, 0x200", "ret"]);
+        assert!(Translator::new().translate(&mut f, &mut objects).is_err());
+    }
+
+    #[test]
+    fn test_special_reg_1() {
+        let mut f = fixture(&[
+            "rsr.ps a2", "wsr.intenable a3", "xsr.excsave1 a2",
+            "rsil a4, 3", "wsr.sar a3", "rsr.sar a2", "ret",
+        ]);
+        let mut objects = ObjectStorage::default();
+        let mut translator = Translator::new();
+
+        translator.special_regs = SrMap::parse("excsave1 slot XT_EXCSAVE1").unwrap();
+
+        let result = translator.translate(&mut f, &mut objects).unwrap();
+        let code: Vec<&str> = result.instructions.iter().map(|i| i.opcode.as_str()).collect();
+        let saved = "push {r0, r1, r2, r3, r12, lr}";
+        let restored = "pop {r0, r1, r2, r3, r12, lr}";
+
+        assert_eq!(code[1], format!("{:}\n\tbl __xt_rsr_ps\n\tstr r0, [sp, #0]\n\t{:}", saved, restored));
+        assert_eq!(code[2], format!("{:}\n\tldr r0, [sp, #4]\n\tbl __xt_wsr_intenable\n\t{:}", saved, restored));
+        assert_eq!(code[3], "ldr r12, =XT_EXCSAVE1\n\tldr r12, [r12]\n\tpush {r12}\n\t\
+                             ldr r12, =XT_EXCSAVE1\n\tstr r0, [r12]\n\tpop {r0}");
+        assert_eq!(code[4], format!("{:}\n\tmov r0, #3\n\tbl __xt_rsil\n\tstr r0, [sp, #8]\n\t{:}", saved, restored));
+        assert_eq!(code[5], "and r6, r1, #63");
+        assert_eq!(code[6], "mov r0, r6");
+
+        let mut f = fixture(&["rsr.lcount a2", "ret"]);
+        assert!(translator.translate(&mut f, &mut objects).is_err());
+    }
+
+    #[test]
+    fn test_it_blocks_1() {
+        assert_eq!(insert_it_blocks("cmp r0, #0\n\tmoveq r1, #0\n\tmovne r1, #1\n\tbne loc_10"),
+            "cmp r0, #0\n\tite eq\n\tmoveq r1, #0\n\tmovne r1, #1\n\tbne loc_10");
+        assert_eq!(insert_it_blocks("movlt r0, r1\n\tmovlt r2, r3\n\tmovgt r4, r5"),
+            "itt lt\n\tmovlt r0, r1\n\tmovlt r2, r3\n\tit gt\n\tmovgt r4, r5");
+        assert_eq!(insert_it_blocks("teq r0, r1\n\tmls r0, r1, r2, r3\n\tldrsh r0, [r1]"),
+            "teq r0, r1\n\tmls r0, r1, r2, r3\n\tldrsh r0, [r1]");
+    }
+
+    #[test]
+    fn test_thumb_1() {
+        let code = ["min a2, a3, a4", "s32c1i a2, a3, 4", "ssr a4", "src a2, a3, a2"];
+
+        assert_eq!(translate_isa(&code, ArmCpu::Armv7m, InstructionSet::Thumb2)[1 ..], [
+            "cmp r1, r2\n\tite lt\n\tmovlt r0, r1\n\tmovge r0, r2",
+            "ldr r12, [r1, #0x4]\n\tcmp r12, r6\n\tit eq\n\tstreq r0, [r1, #0x4]\n\tmov r0, r12",
+            "and r6, r2, #31",
+            "rsb r12, r6, #32\n\tlsl r12, r1, r12\n\tlsr r0, r0, r6\n\torr r0, r0, r12",
+        ]);
+        assert_eq!(translate_isa(&["mul16s a2, a3, a4"], ArmCpu::Armv7m, InstructionSet::Thumb2), vec![
+            "sxth r12, r1\n\tsxth r0, r2\n\tmul r0, r12, r0",
+        ]);
+    }
+
+    #[test]
+    fn test_optimize_1() {
+        let mut f = fixture(&["movi a8, 0x1234", "add a2, a2, a8", "mov a9, a2", "beqz a9, 0x10f", "addi a2, a9, 1", "ret"]);
+        let mut objects = ObjectStorage::default();
+        let mut translator = Translator::new();
+
+        translator.optimize = true;
+        let result: Vec<String> = translator.translate(&mut f, &mut objects).unwrap()
+            .instructions.into_iter()
+            .map(|i| i.opcode)
+            .collect();
+
+        assert_eq!(result, vec!["", "ldr r12, =0x1234\n\tadd r0, r0, r12", "", "cmp r0, #0\n\tbeq loc_10f",
+                                "add r0, r0, #1", "bx lr"]);
+    }
+
+    #[test]
+    fn test_stack_args_1() {
+        assert_eq!(translate(&["add a2, a2, a7", "ret"], ArmCpu::Armv7a), vec![
+            "push {r5, r12}\n\tldr r5, [sp, #12]",
+            "add r0, r0, r5",
+            "pop {r5, r12}\n\tbx lr",
+        ]);
+    }
+
+    #[test]
+    fn test_windowed_call_1() {
+        let mut f = fixture(&["entry a1, 32", "callx12 a9", "retw"]);
+        let mut objects = ObjectStorage::default();
+        let result: Vec<String> = Translator::with_abi(XtensaAbi::Windowed).translate(&mut f, &mut objects).unwrap()
+            .instructions.into_iter()
+            .map(|i| i.opcode)
+            .collect();
+
+        assert_eq!(result, vec![
+            "push {r12, lr}\n\tsub sp, sp, #32",
+            "push {r0, r1, r2, r3, r4, r5, r6, lr}\n\tmov lr, r6\n\tmov r0, r7\n\tblx lr\n\tmov r12, r0\n\t\
+             pop {r0, r1, r2, r3, r4, r5, r6, lr}\n\tmov r7, r12",
+            "add sp, sp, #32\n\tpop {r12, pc}",
+        ]);
+    }
+
+    #[test]
+    fn test_windowed_spill_1() {
+        let code = [
+            "entry a1, 32", "movi a8, 1", "movi a9, 2", "movi a10, 3", "movi a11, 4", "movi a12, 5",
+            "movi a13, 6", "movi a14, 7", "add a2, a8, a9", "add a2, a2, a10", "add a2, a2, a11",
+            "add a2, a2, a12", "add a2, a2, a13", "add a2, a2, a14", "retw",
+        ];
+        let mut f = fixture(&code);
+        let mut objects = ObjectStorage::default();
+        let result = Translator::with_abi(XtensaAbi::Windowed).translate(&mut f, &mut objects).unwrap();
+
+        // Spill area is only allocated by entry
+        assert_eq!(result.instructions.len(), code.len());
+        assert_eq!(result.instructions[0].opcode, "push {r12, lr}\n\tsub sp, sp, #16\n\tmov r11, sp\n\tsub sp, sp, #32");
+        assert_eq!(result.instructions[14].opcode, "add sp, sp, #48\n\tpop {r12, pc}");
+    }
+
+    #[test]
+    fn test_windowed_call_2() {
+        // a10 is spilled, the return value is stored after r11 is restored
+        let mut f = fixture(&[
+            "entry a1, 32", "movi a9, 2", "movi a11, 4", "movi a12, 5", "movi a13, 6", "movi a14, 7",
+            "movi a15, 8", "callx8 a9", "add a2, a9, a11", "add a2, a2, a12", "add a2, a2, a13",
+            "add a2, a2, a14", "add a2, a2, a15", "add a2, a2, a10", "retw",
+        ]);
+        let mut objects = ObjectStorage::default();
+        let result = Translator::with_abi(XtensaAbi::Windowed).translate(&mut f, &mut objects).unwrap();
+
+        assert_eq!(result.instructions[7].opcode, "push {r0, r1, r2, r3, r4, r5, r11, lr}\n\tmov lr, r6\n\t\
+            ldr r0, [r11, #0]\n\tmov r1, r7\n\tmov r2, r8\n\tmov r3, r9\n\tldr r4, [r11, #4]\n\tldr r5, [r11, #8]\n\t\
+            blx lr\n\tmov r12, r0\n\tpop {r0, r1, r2, r3, r4, r5, r11, lr}\n\tstr r12, [r11, #0]");
+    }
+
+    #[test]
+    fn test_imm_op_1() {
+        let mut f = fixture(&["entry a1, 4104", "retw"]);
+        let mut objects = ObjectStorage::default();
+        let result = Translator::with_abi(XtensaAbi::Windowed).translate(&mut f, &mut objects).unwrap();
+
+        assert_eq!(result.instructions[0].opcode, "push {r12, lr}\n\tsub sp, sp, #8\n\tsub sp, sp, #4096");
+        assert_eq!(result.instructions[1].opcode, "add sp, sp, #8\n\tadd sp, sp, #4096\n\tpop {r12, pc}");
     }
 }
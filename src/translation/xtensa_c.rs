@@ -0,0 +1,737 @@
+use std::vec::Vec;
+use std::collections::{BTreeSet, BTreeMap};
+
+use object_storage::ObjectStorage;
+use assembly::{Instruction, InstructionArch};
+use translation::xtensa_op::{SAR, BR, SCOMPARE1, LCOUNT};
+use translation::xtensa_sr::SrMap;
+use translation::xtensa_abi::XtensaAbi;
+use translation::xtensa_lift::Lifter;
+use translation::optimize::{optimize, flags_live_out, flag_readers};
+use translation::ir::{Op, Reg, Value, BinOp, UnaryOp, Width, CompareKind, Cond, CallTarget};
+use translation::backend::Backend;
+use function::Function;
+use error::{Error, Result};
+
+/// Emitted at the top of C output
+pub const PRELUDE: &str = "\
+#include <stdint.h>
+
+/* Access to an xtensa address, may be redefined to relocate memory */
+#ifndef XT_MEM
+#define XT_MEM(type, address) (*(volatile type *)(uintptr_t)(address))
+#endif
+
+/* Division by zero gives 0 and no exception, as with ARM output */
+static inline uint32_t xt_quou(uint32_t a, uint32_t b) { return b ? a / b : 0; }
+static inline uint32_t xt_remu(uint32_t a, uint32_t b) { return b ? a % b : a; }
+static inline uint32_t xt_quos(uint32_t a, uint32_t b)
+{
+\treturn b == 0 ? 0 : b == 0xffffffff ? -a : (uint32_t)((int32_t)a / (int32_t)b);
+}
+static inline uint32_t xt_rems(uint32_t a, uint32_t b)
+{
+\treturn b == 0 ? a : b == 0xffffffff ? 0 : (uint32_t)((int32_t)a % (int32_t)b);
+}
+";
+
+/// Runtime function performing callx0: calls the translated
+/// function at an xtensa address
+const CALLX: &str = "xt_callx";
+
+/// Returns C signature of a translated function. call0 arguments
+/// and stack pointer are passed by value, a2 is returned in the
+/// low and a3 in the high word
+pub fn signature(name: &str) -> String {
+    format!("uint64_t {:}(uint32_t a1, uint32_t a2, uint32_t a3, uint32_t a4, \
+             uint32_t a5, uint32_t a6, uint32_t a7)", name)
+}
+
+/// Turns a symbol name into a C identifier
+pub fn identifier(name: &str) -> String {
+    let mut s: String = name.chars()
+        .map(|c| if c.is_ascii_alphanumeric() || c == '_' { c } else { '_' })
+        .collect();
+
+    if s.starts_with(|c: char| c.is_ascii_digit()) {
+        s.insert(0, '_');
+    }
+
+    s
+}
+
+#[derive(Default)]
+pub struct Translator {
+    /// Addresses of functions called from
+    /// the last translated function
+    pub referenced_objects: BTreeSet<u32>,
+    pub abi: XtensaAbi,
+    /// How special and user registers of the target are accessed
+    pub special_regs: SrMap,
+    /// Replace untranslatable instructions with
+    /// placeholders instead of failing
+    pub lenient: bool,
+    /// Function placeholders call, `__builtin_trap` is used if not set
+    pub placeholder_helper: Option<String>,
+    /// Run the IR optimization passes over each function
+    pub optimize: bool,
+    /// Errors replaced by placeholders so far
+    pub placeholders: Vec<Error>,
+    /// File scope declarations of the callees, intrinsics and
+    /// special register slots used by all translated functions
+    pub declarations: BTreeSet<String>,
+    /// Whether the current function keeps flags
+    /// in `cmp_a` and `cmp_b`
+    flags_used: bool,
+}
+
+/// C variable of an IR register
+fn reg_name(r: Reg) -> String {
+    match r {
+        Reg::Xtensa(n @ 0 ..= 15) => format!("a{:}", n),
+        Reg::Xtensa(SAR) => "sar".to_string(),
+        Reg::Xtensa(SCOMPARE1) => "scompare1".to_string(),
+        Reg::Xtensa(LCOUNT) => "lcount".to_string(),
+        Reg::Xtensa(BR) => "br".to_string(),
+        Reg::Temp(t) => format!("t{:}", t),
+        _ => panic!("Unknown/unsupported register referenced: {:}", r)
+    }
+}
+
+fn signed(value: &str) -> String {
+    format!("(int32_t){:}", value)
+}
+
+/// C constant, small ones in decimal
+fn constant(value: i32) -> String {
+    match value {
+        0 ..= 256 => format!("{:}", value),
+        _ => format!("0x{:x}", value as u32),
+    }
+}
+
+fn value(v: Value) -> String {
+    match v {
+        Value::Reg(r) => reg_name(r),
+        Value::Imm(i) => constant(i),
+    }
+}
+
+/// Comparison of `a` with `b`, `sa` and `sb`
+/// are their signed forms
+fn comparison(cond: Cond, a: &str, b: &str, sa: &str, sb: &str) -> String {
+    match cond {
+        Cond::Eq => format!("{:} == {:}", a, b),
+        Cond::Ne => format!("{:} != {:}", a, b),
+        Cond::Lt => format!("{:} < {:}", sa, sb),
+        Cond::Ge => format!("{:} >= {:}", sa, sb),
+        Cond::Gt => format!("{:} > {:}", sa, sb),
+        Cond::Le => format!("{:} <= {:}", sa, sb),
+        Cond::Ltu => format!("{:} < {:}", a, b),
+        Cond::Geu => format!("{:} >= {:}", a, b),
+        Cond::Gtu => format!("{:} > {:}", a, b),
+        Cond::Leu => format!("{:} <= {:}", a, b),
+    }
+}
+
+/// Expression a `Test` or `TestClear` compares with zero
+fn test_expression(kind: CompareKind, a: Reg, b: Value) -> String {
+    match (kind, a, b) {
+        (CompareKind::Test, _, Value::Imm(i)) => format!("{:} & 0x{:x}", reg_name(a), i as u32),
+        (CompareKind::Test, _, _) => format!("{:} & {:}", reg_name(a), value(b)),
+        _ => format!("~{:} & {:}", value(b), reg_name(a)),
+    }
+}
+
+impl Translator {
+    /// Condition on the flags `compare` sets, None
+    /// if they are kept in `cmp_a` and `cmp_b`
+    fn condition(&self, compare: Option<&(CompareKind, Reg, Value)>, cond: Cond) -> String {
+        match compare.cloned() {
+            None => comparison(cond, "cmp_a", "cmp_b", &signed("cmp_a"), &signed("cmp_b")),
+            Some((CompareKind::Cmp, a, b)) => {
+                let a = reg_name(a);
+                let sb = match b {
+                    Value::Reg(r) => signed(&reg_name(r)),
+                    Value::Imm(i) => format!("{:}", i),
+                };
+
+                comparison(cond, &a, &value(b), &signed(&a), &sb)
+            }
+            Some((kind, a, b)) => match cond {
+                Cond::Eq => format!("!({:})", test_expression(kind, a, b)),
+                Cond::Ne => test_expression(kind, a, b),
+                _ => panic!("Condition {:} of a bit test", cond.name())
+            }
+        }
+    }
+
+    /// Emit flags kept for readers which can't test
+    /// the compared values directly
+    fn emit_flags(&mut self, kind: CompareKind, a: Reg, b: Value) -> String {
+        self.flags_used = true;
+
+        match kind {
+            CompareKind::Cmp => format!("cmp_a = {:}; cmp_b = {:};", reg_name(a), value(b)),
+            _ => format!("cmp_a = {:}; cmp_b = 0;", test_expression(kind, a, b)),
+        }
+    }
+
+    fn emit_binary(&self, op: BinOp, dst: Reg, a: Value, b: Value) -> String {
+        let r1 = reg_name(dst);
+        let (r2, r3) = (value(a), value(b));
+
+        let operator = match op {
+            BinOp::Add => "+",
+            BinOp::Sub => "-",
+            BinOp::Mul => "*",
+            BinOp::And => "&",
+            BinOp::Or => "|",
+            BinOp::Xor => "^",
+            BinOp::Shl => "<<",
+            BinOp::Lsr |
+            BinOp::Asr => ">>",
+        };
+
+        match (op, b) {
+            (BinOp::Add, Value::Imm(i)) if i < 0 => format!("{:} = {:} - {:};", r1, r2, constant(i.wrapping_neg())),
+            // Shifts by 32 or more leave nothing or the sign
+            (BinOp::Shl, Value::Imm(k)) |
+            (BinOp::Lsr, Value::Imm(k)) if k >= 32 => format!("{:} = 0;", r1),
+            (BinOp::Asr, Value::Imm(k)) => format!("{:} = (uint32_t)({:} >> {:});", r1, signed(&r2), k.min(31)),
+            // Shifts by a register are done in 64 bits, where shifting by 32 is defined
+            (BinOp::Shl, Value::Reg(_)) => format!("{:} = (uint32_t)((uint64_t){:} << {:});", r1, r2, r3),
+            (BinOp::Lsr, Value::Reg(_)) => format!("{:} = (uint32_t)((uint64_t){:} >> {:});", r1, r2, r3),
+            (BinOp::Asr, Value::Reg(_)) => format!("{:} = (uint32_t)((int64_t){:} >> {:});", r1, signed(&r2), r3),
+            _ => format!("{:} = {:} {:} {:};", r1, r2, operator, r3),
+        }
+    }
+
+    /// Emit opcodes: load/store operations, as volatile accesses.
+    /// Ordered loads get a barrier after and ordered stores before
+    /// the access
+    fn emit_load_store(&self, op: &Op) -> String {
+        let (r1, base, offset, width, signed, ordered, store) = match *op {
+            Op::Load { dst, base, offset, width, signed, ordered } => (dst, base, offset, width, signed, ordered, false),
+            Op::Store { src, base, offset, width, ordered } => (src, base, offset, width, false, ordered, true),
+            _ => panic!()
+        };
+
+        let r1 = reg_name(r1);
+        let r2 = reg_name(base);
+        let address = if offset == 0 { r2 } else { format!("{:} + 0x{:x}", r2, offset) };
+        let access = |ty: &str| format!("XT_MEM({:}, {:})", ty, address);
+
+        let text = match (width, signed, store) {
+            (Width::Word, _, false) => format!("{:} = {:};", r1, access("uint32_t")),
+            (Width::Half, false, false) => format!("{:} = {:};", r1, access("uint16_t")),
+            (Width::Half, true, false) => format!("{:} = (uint32_t)(int32_t){:};", r1, access("int16_t")),
+            (Width::Byte, false, false) => format!("{:} = {:};", r1, access("uint8_t")),
+            (Width::Byte, true, false) => format!("{:} = (uint32_t)(int32_t){:};", r1, access("int8_t")),
+            (Width::Word, _, true) => format!("{:} = {:};", access("uint32_t"), r1),
+            (Width::Half, _, true) => format!("{:} = (uint16_t){:};", access("uint16_t"), r1),
+            (Width::Byte, _, true) => format!("{:} = (uint8_t){:};", access("uint8_t"), r1),
+        };
+
+        match (ordered, store) {
+            (true, false) => format!("{:}\n\t__sync_synchronize();", text),
+            (true, true) => format!("__sync_synchronize();\n\t{:}", text),
+            _ => text
+        }
+    }
+
+    /// Emit compare and swap. Translated code is
+    /// assumed to run on a single thread
+    fn emit_compare_swap(&self, reg: Reg, base: Reg, offset: u32, compare: Reg) -> String {
+        let r1 = reg_name(reg);
+        let r2 = reg_name(base);
+        let word = if offset == 0 {
+            format!("XT_MEM(uint32_t, {:})", r2)
+        } else {
+            format!("XT_MEM(uint32_t, {:} + 0x{:x})", r2, offset)
+        };
+
+        format!("{{ uint32_t t = {:}; if (t == {:}) {:} = {:}; {:} = t; }}", word, reg_name(compare), word, r1, r1)
+    }
+
+    /// Emit call0/callx0. Indirect calls go through
+    /// the `xt_callx` runtime function
+    fn emit_call(&mut self, target: &CallTarget) -> String {
+        let call = match *target {
+            CallTarget::Function { ref name, address } => {
+                let name = identifier(name);
+
+                self.referenced_objects.insert(address);
+                self.declarations.insert(format!("{:};", signature(&name)));
+                format!("{:}(a1, a2, a3, a4, a5, a6, a7)", name)
+            }
+            CallTarget::Reg(r) => {
+                self.declarations.insert(format!("uint64_t {:}(uint32_t target, uint32_t a1, uint32_t a2, \
+                    uint32_t a3, uint32_t a4, uint32_t a5, uint32_t a6, uint32_t a7);", CALLX));
+                format!("{:}({:}, a1, a2, a3, a4, a5, a6, a7)", CALLX, reg_name(r))
+            }
+        };
+
+        format!("{{ uint64_t r = {:}; a2 = (uint32_t)r; a3 = (uint32_t)(r >> 32); }}", call)
+    }
+
+    /// Emit funnel shift: low word of {high, low} >> amount
+    fn emit_funnel_shift(&self, dst: Reg, high: Reg, low: Reg, amount: Value) -> String {
+        let r1 = reg_name(dst);
+        let (high, low) = (reg_name(high), reg_name(low));
+
+        match amount {
+            Value::Imm(0) => format!("{:} = {:};", r1, low),
+            Value::Imm(32) => format!("{:} = {:};", r1, high),
+            Value::Imm(k) => format!("{:} = {:} << {:} | {:} >> {:};", r1, high, 32 - k, low, k),
+            Value::Reg(r) => format!("{:} = (uint32_t)(((uint64_t){:} << 32 | {:}) >> {:});", r1, high, low, reg_name(r)),
+        }
+    }
+
+    /// Emit bit field extract
+    fn emit_extract(&self, dst: Reg, src: Reg, shift: u8, width: u8, signed: bool) -> String {
+        let r1 = reg_name(dst);
+        let r2 = reg_name(src);
+        let (shift, width) = (shift as u32, width as u32);
+
+        if signed {
+            return if shift + width == 32 {
+                format!("{:} = (uint32_t)((int32_t){:} >> {:});", r1, r2, shift)
+            } else {
+                format!("{:} = (uint32_t)((int32_t)({:} << {:}) >> {:});", r1, r2, 32 - shift - width, 32 - width)
+            };
+        }
+
+        if shift + width == 32 {
+            format!("{:} = {:} >> {:};", r1, r2, shift)
+        } else if shift == 0 {
+            format!("{:} = {:} & 0x{:x};", r1, r2, (1u32 << width) - 1)
+        } else {
+            format!("{:} = ({:} >> {:}) & 0x{:x};", r1, r2, shift, (1u32 << width) - 1)
+        }
+    }
+
+    /// Emit normalization shift amount: leading zeros
+    /// or redundant sign bits
+    fn emit_unary(&self, op: UnaryOp, dst: Reg, src: Reg) -> String {
+        let r1 = reg_name(dst);
+        let r2 = reg_name(src);
+
+        match op {
+            UnaryOp::Clz => format!("{:} = {:} ? (uint32_t)__builtin_clz({:}) : 32;", r1, r2, r2),
+            UnaryOp::Cls => format!("{:} = (uint32_t)__builtin_clrsb({:});", r1, signed(&r2)),
+        }
+    }
+
+    /// Emit signed saturation to `bits` bits
+    fn emit_saturate(&self, dst: Reg, src: Reg, bits: u8) -> String {
+        let r1 = reg_name(dst);
+        let r2 = reg_name(src);
+        let bound = (1i32 << (bits - 1)) - 1;
+
+        format!("{:} = {:} > {:} ? {:} : {:} < {:} ? (uint32_t){:} : {:};",
+            r1, signed(&r2), bound, bound, signed(&r2), -bound - 1, -bound - 1, r2)
+    }
+
+    /// Emit high word of 32x32 bit multiply
+    fn emit_mul_high(&self, dst: Reg, a: Reg, b: Reg, signed_mul: bool) -> String {
+        let r1 = reg_name(dst);
+        let (r2, r3) = (reg_name(a), reg_name(b));
+
+        if signed_mul {
+            format!("{:} = (uint32_t)((int64_t){:} * {:} >> 32);", r1, signed(&r2), signed(&r3))
+        } else {
+            format!("{:} = (uint32_t)((uint64_t){:} * {:} >> 32);", r1, r2, r3)
+        }
+    }
+
+    /// Emit 16x16 bit multiply
+    fn emit_mul16(&self, dst: Reg, a: Reg, b: Reg, signed_mul: bool) -> String {
+        let r1 = reg_name(dst);
+        let (r2, r3) = (reg_name(a), reg_name(b));
+
+        if signed_mul {
+            format!("{:} = (uint32_t)((int16_t){:} * (int16_t){:});", r1, r2, r3)
+        } else {
+            format!("{:} = ({:} & 0xffff) * ({:} & 0xffff);", r1, r2, r3)
+        }
+    }
+
+    /// Emit division or remainder through the prelude helpers
+    fn emit_div(&self, dst: Reg, a: Reg, b: Reg, signed_div: bool, remainder: bool) -> String {
+        let helper = match (signed_div, remainder) {
+            (false, false) => "xt_quou",
+            (true, false) => "xt_quos",
+            (false, true) => "xt_remu",
+            (true, true) => "xt_rems",
+        };
+
+        format!("{:} = {:}({:}, {:});", reg_name(dst), helper, reg_name(a), reg_name(b))
+    }
+
+    /// Emit zero-overhead loop back edge. Counter 0
+    /// means 2^32 iterations, as with `loop`
+    fn emit_loop_end(&self, body: u32) -> String {
+        format!("if (--lcount != 0) goto loc_{:x};", body)
+    }
+
+    /// Emit access to a special register kept in a global word
+    fn emit_global(&mut self, op: &Op) -> String {
+        let (r1, symbol) = match *op {
+            Op::LoadGlobal { dst, ref symbol } |
+            Op::StoreGlobal { src: dst, ref symbol } |
+            Op::ExchangeGlobal { reg: dst, ref symbol } => (reg_name(dst), symbol),
+            _ => panic!()
+        };
+
+        self.declarations.insert(format!("extern uint32_t {:};", symbol));
+
+        match *op {
+            Op::LoadGlobal { .. } => format!("{:} = {:};", r1, symbol),
+            Op::StoreGlobal { .. } => format!("{:} = {:};", symbol, r1),
+            _ => format!("{{ uint32_t t = {:}; {:} = {:}; {:} = t; }}", symbol, symbol, r1, r1),
+        }
+    }
+
+    /// Emit intrinsic as a call of a runtime function
+    fn emit_intrinsic(&mut self, name: &str, dst: Option<Reg>, args: &[Value]) -> String {
+        let ret = if dst.is_some() { "uint32_t" } else { "void" };
+        let params = if args.is_empty() { "void".to_string() } else { vec!["uint32_t"; args.len()].join(", ") };
+        let args: Vec<String> = args.iter().map(|&a| value(a)).collect();
+
+        self.declarations.insert(format!("{:} {:}({:});", ret, name, params));
+
+        match dst {
+            Some(r) => format!("{:} = {:}({:});", reg_name(r), name, args.join(", ")),
+            None => format!("{:}({:});", name, args.join(", ")),
+        }
+    }
+
+    /// Emit marker for an instruction which failed to translate
+    fn emit_placeholder(&mut self, text: &str, error: &Error) -> String {
+        let trap = match self.placeholder_helper {
+            Some(ref helper) => {
+                self.declarations.insert(format!("void {:}(void);", helper));
+                format!("{:}();", helper)
+            }
+            None => "__builtin_trap();".to_string(),
+        };
+
+        format!("// untranslated: {:} ({:})\n\t{:}", text, error.cause(), trap)
+    }
+
+    /// Emit one IR operation not involving
+    /// the flags, empty if it needs no code
+    fn emit_op(&mut self, op: &Op) -> String {
+        match *op {
+            Op::Mov { dst, src } => format!("{:} = {:};", reg_name(dst), value(src)),
+            Op::Binary { op, dst, a, b } => self.emit_binary(op, dst, a, b),
+            Op::AddShifted { dst, a, shift, b } =>
+                format!("{:} = ({:} << {:}) + {:};", reg_name(dst), reg_name(a), shift, reg_name(b)),
+            Op::Unary { op, dst, src } => self.emit_unary(op, dst, src),
+            Op::Extract { dst, src, shift, width, signed } => self.emit_extract(dst, src, shift, width, signed),
+            Op::Saturate { dst, src, bits } => self.emit_saturate(dst, src, bits),
+            Op::MulHigh { dst, a, b, signed } => self.emit_mul_high(dst, a, b, signed),
+            Op::Mul16 { dst, a, b, signed } => self.emit_mul16(dst, a, b, signed),
+            Op::Div { dst, a, b, signed, remainder } => self.emit_div(dst, a, b, signed, remainder),
+            Op::FunnelShift { dst, high, low, amount } => self.emit_funnel_shift(dst, high, low, amount),
+            Op::Load { .. } |
+            Op::Store { .. } => self.emit_load_store(op),
+            Op::CompareSwap { reg, base, offset, compare } => self.emit_compare_swap(reg, base, offset, compare),
+            Op::LoadGlobal { .. } |
+            Op::StoreGlobal { .. } |
+            Op::ExchangeGlobal { .. } => self.emit_global(op),
+            Op::Branch { cond: None, target } => format!("goto loc_{:x};", target),
+            Op::LoopBack { target } => self.emit_loop_end(target),
+            Op::Call { ref target, .. } => self.emit_call(target),
+            Op::Intrinsic { ref name, dst, ref args } => self.emit_intrinsic(name, dst, args),
+            Op::Return { windowed: false } => "return (uint64_t)a3 << 32 | a2;".to_string(),
+            Op::Barrier |
+            Op::Nop => String::new(),
+            // The lifter rejects windowed code under call0
+            Op::Entry { .. } |
+            Op::Return { windowed: true } => panic!("Windowed operation in call0 code: {:}", op),
+            Op::Compare { .. } |
+            Op::Select { .. } |
+            Op::Branch { .. } => panic!("Flags operation emitted alone: {:}", op),
+        }
+    }
+
+    /// Emit IR operations of one instruction. A compare is tested
+    /// directly by its readers if they are in the same instruction
+    /// and nothing in between changes the compared values. Two
+    /// selects of one destination on inverse conditions become one
+    /// conditional expression. `flags_live` tells whether later
+    /// instructions read the flags
+    fn emit_ops(&mut self, ops: &[Op], flags_live: bool) -> Vec<String> {
+        let mut lines = Vec::<String>::new();
+        // Compare tested by each flag reader, others read `cmp_a` and `cmp_b`
+        let mut compares = BTreeMap::<usize, (CompareKind, Reg, Value)>::new();
+        let mut n = 0;
+
+        while n < ops.len() {
+            let line = match ops[n] {
+                Op::Compare { kind, a, b } => match flag_readers(ops, n, flags_live) {
+                    Some(readers) => {
+                        let last = readers.last().cloned().unwrap_or(n);
+                        let kept = ops[n + 1 .. last].iter()
+                            .all(|op| op.defs().iter().all(|&d| d != a && Value::Reg(d) != b));
+
+                        if kept {
+                            for r in readers {
+                                compares.insert(r, (kind, a, b));
+                            }
+
+                            String::new()
+                        } else {
+                            self.emit_flags(kind, a, b)
+                        }
+                    }
+                    None => self.emit_flags(kind, a, b),
+                },
+                Op::Select { cond, dst, src } => {
+                    let condition = self.condition(compares.get(&n), cond);
+
+                    match ops.get(n + 1) {
+                        Some(&Op::Select { cond: other, dst: other_dst, src: other_src })
+                                if other_dst == dst && other == cond.inverse() => {
+                            n += 1;
+                            format!("{:} = {:} ? {:} : {:};", reg_name(dst), condition, reg_name(src), reg_name(other_src))
+                        }
+                        _ => format!("if ({:}) {:} = {:};", condition, reg_name(dst), reg_name(src)),
+                    }
+                }
+                Op::Branch { cond: Some(cond), target } =>
+                    format!("if ({:}) goto loc_{:x};", self.condition(compares.get(&n), cond), target),
+                ref op => self.emit_op(op),
+            };
+
+            if !line.is_empty() {
+                lines.push(line);
+            }
+
+            n += 1;
+        }
+
+        lines
+    }
+
+    /// Emit declaration of local variables: registers
+    /// other than the parameters a1..a7
+    fn emit_locals(&self, code: &[Option<Vec<Op>>]) -> String {
+        let mut locals = BTreeSet::<Reg>::new();
+
+        for op in code.iter().filter_map(|ops| ops.as_ref()).flat_map(|ops| ops.iter()) {
+            for r in op.uses().into_iter().chain(op.defs()) {
+                match r {
+                    Reg::Xtensa(1 ..= 7) => {}
+                    _ => { locals.insert(r); }
+                }
+            }
+        }
+
+        let mut names: Vec<String> = locals.into_iter().map(|r| format!("{:} = 0", reg_name(r))).collect();
+
+        if self.flags_used {
+            names.push("cmp_a = 0".to_string());
+            names.push("cmp_b = 0".to_string());
+        }
+
+        if names.is_empty() {
+            return String::new();
+        }
+
+        format!("uint32_t {:};", names.join(", "))
+    }
+
+    pub fn new() -> Translator {
+        Default::default()
+    }
+
+    pub fn with_abi(abi: XtensaAbi) -> Translator {
+        Translator { abi, ..Default::default() }
+    }
+
+    /// Lifts a call0 function to IR and emits a C function:
+    /// xtensa registers become locals declared upfront and
+    /// each instruction a few statements, labelled if
+    /// something branches to it
+    pub fn translate(&mut self, function: &mut Function, objects: &mut ObjectStorage) -> Result<Function> {
+        let mut result = Function::new();
+
+        if self.abi != XtensaAbi::Call0 {
+            return Err(Error::Abi("C output supports call0 ABI only".to_string()));
+        }
+
+        self.referenced_objects.clear();
+        self.flags_used = false;
+
+        let mut lifted = Lifter::new(self.abi, self.special_regs.clone())
+            .lift_function(function, objects, self.lenient)?;
+
+        if self.optimize {
+            optimize(&mut lifted.code, &lifted.cfg);
+        }
+
+        let flags_live = flags_live_out(&lifted.code, &lifted.cfg);
+
+        for (n, instruction) in function.instructions.iter().enumerate() {
+            let mut result_instruction = Instruction::new();
+
+            let lines = match lifted.code[n] {
+                Some(ref ops) => self.emit_ops(ops, flags_live[n]),
+                None => {
+                    let e = lifted.errors[&n].clone();
+                    let mut lines = vec![self.emit_placeholder(&instruction.opcode, &e)];
+
+                    self.placeholders.push(e);
+                    lines.extend(self.emit_ops(&lifted.loop_end_after(n), flags_live[n]));
+
+                    lines
+                }
+            };
+
+            result_instruction.opcode = lines.join("\n\t");
+            result_instruction.offset = instruction.offset;
+            result_instruction.referenced = lifted.cfg.is_branch_target(instruction.offset);
+            result_instruction.arch = InstructionArch::C;
+            result.instructions.push(result_instruction);
+        }
+
+        let locals = self.emit_locals(&lifted.code);
+
+        if !locals.is_empty() {
+            let mut instruction = Instruction::new();
+
+            instruction.opcode = locals;
+            instruction.offset = function.instructions.first().map_or(0, |i| i.offset);
+            instruction.arch = InstructionArch::C;
+            result.instructions.insert(0, instruction);
+        }
+
+        result.name = identifier(&function.name);
+        Ok(result)
+    }
+}
+
+impl Backend for Translator {
+    fn translate(&mut self, function: &mut Function, objects: &mut ObjectStorage) -> Result<Function> {
+        Translator::translate(self, function, objects)
+    }
+
+    fn referenced_objects(&self) -> &BTreeSet<u32> {
+        &self.referenced_objects
+    }
+
+    fn placeholders(&mut self) -> &mut Vec<Error> {
+        &mut self.placeholders
+    }
+
+    fn declarations(&self) -> Vec<String> {
+        self.declarations.iter().cloned().collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use translation::xtensa_c::{Translator, identifier};
+    use translation::xtensa_sr::SrMap;
+    use object_storage::ObjectStorage;
+    use translation::backend::translate_fixture;
+    use function::fixture;
+
+    #[test]
+    fn test_c_basic_1() {
+        let code = [
+            "addi a1, a1, -16", "s32i a0, a1, 12", "l16si a8, a2, 2", "add a2, a8, a3",
+            "s8i a2, a1, 0", "srai a2, a2, 3", "l32i a0, a1, 12", "ret",
+        ];
+
+        assert_eq!(translate_fixture(&mut Translator::new(), &code), vec![
+            "uint32_t a0 = 0, a8 = 0;",
+            "a1 = a1 - 16;",
+            "XT_MEM(uint32_t, a1 + 0xc) = a0;",
+            "a8 = (uint32_t)(int32_t)XT_MEM(int16_t, a2 + 0x2);",
+            "a2 = a8 + a3;",
+            "XT_MEM(uint8_t, a1) = (uint8_t)a2;",
+            "a2 = (uint32_t)((int32_t)a2 >> 3);",
+            "a0 = XT_MEM(uint32_t, a1 + 0xc);",
+            "return (uint64_t)a3 << 32 | a2;",
+        ]);
+    }
+
+    #[test]
+    fn test_c_branch_1() {
+        let code = ["blt a2, a3, 0x100", "bbsi a2, 4, 0x100", "bnall a2, a3, 0x100", "bgeui a2, 65536, 0x100", "bltz a2, 0x100"];
+
+        assert_eq!(translate_fixture(&mut Translator::new(), &code), vec![
+            "if ((int32_t)a2 < (int32_t)a3) goto loc_100;",
+            "if (a2 & 0x10) goto loc_100;",
+            "if (~a2 & a3) goto loc_100;",
+            "if (a2 >= 0x10000) goto loc_100;",
+            "if ((int32_t)a2 < 0) goto loc_100;",
+        ]);
+    }
+
+    #[test]
+    fn test_c_ops_1() {
+        let code = ["ssl a4", "src a2, a3, a2", "extui a2, a3, 4, 8", "moveqz a2, a3, a4", "mulsh a2, a3, a4", "quos a2, a2, a3"];
+
+        assert_eq!(translate_fixture(&mut Translator::new(), &code), vec![
+            "uint32_t sar = 0;",
+            "sar = a4 & 31;\n\tsar = 32 - sar;",
+            "a2 = (uint32_t)(((uint64_t)a3 << 32 | a2) >> sar);",
+            "a2 = (a3 >> 4) & 0xff;",
+            "if (a4 == 0) a2 = a3;",
+            "a2 = (uint32_t)((int64_t)(int32_t)a3 * (int32_t)a4 >> 32);",
+            "a2 = xt_quos(a2, a3);",
+        ]);
+    }
+
+    #[test]
+    fn test_c_special_reg_1() {
+        let mut f = fixture(&["rsr.ps a2", "xsr.excsave1 a3", "loopnez a2, 0x10c", "callx0 a8", "ret"]);
+        let mut objects = ObjectStorage::default();
+        let mut translator = Translator::new();
+
+        translator.special_regs = SrMap::parse("excsave1 slot XT_EXCSAVE1").unwrap();
+
+        let result = translator.translate(&mut f, &mut objects).unwrap();
+        let code: Vec<&str> = result.instructions.iter().map(|i| i.opcode.as_str()).collect();
+
+        assert_eq!(code[1], "a2 = __xt_rsr_ps();");
+        assert_eq!(code[2], "{ uint32_t t = XT_EXCSAVE1; XT_EXCSAVE1 = a3; a3 = t; }");
+        assert_eq!(code[3], "lcount = a2;\n\tif (lcount == 0) goto loc_10c;");
+        assert_eq!(code[4], "{ uint64_t r = xt_callx(a8, a1, a2, a3, a4, a5, a6, a7); \
+            a2 = (uint32_t)r; a3 = (uint32_t)(r >> 32); }\n\tif (--lcount != 0) goto loc_109;");
+        assert!(translator.declarations.contains("uint32_t __xt_rsr_ps(void);"));
+        assert!(translator.declarations.contains("extern uint32_t XT_EXCSAVE1;"));
+
+        assert_eq!(identifier("foo.constprop.0"), "foo_constprop_0");
+        assert_eq!(identifier("0day"), "_0day");
+    }
+
+    #[test]
+    fn test_c_optimize_1() {
+        let mut f = fixture(&["movi a8, 0x1234", "add a2, a2, a8", "mov a9, a2", "beqz a9, 0x112", "blt a2, a3, 0x112", "bge a2, a3, 0x112", "ret"]);
+        let mut objects = ObjectStorage::default();
+        let mut translator = Translator::new();
+
+        translator.optimize = true;
+        let result: Vec<String> = translator.translate(&mut f, &mut objects).unwrap()
+            .instructions.into_iter()
+            .map(|i| i.opcode)
+            .collect();
+
+        assert_eq!(result, vec![
+            "uint32_t cmp_a = 0, cmp_b = 0;",
+            "",
+            "a2 = a2 + 0x1234;",
+            "",
+            "if (a2 == 0) goto loc_112;",
+            "cmp_a = a2; cmp_b = a3;\n\tif ((int32_t)cmp_a < (int32_t)cmp_b) goto loc_112;",
+            "if ((int32_t)cmp_a >= (int32_t)cmp_b) goto loc_112;",
+            "return (uint64_t)a3 << 32 | a2;",
+        ]);
+    }
+}
@@ -0,0 +1,742 @@
+use translation::xtensa_op::{XtensaOpcode, XtensaInstruction, B4CONST, B4CONSTU};
+use translation::xtensa_operand::{XtensaOperand, XtensaOperandKind};
+use error::{Error, Result};
+
+/// Raw instruction word. Narrow instructions
+/// use only the lower 16 bits
+struct Word(u32);
+
+/// Field accessors, named as in the xtensa ISA
+/// reference manual
+impl Word {
+    fn op0(&self) -> u32 { self.0 & 0xf }
+    fn t(&self) -> u32 { (self.0 >> 4) & 0xf }
+    fn s(&self) -> u32 { (self.0 >> 8) & 0xf }
+    fn r(&self) -> u32 { (self.0 >> 12) & 0xf }
+    fn op1(&self) -> u32 { (self.0 >> 16) & 0xf }
+    fn op2(&self) -> u32 { (self.0 >> 20) & 0xf }
+    fn n(&self) -> u32 { (self.0 >> 4) & 0x3 }
+    fn m(&self) -> u32 { (self.0 >> 6) & 0x3 }
+    fn imm8(&self) -> u32 { (self.0 >> 16) & 0xff }
+    fn imm12(&self) -> u32 { (self.0 >> 12) & 0xfff }
+    fn imm16(&self) -> u32 { (self.0 >> 8) & 0xffff }
+    fn offset18(&self) -> u32 { (self.0 >> 6) & 0x3ffff }
+    fn sr(&self) -> u32 { (self.0 >> 8) & 0xff }
+}
+
+fn sign_extend(value: u32, bits: u32) -> i32 {
+    let shift = 32 - bits;
+    ((value << shift) as i32) >> shift
+}
+
+fn reg(r: u32) -> XtensaOperand {
+    XtensaOperand { kind: XtensaOperandKind::Reg(r as u8) }
+}
+
+fn breg(r: u32) -> XtensaOperand {
+    XtensaOperand { kind: XtensaOperandKind::BoolReg(r as u8) }
+}
+
+fn sreg(r: u32) -> XtensaOperand {
+    XtensaOperand { kind: XtensaOperandKind::SpecialReg(r as u8) }
+}
+
+fn ureg(r: u32) -> XtensaOperand {
+    XtensaOperand { kind: XtensaOperandKind::UserReg(r as u8) }
+}
+
+fn imm(i: i32) -> XtensaOperand {
+    XtensaOperand { kind: XtensaOperandKind::Imm(i as u32) }
+}
+
+fn addr(a: u32) -> XtensaOperand {
+    XtensaOperand { kind: XtensaOperandKind::Imm(a) }
+}
+
+fn build(opcode: XtensaOpcode, operands: Vec<XtensaOperand>) -> XtensaInstruction {
+    XtensaInstruction {
+        opcode,
+        operands,
+    }
+}
+
+/// Branch or jump target relative to the instruction address
+fn target(pc: u32, offset: i32) -> u32 {
+    pc.wrapping_add(4).wrapping_add(offset as u32)
+}
+
+/// Returns instruction length in bytes judging by its first byte.
+/// Code density option is assumed to be present
+pub fn length(first: u8) -> usize {
+    match first & 0xf {
+        8 ..= 13 => 2,
+        _ => 3,
+    }
+}
+
+/// Decodes a single instruction located at `address`.
+/// Returns the instruction and its length in bytes
+pub fn decode(data: &[u8], address: u32) -> Result<(XtensaInstruction, usize)> {
+    if data.is_empty() {
+        return Err(Error::Decode { address, bytes: "no data".to_string() });
+    }
+
+    let size = length(data[0]);
+
+    if data.len() < size {
+        let bytes: Vec<String> = data.iter().map(|b| format!("{:02x}", b)).collect();
+        return Err(Error::Decode { address, bytes: format!("{:} (truncated)", bytes.join("")) });
+    }
+
+    let mut value = 0u32;
+
+    for (i, &byte) in data[.. size].iter().enumerate() {
+        value |= (byte as u32) << (8 * i);
+    }
+
+    let w = Word(value);
+
+    let instruction = match w.op0() {
+        0 => decode_qrst(&w),
+        1 => decode_ri16(&w, address),
+        2 => decode_lsai(&w),
+        5 => decode_call(&w, address),
+        6 => decode_si(&w, address),
+        7 => decode_b(&w, address),
+        8 ..= 13 => decode_narrow(&w, address),
+        _ => None,
+    };
+
+    match instruction {
+        Some(i) => Ok((i, size)),
+        None => Err(Error::Decode { address, bytes: format!("{:0w$x}", value, w = size * 2) })
+    }
+}
+
+/// RRR format: QRST opcode space
+fn decode_qrst(w: &Word) -> Option<XtensaInstruction> {
+    match w.op1() {
+        0 => decode_rst0(w),
+        1 => decode_rst1(w),
+        2 => decode_rst2(w),
+        3 => decode_rst3(w),
+        4 | 5 => decode_extui(w),
+        _ => None,
+    }
+}
+
+fn decode_rrr(opcode: XtensaOpcode, w: &Word) -> Option<XtensaInstruction> {
+    Some(build(opcode, vec![reg(w.r()), reg(w.s()), reg(w.t())]))
+}
+
+fn decode_rst0(w: &Word) -> Option<XtensaInstruction> {
+    match w.op2() {
+        0 => decode_st0(w),
+        1 => decode_rrr(XtensaOpcode::And, w),
+        2 => {
+            // or ar, as, as is the canonical form of mov
+            if w.s() == w.t() {
+                Some(build(XtensaOpcode::Mov, vec![reg(w.r()), reg(w.s())]))
+            } else {
+                decode_rrr(XtensaOpcode::Or, w)
+            }
+        }
+        3 => decode_rrr(XtensaOpcode::Xor, w),
+        4 => decode_st1(w),
+        6 => match w.s() {
+            0 => Some(build(XtensaOpcode::Neg, vec![reg(w.r()), reg(w.t())])),
+            1 => Some(build(XtensaOpcode::Abs, vec![reg(w.r()), reg(w.t())])),
+            _ => None,
+        },
+        8 => decode_rrr(XtensaOpcode::Add, w),
+        9 => decode_rrr(XtensaOpcode::Addx2, w),
+        10 => decode_rrr(XtensaOpcode::Addx4, w),
+        11 => decode_rrr(XtensaOpcode::Addx8, w),
+        12 => decode_rrr(XtensaOpcode::Sub, w),
+        13 => decode_rrr(XtensaOpcode::Subx2, w),
+        14 => decode_rrr(XtensaOpcode::Subx4, w),
+        15 => decode_rrr(XtensaOpcode::Subx8, w),
+        _ => None,
+    }
+}
+
+fn decode_st0(w: &Word) -> Option<XtensaInstruction> {
+    match w.r() {
+        0 => decode_callx(w),
+        2 => match w.t() {
+            12 => Some(build(XtensaOpcode::Memw, vec![])),
+            15 => Some(build(XtensaOpcode::Nop, vec![])),
+            _ => None,
+        },
+        6 => Some(build(XtensaOpcode::Rsil, vec![reg(w.t()), imm(w.s() as i32)])),
+        _ => None,
+    }
+}
+
+/// SAR setup
+fn decode_st1(w: &Word) -> Option<XtensaInstruction> {
+    match w.r() {
+        0 => Some(build(XtensaOpcode::Ssr, vec![reg(w.s())])),
+        1 => Some(build(XtensaOpcode::Ssl, vec![reg(w.s())])),
+        2 => Some(build(XtensaOpcode::Ssa8l, vec![reg(w.s())])),
+        3 => Some(build(XtensaOpcode::Ssa8b, vec![reg(w.s())])),
+        4 => {
+            let sa = ((w.t() & 1) << 4) | w.s();
+            Some(build(XtensaOpcode::Ssai, vec![imm(sa as i32)]))
+        }
+        14 => Some(build(XtensaOpcode::Nsa, vec![reg(w.t()), reg(w.s())])),
+        15 => Some(build(XtensaOpcode::Nsau, vec![reg(w.t()), reg(w.s())])),
+        _ => None,
+    }
+}
+
+/// CALLX format: jumps and calls through register
+fn decode_callx(w: &Word) -> Option<XtensaInstruction> {
+    match (w.m(), w.n()) {
+        (2, 0) => Some(build(XtensaOpcode::Ret, vec![])),
+        (2, 1) => Some(build(XtensaOpcode::Retw, vec![])),
+        (2, 2) => Some(build(XtensaOpcode::Jx, vec![reg(w.s())])),
+        (3, 0) => Some(build(XtensaOpcode::Callx0, vec![reg(w.s())])),
+        (3, 1) => Some(build(XtensaOpcode::Callx4, vec![reg(w.s())])),
+        (3, 2) => Some(build(XtensaOpcode::Callx8, vec![reg(w.s())])),
+        (3, 3) => Some(build(XtensaOpcode::Callx12, vec![reg(w.s())])),
+        _ => None,
+    }
+}
+
+fn decode_rst1(w: &Word) -> Option<XtensaInstruction> {
+    match w.op2() {
+        0 | 1 => {
+            let sa = 32 - (((w.op2() & 1) << 4) | w.t());
+            Some(build(XtensaOpcode::Slli, vec![reg(w.r()), reg(w.s()), imm(sa as i32)]))
+        }
+        2 | 3 => {
+            let sa = ((w.op2() & 1) << 4) | w.s();
+            Some(build(XtensaOpcode::Srai, vec![reg(w.r()), reg(w.t()), imm(sa as i32)]))
+        }
+        4 => Some(build(XtensaOpcode::Slri, vec![reg(w.r()), reg(w.t()), imm(w.s() as i32)])),
+        6 => Some(build(XtensaOpcode::Xsr, vec![reg(w.t()), sreg(w.sr())])),
+        8 => decode_rrr(XtensaOpcode::Src, w),
+        9 => Some(build(XtensaOpcode::Srl, vec![reg(w.r()), reg(w.t())])),
+        10 => Some(build(XtensaOpcode::Sll, vec![reg(w.r()), reg(w.s())])),
+        11 => Some(build(XtensaOpcode::Sra, vec![reg(w.r()), reg(w.t())])),
+        12 => decode_rrr(XtensaOpcode::Mul16u, w),
+        13 => decode_rrr(XtensaOpcode::Mul16s, w),
+        _ => None,
+    }
+}
+
+/// MUL32 and DIV32 options
+fn decode_rst2(w: &Word) -> Option<XtensaInstruction> {
+    match w.op2() {
+        8 => decode_rrr(XtensaOpcode::Mull, w),
+        10 => decode_rrr(XtensaOpcode::Muluh, w),
+        11 => decode_rrr(XtensaOpcode::Mulsh, w),
+        12 => decode_rrr(XtensaOpcode::Quou, w),
+        13 => decode_rrr(XtensaOpcode::Quos, w),
+        14 => decode_rrr(XtensaOpcode::Remu, w),
+        15 => decode_rrr(XtensaOpcode::Rems, w),
+        _ => None,
+    }
+}
+
+fn decode_rst3(w: &Word) -> Option<XtensaInstruction> {
+    match w.op2() {
+        0 => Some(build(XtensaOpcode::Rsr, vec![reg(w.t()), sreg(w.sr())])),
+        1 => Some(build(XtensaOpcode::Wsr, vec![reg(w.t()), sreg(w.sr())])),
+        // Bit position is encoded as t + 7
+        2 => Some(build(XtensaOpcode::Sext, vec![reg(w.r()), reg(w.s()), imm(w.t() as i32 + 7)])),
+        3 => Some(build(XtensaOpcode::Clamps, vec![reg(w.r()), reg(w.s()), imm(w.t() as i32 + 7)])),
+        4 => decode_rrr(XtensaOpcode::Min, w),
+        5 => decode_rrr(XtensaOpcode::Max, w),
+        6 => decode_rrr(XtensaOpcode::Minu, w),
+        7 => decode_rrr(XtensaOpcode::Maxu, w),
+        8 => decode_rrr(XtensaOpcode::Moveqz, w),
+        9 => decode_rrr(XtensaOpcode::Movnez, w),
+        10 => decode_rrr(XtensaOpcode::Movltz, w),
+        11 => decode_rrr(XtensaOpcode::Movgez, w),
+        12 => Some(build(XtensaOpcode::Movf, vec![reg(w.r()), reg(w.s()), breg(w.t())])),
+        13 => Some(build(XtensaOpcode::Movt, vec![reg(w.r()), reg(w.s()), breg(w.t())])),
+        14 => Some(build(XtensaOpcode::Rur, vec![reg(w.r()), ureg((w.s() << 4) | w.t())])),
+        15 => Some(build(XtensaOpcode::Wur, vec![reg(w.t()), ureg(w.sr())])),
+        _ => None,
+    }
+}
+
+/// RRR format with shift amount in op1 and s,
+/// mask width - 1 in op2
+fn decode_extui(w: &Word) -> Option<XtensaInstruction> {
+    let shift = ((w.op1() & 1) << 4) | w.s();
+    let width = w.op2() + 1;
+
+    Some(build(XtensaOpcode::Extui, vec![reg(w.r()), reg(w.t()), imm(shift as i32), imm(width as i32)]))
+}
+
+/// RI16 format: l32r
+fn decode_ri16(w: &Word, address: u32) -> Option<XtensaInstruction> {
+    let offset = (0xfffc0000 | (w.imm16() << 2)) as i32;
+    let literal = (address.wrapping_add(3) & !3).wrapping_add(offset as u32);
+
+    Some(build(XtensaOpcode::L32r, vec![reg(w.t()), addr(literal)]))
+}
+
+/// RRI8 format: loads, stores and immediate arithmetic
+fn decode_lsai(w: &Word) -> Option<XtensaInstruction> {
+    let rri8 = |opcode, scale: u32| {
+        Some(build(opcode, vec![reg(w.t()), reg(w.s()), imm((w.imm8() << scale) as i32)]))
+    };
+
+    match w.r() {
+        0 => rri8(XtensaOpcode::L8ui, 0),
+        1 => rri8(XtensaOpcode::L16ui, 1),
+        2 => rri8(XtensaOpcode::L32i, 2),
+        4 => rri8(XtensaOpcode::S8i, 0),
+        5 => rri8(XtensaOpcode::S16i, 1),
+        6 => rri8(XtensaOpcode::S32i, 2),
+        9 => rri8(XtensaOpcode::L16si, 1),
+        10 => {
+            let value = sign_extend((w.s() << 8) | w.imm8(), 12);
+            Some(build(XtensaOpcode::Movi, vec![reg(w.t()), imm(value)]))
+        }
+        11 => rri8(XtensaOpcode::L32ai, 2),
+        12 => {
+            let value = sign_extend(w.imm8(), 8);
+            Some(build(XtensaOpcode::Addi, vec![reg(w.t()), reg(w.s()), imm(value)]))
+        }
+        13 => {
+            let value = sign_extend(w.imm8(), 8) << 8;
+            Some(build(XtensaOpcode::Addmi, vec![reg(w.t()), reg(w.s()), imm(value)]))
+        }
+        14 => rri8(XtensaOpcode::S32c1i, 2),
+        15 => rri8(XtensaOpcode::S32ri, 2),
+        _ => None,
+    }
+}
+
+/// CALL format: call0, call4, call8 and call12
+fn decode_call(w: &Word, address: u32) -> Option<XtensaInstruction> {
+    let offset = sign_extend(w.offset18(), 18) << 2;
+    let destination = (address & !3).wrapping_add(4).wrapping_add(offset as u32);
+
+    let opcode = match w.n() {
+        0 => XtensaOpcode::Call0,
+        1 => XtensaOpcode::Call4,
+        2 => XtensaOpcode::Call8,
+        _ => XtensaOpcode::Call12,
+    };
+
+    Some(build(opcode, vec![addr(destination)]))
+}
+
+/// SI opcode space: jumps and BRI8/BRI12 branches
+fn decode_si(w: &Word, address: u32) -> Option<XtensaInstruction> {
+    match w.n() {
+        0 => {
+            let offset = sign_extend(w.offset18(), 18);
+            Some(build(XtensaOpcode::Jmp, vec![addr(target(address, offset))]))
+        }
+        1 => decode_bri12(w, address),
+        _ => decode_bri8(w, address),
+    }
+}
+
+/// BRI12 format: compare with zero and branch
+fn decode_bri12(w: &Word, address: u32) -> Option<XtensaInstruction> {
+    let destination = target(address, sign_extend(w.imm12(), 12));
+    let opcode = match w.m() {
+        0 => XtensaOpcode::Beqz,
+        1 => XtensaOpcode::Bnez,
+        2 => XtensaOpcode::Bltz,
+        _ => XtensaOpcode::Bgez,
+    };
+
+    Some(build(opcode, vec![reg(w.s()), addr(destination)]))
+}
+
+/// BRI12 format with n = 3, m = 0: entry
+fn decode_entry(w: &Word) -> Option<XtensaInstruction> {
+    Some(build(XtensaOpcode::Entry, vec![reg(w.s()), imm((w.imm12() << 3) as i32)]))
+}
+
+/// BRI8 format: compare with B4CONST/B4CONSTU and branch
+fn decode_bri8(w: &Word, address: u32) -> Option<XtensaInstruction> {
+    if w.n() == 3 && w.m() == 0 {
+        return decode_entry(w);
+    }
+
+    if w.n() == 3 && w.m() == 1 {
+        return decode_loop(w, address);
+    }
+
+    let destination = target(address, sign_extend(w.imm8(), 8));
+    let constant = B4CONST[w.r() as usize];
+    let constant_u = B4CONSTU[w.r() as usize] as i32;
+
+    let (opcode, value) = match (w.n(), w.m()) {
+        (2, 0) => (XtensaOpcode::Beqi, constant),
+        (2, 1) => (XtensaOpcode::Bnei, constant),
+        (2, 2) => (XtensaOpcode::Blti, constant),
+        (2, 3) => (XtensaOpcode::Bgei, constant),
+        (3, 2) => (XtensaOpcode::Bltui, constant_u),
+        (3, 3) => (XtensaOpcode::Bgeui, constant_u),
+        _ => return None,
+    };
+
+    Some(build(opcode, vec![reg(w.s()), imm(value), addr(destination)]))
+}
+
+/// BRI8 format with n = 3, m = 1: zero-overhead loops.
+/// Loop end offset is unsigned
+fn decode_loop(w: &Word, address: u32) -> Option<XtensaInstruction> {
+    let opcode = match w.r() {
+        8 => XtensaOpcode::Loop,
+        9 => XtensaOpcode::Loopnez,
+        10 => XtensaOpcode::Loopgtz,
+        _ => return None,
+    };
+
+    Some(build(opcode, vec![reg(w.s()), addr(target(address, w.imm8() as i32))]))
+}
+
+/// RRI8 format: compare registers and branch
+fn decode_b(w: &Word, address: u32) -> Option<XtensaInstruction> {
+    let destination = target(address, sign_extend(w.imm8(), 8));
+    let bit = w.t() | ((w.r() & 1) << 4);
+
+    let opcode = match w.r() {
+        6 | 7 => return Some(build(XtensaOpcode::Bbci, vec![reg(w.s()), imm(bit as i32), addr(destination)])),
+        14 | 15 => return Some(build(XtensaOpcode::Bbsi, vec![reg(w.s()), imm(bit as i32), addr(destination)])),
+        0 => XtensaOpcode::Bnone,
+        1 => XtensaOpcode::Beq,
+        2 => XtensaOpcode::Blt,
+        3 => XtensaOpcode::Bltu,
+        4 => XtensaOpcode::Ball,
+        5 => XtensaOpcode::Bbc,
+        8 => XtensaOpcode::Bany,
+        9 => XtensaOpcode::Bne,
+        10 => XtensaOpcode::Bge,
+        11 => XtensaOpcode::Bgeu,
+        12 => XtensaOpcode::Bnall,
+        _ => XtensaOpcode::Bbs,
+    };
+
+    Some(build(opcode, vec![reg(w.s()), reg(w.t()), addr(destination)]))
+}
+
+/// Narrow RRRN, RI7 and RI6 formats
+fn decode_narrow(w: &Word, address: u32) -> Option<XtensaInstruction> {
+    match w.op0() {
+        8 => Some(build(XtensaOpcode::L32i, vec![reg(w.t()), reg(w.s()), imm((w.r() << 2) as i32)])),
+        9 => Some(build(XtensaOpcode::S32i, vec![reg(w.t()), reg(w.s()), imm((w.r() << 2) as i32)])),
+        10 => decode_rrr(XtensaOpcode::Add, w),
+        11 => {
+            let value = if w.t() == 0 { -1 } else { w.t() as i32 };
+            Some(build(XtensaOpcode::Addi, vec![reg(w.r()), reg(w.s()), imm(value)]))
+        }
+        12 => decode_ri7(w, address),
+        13 => decode_st3(w),
+        _ => None,
+    }
+}
+
+/// RI7 format: movi.n, RI6 format: beqz.n and bnez.n
+fn decode_ri7(w: &Word, address: u32) -> Option<XtensaInstruction> {
+    if w.t() & 0x8 != 0 {
+        let offset = ((w.t() & 0x3) << 4) | w.r();
+        let opcode = if w.t() & 0x4 == 0 { XtensaOpcode::Beqz } else { XtensaOpcode::Bnez };
+
+        return Some(build(opcode, vec![reg(w.s()), addr(target(address, offset as i32))]));
+    }
+
+    let value = ((w.t() & 0x7) << 4) | w.r();
+    let value = if value >= 96 { value as i32 - 128 } else { value as i32 };
+
+    Some(build(XtensaOpcode::Movi, vec![reg(w.s()), imm(value)]))
+}
+
+fn decode_st3(w: &Word) -> Option<XtensaInstruction> {
+    match w.r() {
+        0 => Some(build(XtensaOpcode::Mov, vec![reg(w.t()), reg(w.s())])),
+        15 => match w.t() {
+            0 => Some(build(XtensaOpcode::Ret, vec![])),
+            1 => Some(build(XtensaOpcode::Retw, vec![])),
+            3 => Some(build(XtensaOpcode::Nop, vec![])),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use translation::xtensa_decode::decode;
+    use translation::xtensa_op::{XtensaInstruction, XtensaOpcode};
+    use translation::xtensa_operand::{XtensaOperand, XtensaOperandKind};
+
+    #[test]
+    fn test_decode_1() {
+        assert!(decode(&[0x62, 0x22], 0).is_err());
+    }
+
+    #[test]
+    fn test_decode_2() {
+        let (i, size) = decode(&[0x0d, 0xf0], 0).unwrap();
+
+        assert_eq!(size, 2);
+        assert!(i.opcode == XtensaOpcode::Ret);
+
+        let (i, size) = decode(&[0x80, 0x00, 0x00], 0).unwrap();
+
+        assert_eq!(size, 3);
+        assert!(i.opcode == XtensaOpcode::Ret);
+    }
+
+    #[test]
+    fn test_decode_3() {
+        let (i, _) = decode(&[0x62, 0x22, 0xc0], 0).unwrap();
+
+        assert!(i == XtensaInstruction {
+            opcode: XtensaOpcode::L32i,
+            operands: vec![
+                XtensaOperand { kind: XtensaOperandKind::Reg(6) },
+                XtensaOperand { kind: XtensaOperandKind::Reg(2) },
+                XtensaOperand { kind: XtensaOperandKind::Imm(0x300) },
+            ],
+        });
+    }
+
+    #[test]
+    fn test_decode_4() {
+        let (i, _) = decode(&[0x91, 0xfe, 0xff], 0x40224a30).unwrap();
+
+        assert!(i == XtensaInstruction {
+            opcode: XtensaOpcode::L32r,
+            operands: vec![
+                XtensaOperand { kind: XtensaOperandKind::Reg(9) },
+                XtensaOperand { kind: XtensaOperandKind::Imm(0x40224a28) },
+            ],
+        });
+
+        let (i, _) = decode(&[0xc5, 0xff, 0x00], 0x1000).unwrap();
+
+        assert!(i == XtensaInstruction {
+            opcode: XtensaOpcode::Call0,
+            operands: vec![
+                XtensaOperand { kind: XtensaOperandKind::Imm(0x2000) },
+            ],
+        });
+    }
+
+    #[test]
+    fn test_decode_5() {
+        let (i, _) = decode(&[0x97, 0x76, 0x04], 0x1000).unwrap();
+
+        assert!(i == XtensaInstruction {
+            opcode: XtensaOpcode::Bbci,
+            operands: vec![
+                XtensaOperand { kind: XtensaOperandKind::Reg(6) },
+                XtensaOperand { kind: XtensaOperandKind::Imm(25) },
+                XtensaOperand { kind: XtensaOperandKind::Imm(0x1008) },
+            ],
+        });
+
+        let (i, _) = decode(&[0x00, 0x85, 0x11], 0).unwrap();
+
+        assert!(i == XtensaInstruction {
+            opcode: XtensaOpcode::Slli,
+            operands: vec![
+                XtensaOperand { kind: XtensaOperandKind::Reg(8) },
+                XtensaOperand { kind: XtensaOperandKind::Reg(5) },
+                XtensaOperand { kind: XtensaOperandKind::Imm(16) },
+            ],
+        });
+    }
+
+    #[test]
+    fn test_decode_6() {
+        let (i, size) = decode(&[0x7c, 0xf2], 0).unwrap();
+
+        assert_eq!(size, 2);
+        assert!(i == XtensaInstruction {
+            opcode: XtensaOpcode::Movi,
+            operands: vec![
+                XtensaOperand { kind: XtensaOperandKind::Reg(2) },
+                XtensaOperand { kind: XtensaOperandKind::Imm(-1i32 as u32) },
+            ],
+        });
+    }
+
+    #[test]
+    fn test_decode_8() {
+        // entry a1, 32
+        let (i, _) = decode(&[0x36, 0x41, 0x00], 0).unwrap();
+
+        assert!(i == XtensaInstruction {
+            opcode: XtensaOpcode::Entry,
+            operands: vec![
+                XtensaOperand { kind: XtensaOperandKind::Reg(1) },
+                XtensaOperand { kind: XtensaOperandKind::Imm(32) },
+            ],
+        });
+
+        let (i, _) = decode(&[0x1d, 0xf0], 0).unwrap();
+        assert!(i.opcode == XtensaOpcode::Retw);
+
+        let (i, _) = decode(&[0xe0, 0x08, 0x00], 0).unwrap();
+        assert!(i.opcode == XtensaOpcode::Callx8);
+    }
+
+    #[test]
+    fn test_decode_7() {
+        // bnez.n a2, +0x12
+        let (i, size) = decode(&[0xcc, 0xe2], 0x1000).unwrap();
+
+        assert_eq!(size, 2);
+        assert!(i == XtensaInstruction {
+            opcode: XtensaOpcode::Bnez,
+            operands: vec![
+                XtensaOperand { kind: XtensaOperandKind::Reg(2) },
+                XtensaOperand { kind: XtensaOperandKind::Imm(0x1012) },
+            ],
+        });
+
+        // bgeui a3, 32768, -4
+        let (i, _) = decode(&[0xf6, 0x03, 0xfc], 0x1000).unwrap();
+
+        assert!(i == XtensaInstruction {
+            opcode: XtensaOpcode::Bgeui,
+            operands: vec![
+                XtensaOperand { kind: XtensaOperandKind::Reg(3) },
+                XtensaOperand { kind: XtensaOperandKind::Imm(32768) },
+                XtensaOperand { kind: XtensaOperandKind::Imm(0x1000) },
+            ],
+        });
+
+        // bne a2, a3, +8
+        let (i, _) = decode(&[0x37, 0x92, 0x08], 0x1000).unwrap();
+
+        assert!(i == XtensaInstruction {
+            opcode: XtensaOpcode::Bne,
+            operands: vec![
+                XtensaOperand { kind: XtensaOperandKind::Reg(2) },
+                XtensaOperand { kind: XtensaOperandKind::Reg(3) },
+                XtensaOperand { kind: XtensaOperandKind::Imm(0x100c) },
+            ],
+        });
+    }
+
+    #[test]
+    fn test_decode_9() {
+        let (i, _) = decode(&[0x00, 0x48, 0x40], 0).unwrap();
+        assert_eq!(i.to_string(), "ssai 8");
+
+        let (i, _) = decode(&[0x10, 0x43, 0x40], 0).unwrap();
+        assert_eq!(i.to_string(), "ssai 19");
+
+        let (i, _) = decode(&[0x00, 0x13, 0x40], 0).unwrap();
+        assert_eq!(i.to_string(), "ssl a3");
+
+        let (i, _) = decode(&[0x40, 0x23, 0x81], 0).unwrap();
+        assert_eq!(i.to_string(), "src a2, a3, a4");
+
+        let (i, _) = decode(&[0x00, 0x23, 0xa1], 0).unwrap();
+        assert_eq!(i.to_string(), "sll a2, a3");
+
+        let (i, _) = decode(&[0x50, 0x20, 0xb1], 0).unwrap();
+        assert_eq!(i.to_string(), "sra a2, a5");
+    }
+
+    #[test]
+    fn test_decode_10() {
+        let (i, _) = decode(&[0x30, 0x20, 0x75], 0).unwrap();
+        assert_eq!(i.to_string(), "extui a2, a3, 16, 8");
+
+        let (i, _) = decode(&[0x00, 0x23, 0x23], 0).unwrap();
+        assert_eq!(i.to_string(), "sext a2, a3, 7");
+
+        let (i, _) = decode(&[0x20, 0xf3, 0x40], 0).unwrap();
+        assert_eq!(i.to_string(), "nsau a2, a3");
+
+        let (i, _) = decode(&[0x40, 0x23, 0x43], 0).unwrap();
+        assert_eq!(i.to_string(), "min a2, a3, a4");
+    }
+
+    #[test]
+    fn test_decode_11() {
+        let (i, _) = decode(&[0x40, 0x23, 0xc1], 0).unwrap();
+        assert_eq!(i.to_string(), "mul16u a2, a3, a4");
+
+        let (i, _) = decode(&[0x40, 0x23, 0x82], 0).unwrap();
+        assert_eq!(i.to_string(), "mull a2, a3, a4");
+
+        let (i, _) = decode(&[0x40, 0x23, 0xf2], 0).unwrap();
+        assert_eq!(i.to_string(), "rems a2, a3, a4");
+    }
+
+    #[test]
+    fn test_decode_12() {
+        let (i, _) = decode(&[0x40, 0x23, 0x83], 0).unwrap();
+        assert_eq!(i.to_string(), "moveqz a2, a3, a4");
+
+        let (i, _) = decode(&[0x10, 0x23, 0xd3], 0).unwrap();
+        assert_eq!(i.to_string(), "movt a2, a3, b1");
+    }
+
+    #[test]
+    fn test_decode_13() {
+        // s16i a7, a2, 0x1fe
+        let (i, _) = decode(&[0x72, 0x52, 0xff], 0).unwrap();
+        assert_eq!(i.to_string(), "s16i a7, a2, 510");
+
+        // addmi a2, a1, -256
+        let (i, _) = decode(&[0x22, 0xd1, 0xff], 0).unwrap();
+        assert_eq!(i.to_string(), "addmi a2, a1, -256");
+
+        let (i, _) = decode(&[0x72, 0xe2, 0x01], 0).unwrap();
+        assert_eq!(i.to_string(), "s32c1i a7, a2, 4");
+    }
+
+    #[test]
+    fn test_decode_14() {
+        // loopnez a2, +0x10
+        let (i, _) = decode(&[0x76, 0x92, 0x10], 0x1000).unwrap();
+
+        assert!(i == XtensaInstruction {
+            opcode: XtensaOpcode::Loopnez,
+            operands: vec![
+                XtensaOperand { kind: XtensaOperandKind::Reg(2) },
+                XtensaOperand { kind: XtensaOperandKind::Imm(0x1014) },
+            ],
+        });
+    }
+
+    #[test]
+    fn test_decode_15() {
+        let text = |bytes: &[u8]| decode(bytes, 0).unwrap().0.to_string();
+
+        assert_eq!(text(&[0x20, 0xe6, 0x03]), "rsr a2, ps");
+        assert_eq!(text(&[0x20, 0xe4, 0x13]), "wsr a2, intenable");
+        assert_eq!(text(&[0x20, 0xd1, 0x61]), "xsr a2, excsave1");
+        assert_eq!(text(&[0x20, 0x63, 0x00]), "rsil a2, 3");
+        assert_eq!(text(&[0x70, 0x2e, 0xe3]), "rur a2, threadptr");
+        assert_eq!(text(&[0x20, 0xe7, 0xf3]), "wur a2, threadptr");
+    }
+    #[test]
+    fn test_decode_16() {
+        let text = |bytes: &[u8]| decode(bytes, 0).unwrap().0.to_string();
+
+        assert_eq!(text(&[0x40, 0x23, 0x30]), "xor a2, a3, a4");
+        assert_eq!(text(&[0x40, 0x20, 0x60]), "neg a2, a4");
+        assert_eq!(text(&[0x40, 0x21, 0x60]), "abs a2, a4");
+        assert_eq!(text(&[0x40, 0x23, 0xd0]), "subx2 a2, a3, a4");
+        assert_eq!(text(&[0x40, 0x23, 0xe0]), "subx4 a2, a3, a4");
+        assert_eq!(text(&[0x40, 0x23, 0xf0]), "subx8 a2, a3, a4");
+        assert!(decode(&[0x40, 0x22, 0x60], 0).is_err());
+
+        let (i, _) = decode(&[0xa0, 0x03, 0x00], 0).unwrap();
+
+        assert_eq!(i.to_string(), "jx a3");
+        assert!(i.is_terminator());
+        assert_eq!(i.uses(), vec![3]);
+    }
+}
@@ -0,0 +1,877 @@
+use std::vec::Vec;
+use std::collections::{BTreeSet, BTreeMap};
+
+use object_storage::{ObjectKind, ObjectStorage};
+use function::Function;
+use cfg::Cfg;
+use assembly::Operand;
+use translation::xtensa_op::{XtensaOpcode, XtensaInstruction, SAR, BR, SCOMPARE1, LCOUNT};
+use translation::xtensa_operand::XtensaOperandKind;
+use translation::xtensa_sr::{SrMap, SrAccess, special_reg_name, user_reg_name};
+use translation::xtensa_abi::XtensaAbi;
+use translation::ir::{Op, Reg, Value, BinOp, UnaryOp, Width, CompareKind, Cond, CallTarget};
+use error::{Error, Result};
+
+/// Lifts xtensa instructions of one function to IR
+#[derive(Default)]
+pub struct Lifter {
+    pub abi: XtensaAbi,
+    /// How special and user registers of the target are accessed
+    pub special_regs: SrMap,
+    /// Body end addresses of zero-overhead loops in the function
+    pub loop_ends: BTreeSet<u32>,
+    /// SAR value set by ssai, if known at the current instruction.
+    /// Only valid along straight-line code, the caller resets it
+    /// at branch targets
+    pub sar: Option<u32>,
+}
+
+/// Function lifted to IR, what the backends emit from
+pub struct LiftedFunction {
+    /// Parsed instructions, instructions which failed
+    /// to parse are defaulted
+    pub instructions: Vec<XtensaInstruction>,
+    pub offsets: Vec<u32>,
+    pub cfg: Cfg,
+    /// IR of each instruction, None where lifting failed
+    pub code: Vec<Option<Vec<Op>>>,
+    /// Errors of the instructions without IR
+    pub errors: BTreeMap<usize, Error>,
+}
+
+impl LiftedFunction {
+    /// Replaces the IR of instruction `n` with an error,
+    /// fails unless `lenient`
+    pub fn reject(&mut self, n: usize, error: Error, lenient: bool) -> Result<()> {
+        if !lenient {
+            return Err(error);
+        }
+
+        self.code[n] = None;
+        self.errors.insert(n, error);
+        Ok(())
+    }
+
+    /// Loop back edge following instruction `n` if it ends
+    /// the body of a zero-overhead loop. Lifted instructions
+    /// already have it, a placeholder needs it added
+    pub fn loop_end_after(&self, n: usize) -> Vec<Op> {
+        match self.cfg.loops.iter().find(|l| l.end == n + 1) {
+            Some(l) if !self.instructions[n].is_terminator() => vec![Op::LoopBack { target: self.offsets[l.start + 1] }],
+            _ => Vec::new(),
+        }
+    }
+}
+
+fn reg(xtensa_reg: u8) -> Reg {
+    Reg::Xtensa(xtensa_reg)
+}
+
+fn value(xtensa_reg: u8) -> Value {
+    Value::Reg(Reg::Xtensa(xtensa_reg))
+}
+
+fn operand_reg(instruction: &XtensaInstruction, n: usize) -> Reg {
+    reg(instruction.operands[n].get_reg())
+}
+
+fn operand_imm(instruction: &XtensaInstruction, n: usize) -> i32 {
+    instruction.operands[n].get_imm()
+}
+
+/// Bit of a boolean register operand in BR
+fn operand_bool_mask(instruction: &XtensaInstruction, n: usize) -> i32 {
+    match instruction.operands[n].kind {
+        XtensaOperandKind::BoolReg(b) => 1 << b,
+        _ => panic!("called operand_bool_mask on non-boolean operand")
+    }
+}
+
+/// Flags for a compare and branch
+fn compare_branch(kind: CompareKind, a: Reg, b: Value, cond: Cond, target: i32) -> Vec<Op> {
+    vec![
+        Op::Compare { kind, a, b },
+        Op::Branch { cond: Some(cond), target: target as u32 },
+    ]
+}
+
+impl Lifter {
+    pub fn new(abi: XtensaAbi, special_regs: SrMap) -> Lifter {
+        Lifter { abi, special_regs, ..Default::default() }
+    }
+
+    fn require_windowed(&self, instruction: &XtensaInstruction) -> Result<()> {
+        if self.abi != XtensaAbi::Windowed {
+            return Err(Error::Abi(format!("{:} requires windowed ABI", instruction.opcode.mnemonic())));
+        }
+
+        Ok(())
+    }
+
+    fn lift_binary(&self, instruction: &XtensaInstruction) -> Vec<Op> {
+        let op = match instruction.opcode {
+            XtensaOpcode::Add | XtensaOpcode::Addi | XtensaOpcode::Addmi => BinOp::Add,
+            XtensaOpcode::Sub => BinOp::Sub,
+            XtensaOpcode::And => BinOp::And,
+            XtensaOpcode::Or => BinOp::Or,
+            XtensaOpcode::Xor => BinOp::Xor,
+            XtensaOpcode::Mull => BinOp::Mul,
+            XtensaOpcode::Slli => BinOp::Shl,
+            XtensaOpcode::Slri => BinOp::Lsr,
+            XtensaOpcode::Srai => BinOp::Asr,
+            _ => panic!()
+        };
+
+        let b = match instruction.operands[2].kind {
+            XtensaOperandKind::Imm(i) => Value::Imm(i as i32),
+            _ => Value::Reg(operand_reg(instruction, 2)),
+        };
+
+        vec![Op::Binary { op, dst: operand_reg(instruction, 0), a: Value::Reg(operand_reg(instruction, 1)), b }]
+    }
+
+    fn lift_load_store(&self, instruction: &XtensaInstruction) -> Vec<Op> {
+        let r = operand_reg(instruction, 0);
+        let base = operand_reg(instruction, 1);
+        let offset = operand_imm(instruction, 2) as u32;
+
+        // Width, signedness, ordering
+        let load = |width, signed, ordered| Op::Load {
+            dst: r, base, offset, width, signed, ordered
+        };
+        let store = |width, ordered| Op::Store {
+            src: r, base, offset, width, ordered
+        };
+
+        vec![match instruction.opcode {
+            XtensaOpcode::L32i => load(Width::Word, false, false),
+            XtensaOpcode::L32ai => load(Width::Word, false, true),
+            XtensaOpcode::L16ui => load(Width::Half, false, false),
+            XtensaOpcode::L16si => load(Width::Half, true, false),
+            XtensaOpcode::L8ui => load(Width::Byte, false, false),
+            XtensaOpcode::S32i => store(Width::Word, false),
+            XtensaOpcode::S32ri => store(Width::Word, true),
+            XtensaOpcode::S16i => store(Width::Half, false),
+            XtensaOpcode::S8i => store(Width::Byte, false),
+            XtensaOpcode::S32c1i => Op::CompareSwap { reg: r, base, offset, compare: reg(SCOMPARE1) },
+            _ => panic!()
+        }]
+    }
+
+    fn lift_branch(&self, instruction: &XtensaInstruction) -> Vec<Op> {
+        if instruction.opcode == XtensaOpcode::Jmp {
+            return vec![Op::Branch { cond: None, target: operand_imm(instruction, 0) as u32 }];
+        }
+
+        let r1 = operand_reg(instruction, 0);
+        let reg_cond = |kind, cond| compare_branch(kind, r1, Value::Reg(operand_reg(instruction, 1)),
+            cond, operand_imm(instruction, 2));
+        let zero_cond = |cond| compare_branch(CompareKind::Cmp, r1, Value::Imm(0), cond, operand_imm(instruction, 1));
+        let imm_cond = |kind, value, cond| compare_branch(kind, r1, Value::Imm(value), cond, operand_imm(instruction, 2));
+
+        match instruction.opcode {
+            XtensaOpcode::Beq => reg_cond(CompareKind::Cmp, Cond::Eq),
+            XtensaOpcode::Bne => reg_cond(CompareKind::Cmp, Cond::Ne),
+            XtensaOpcode::Blt => reg_cond(CompareKind::Cmp, Cond::Lt),
+            XtensaOpcode::Bge => reg_cond(CompareKind::Cmp, Cond::Ge),
+            XtensaOpcode::Bltu => reg_cond(CompareKind::Cmp, Cond::Ltu),
+            XtensaOpcode::Bgeu => reg_cond(CompareKind::Cmp, Cond::Geu),
+            XtensaOpcode::Bnone => reg_cond(CompareKind::Test, Cond::Eq),
+            XtensaOpcode::Bany => reg_cond(CompareKind::Test, Cond::Ne),
+            // All bits of the mask in the second operand are set
+            XtensaOpcode::Ball |
+            XtensaOpcode::Bnall => {
+                let cond = if instruction.opcode == XtensaOpcode::Ball { Cond::Eq } else { Cond::Ne };
+                compare_branch(CompareKind::TestClear, operand_reg(instruction, 1), Value::Reg(r1),
+                    cond, operand_imm(instruction, 2))
+            }
+            XtensaOpcode::Beqz => zero_cond(Cond::Eq),
+            XtensaOpcode::Bnez => zero_cond(Cond::Ne),
+            XtensaOpcode::Bltz => zero_cond(Cond::Lt),
+            XtensaOpcode::Bgez => zero_cond(Cond::Ge),
+            XtensaOpcode::Beqi => imm_cond(CompareKind::Cmp, operand_imm(instruction, 1), Cond::Eq),
+            XtensaOpcode::Bnei => imm_cond(CompareKind::Cmp, operand_imm(instruction, 1), Cond::Ne),
+            XtensaOpcode::Blti => imm_cond(CompareKind::Cmp, operand_imm(instruction, 1), Cond::Lt),
+            XtensaOpcode::Bgei => imm_cond(CompareKind::Cmp, operand_imm(instruction, 1), Cond::Ge),
+            XtensaOpcode::Bltui => imm_cond(CompareKind::Cmp, operand_imm(instruction, 1), Cond::Ltu),
+            XtensaOpcode::Bgeui => imm_cond(CompareKind::Cmp, operand_imm(instruction, 1), Cond::Geu),
+            XtensaOpcode::Bbci => imm_cond(CompareKind::Test, 1 << operand_imm(instruction, 1), Cond::Eq),
+            XtensaOpcode::Bbsi => imm_cond(CompareKind::Test, 1 << operand_imm(instruction, 1), Cond::Ne),
+            // Bit number is taken modulo 32
+            XtensaOpcode::Bbc |
+            XtensaOpcode::Bbs => {
+                let t = Reg::Temp(0);
+                let cond = if instruction.opcode == XtensaOpcode::Bbc { Cond::Eq } else { Cond::Ne };
+                let mut ops = vec![
+                    Op::Binary { op: BinOp::And, dst: t, a: Value::Reg(operand_reg(instruction, 1)), b: Value::Imm(31) },
+                    Op::Binary { op: BinOp::Lsr, dst: t, a: Value::Reg(r1), b: Value::Reg(t) },
+                ];
+
+                ops.extend(compare_branch(CompareKind::Test, t, Value::Imm(1), cond, operand_imm(instruction, 2)));
+                ops
+            }
+            _ => panic!()
+        }
+    }
+
+    /// l32r reads read-only memory, so the literal is lifted as a constant
+    fn lift_load_relative(&self, instruction: &XtensaInstruction, objects: &ObjectStorage) -> Result<Vec<Op>> {
+        let address = operand_imm(instruction, 1) as u32;
+        let data = match objects.read_u32(address) {
+            Some(d) => d,
+            None => return Err(Error::Unresolved(address))
+        };
+
+        Ok(vec![Op::Mov { dst: operand_reg(instruction, 0), src: Value::Imm(data as i32) }])
+    }
+
+    /// Resolves call target to a function
+    fn call_target(&self, instruction: &XtensaInstruction, objects: &ObjectStorage) -> Result<CallTarget> {
+        let jt = operand_imm(instruction, 0) as u32;
+        let object = match objects.get_object(jt) {
+            Some(o) => o,
+            None => return Err(Error::Unresolved(jt))
+        };
+
+        match object.kind {
+            ObjectKind::Function => {},
+            _ => return Err(Error::Unsupported(format!("call to non-function {:}", object.name)))
+        }
+
+        Ok(CallTarget::Function { name: object.name.clone(), address: object.address })
+    }
+
+    fn lift_call(&mut self, instruction: &XtensaInstruction, objects: &ObjectStorage) -> Result<Vec<Op>> {
+        let rotation = match instruction.opcode {
+            XtensaOpcode::Call0 | XtensaOpcode::Callx0 => 0,
+            XtensaOpcode::Call4 | XtensaOpcode::Callx4 => 4,
+            XtensaOpcode::Call8 | XtensaOpcode::Callx8 => 8,
+            XtensaOpcode::Call12 | XtensaOpcode::Callx12 => 12,
+            _ => panic!()
+        };
+
+        if rotation != 0 {
+            self.require_windowed(instruction)?;
+        }
+
+        let target = match instruction.operands[0].kind {
+            XtensaOperandKind::Reg(r) => CallTarget::Reg(reg(r)),
+            _ => self.call_target(instruction, objects)?,
+        };
+
+        // Callees don't preserve SAR
+        self.sar = None;
+
+        Ok(vec![Op::Call { target, rotation }])
+    }
+
+    fn lift_entry(&self, instruction: &XtensaInstruction) -> Result<Vec<Op>> {
+        self.require_windowed(instruction)?;
+
+        let r1 = instruction.operands[0].get_reg();
+
+        if r1 != 1 {
+            return Err(Error::Unsupported(format!("entry with stack pointer a{:}", r1)));
+        }
+
+        Ok(vec![Op::Entry { frame: operand_imm(instruction, 1) as u32 }])
+    }
+
+    /// SAR holds the right shift amount, ssai also makes it known
+    fn lift_set_sar(&mut self, instruction: &XtensaInstruction) -> Vec<Op> {
+        let sar = reg(SAR);
+
+        if instruction.opcode == XtensaOpcode::Ssai {
+            let amount = operand_imm(instruction, 0) & 31;
+
+            self.sar = Some(amount as u32);
+            return vec![Op::Mov { dst: sar, src: Value::Imm(amount) }];
+        }
+
+        let r1 = Value::Reg(operand_reg(instruction, 0));
+        let binary = |op, a, b| Op::Binary { op, dst: sar, a, b };
+        let to_left = binary(BinOp::Sub, Value::Imm(32), Value::Reg(sar));
+
+        self.sar = None;
+
+        match instruction.opcode {
+            XtensaOpcode::Ssr => vec![binary(BinOp::And, r1, Value::Imm(31))],
+            XtensaOpcode::Ssl => vec![binary(BinOp::And, r1, Value::Imm(31)), to_left],
+            XtensaOpcode::Ssa8l => vec![
+                binary(BinOp::And, r1, Value::Imm(3)),
+                binary(BinOp::Shl, Value::Reg(sar), Value::Imm(3)),
+            ],
+            XtensaOpcode::Ssa8b => vec![
+                binary(BinOp::And, r1, Value::Imm(3)),
+                binary(BinOp::Shl, Value::Reg(sar), Value::Imm(3)),
+                to_left,
+            ],
+            _ => panic!()
+        }
+    }
+
+    /// Shifts by SAR use an immediate amount if SAR is known
+    fn lift_shift_sar(&self, instruction: &XtensaInstruction) -> Vec<Op> {
+        let dst = operand_reg(instruction, 0);
+        let src = Value::Reg(operand_reg(instruction, 1));
+        let shift = |op, amount| Op::Binary { op, dst, a: src, b: amount };
+
+        match (instruction.opcode, self.sar) {
+            (XtensaOpcode::Srl, Some(0)) |
+            (XtensaOpcode::Sra, Some(0)) => vec![Op::Mov { dst, src }],
+            (XtensaOpcode::Srl, Some(k)) => vec![shift(BinOp::Lsr, Value::Imm(k as i32))],
+            (XtensaOpcode::Sra, Some(k)) => vec![shift(BinOp::Asr, Value::Imm(k as i32))],
+            (XtensaOpcode::Srl, None) => vec![shift(BinOp::Lsr, value(SAR))],
+            (XtensaOpcode::Sra, None) => vec![shift(BinOp::Asr, value(SAR))],
+            // sll shifts left by 32 - SAR
+            (XtensaOpcode::Sll, Some(0)) => vec![Op::Mov { dst, src: Value::Imm(0) }],
+            (XtensaOpcode::Sll, Some(k)) => vec![shift(BinOp::Shl, Value::Imm(32 - k as i32))],
+            (XtensaOpcode::Sll, None) => {
+                let t = Reg::Temp(0);
+
+                vec![
+                    Op::Binary { op: BinOp::Sub, dst: t, a: Value::Imm(32), b: value(SAR) },
+                    shift(BinOp::Shl, Value::Reg(t)),
+                ]
+            }
+            _ => panic!()
+        }
+    }
+
+    fn lift_src(&self, instruction: &XtensaInstruction) -> Vec<Op> {
+        let amount = match self.sar {
+            Some(k) => Value::Imm(k as i32),
+            None => value(SAR),
+        };
+
+        vec![Op::FunnelShift {
+            dst: operand_reg(instruction, 0),
+            high: operand_reg(instruction, 1),
+            low: operand_reg(instruction, 2),
+            amount,
+        }]
+    }
+
+    fn lift_bit_ops(&self, instruction: &XtensaInstruction) -> Vec<Op> {
+        let dst = operand_reg(instruction, 0);
+        let src = operand_reg(instruction, 1);
+
+        vec![match instruction.opcode {
+            XtensaOpcode::Extui => {
+                let shift = operand_imm(instruction, 2) as u8;
+                let width = (operand_imm(instruction, 3) as u8).min(32 - shift);
+
+                Op::Extract { dst, src, shift, width, signed: false }
+            }
+            XtensaOpcode::Sext => Op::Extract {
+                dst, src, shift: 0, width: operand_imm(instruction, 2) as u8 + 1, signed: true
+            },
+            XtensaOpcode::Nsau => Op::Unary { op: UnaryOp::Clz, dst, src },
+            XtensaOpcode::Nsa => Op::Unary { op: UnaryOp::Cls, dst, src },
+            XtensaOpcode::Clamps => Op::Saturate { dst, src, bits: operand_imm(instruction, 2) as u8 + 1 },
+            _ => panic!()
+        }]
+    }
+
+    /// Compare and conditional moves. Moves to the
+    /// register itself are left out
+    fn lift_select(&self, dst: Reg, compare: Op, moves: &[(Cond, Reg)]) -> Vec<Op> {
+        let moves: Vec<Op> = moves.iter()
+            .filter(|&&(_, src)| src != dst)
+            .map(|&(cond, src)| Op::Select { cond, dst, src })
+            .collect();
+
+        if moves.is_empty() {
+            return moves;
+        }
+
+        let mut ops = vec![compare];
+        ops.extend(moves);
+        ops
+    }
+
+    /// neg and abs, abs selects between the register and its negation
+    fn lift_negate(&self, instruction: &XtensaInstruction) -> Vec<Op> {
+        let r1 = operand_reg(instruction, 0);
+        let r2 = operand_reg(instruction, 1);
+        let negate = |dst| Op::Binary { op: BinOp::Sub, dst, a: Value::Imm(0), b: Value::Reg(r2) };
+
+        if instruction.opcode == XtensaOpcode::Neg {
+            return vec![negate(r1)];
+        }
+
+        let t = Reg::Temp(0);
+        let compare = Op::Compare { kind: CompareKind::Cmp, a: r2, b: Value::Imm(0) };
+        let mut ops = vec![negate(t)];
+
+        ops.extend(self.lift_select(r1, compare, &[(Cond::Ge, r2), (Cond::Lt, t)]));
+        ops
+    }
+
+    fn lift_min_max(&self, instruction: &XtensaInstruction) -> Vec<Op> {
+        let (first, second) = match instruction.opcode {
+            XtensaOpcode::Min => (Cond::Lt, Cond::Ge),
+            XtensaOpcode::Max => (Cond::Gt, Cond::Le),
+            XtensaOpcode::Minu => (Cond::Ltu, Cond::Geu),
+            XtensaOpcode::Maxu => (Cond::Gtu, Cond::Leu),
+            _ => panic!()
+        };
+
+        let r2 = operand_reg(instruction, 1);
+        let r3 = operand_reg(instruction, 2);
+        let compare = Op::Compare { kind: CompareKind::Cmp, a: r2, b: Value::Reg(r3) };
+
+        // Conditions are exclusive, so writing the destination first is safe
+        self.lift_select(operand_reg(instruction, 0), compare, &[(first, r2), (second, r3)])
+    }
+
+    fn lift_conditional_move(&self, instruction: &XtensaInstruction) -> Vec<Op> {
+        let r3 = operand_reg(instruction, 2);
+        let cmp = |cond| (Op::Compare { kind: CompareKind::Cmp, a: r3, b: Value::Imm(0) }, cond);
+        let test = |cond| (Op::Compare { kind: CompareKind::Test, a: reg(BR), b: Value::Imm(operand_bool_mask(instruction, 2)) }, cond);
+
+        let (compare, cond) = match instruction.opcode {
+            XtensaOpcode::Moveqz => cmp(Cond::Eq),
+            XtensaOpcode::Movnez => cmp(Cond::Ne),
+            XtensaOpcode::Movltz => cmp(Cond::Lt),
+            XtensaOpcode::Movgez => cmp(Cond::Ge),
+            XtensaOpcode::Movt => test(Cond::Ne),
+            XtensaOpcode::Movf => test(Cond::Eq),
+            _ => panic!()
+        };
+
+        self.lift_select(operand_reg(instruction, 0), compare, &[(cond, operand_reg(instruction, 1))])
+    }
+
+    fn lift_mul_div(&self, instruction: &XtensaInstruction) -> Vec<Op> {
+        let dst = operand_reg(instruction, 0);
+        let a = operand_reg(instruction, 1);
+        let b = operand_reg(instruction, 2);
+        let div = |signed, remainder| Op::Div { dst, a, b, signed, remainder };
+
+        vec![match instruction.opcode {
+            XtensaOpcode::Mul16u => Op::Mul16 { dst, a, b, signed: false },
+            XtensaOpcode::Mul16s => Op::Mul16 { dst, a, b, signed: true },
+            XtensaOpcode::Muluh => Op::MulHigh { dst, a, b, signed: false },
+            XtensaOpcode::Mulsh => Op::MulHigh { dst, a, b, signed: true },
+            XtensaOpcode::Quou => div(false, false),
+            XtensaOpcode::Quos => div(true, false),
+            XtensaOpcode::Remu => div(false, true),
+            XtensaOpcode::Rems => div(true, true),
+            _ => panic!()
+        }]
+    }
+
+    /// Zero-overhead loop setup. The loop counter holds remaining
+    /// iterations, loopnez and loopgtz skip the body if it is zero
+    /// or negative. Loop end is added by `lift_function`
+    fn lift_loop(&self, instruction: &XtensaInstruction) -> Result<Vec<Op>> {
+        let end = operand_imm(instruction, 1);
+
+        if !self.loop_ends.contains(&(end as u32)) {
+            return Err(Error::Unsupported(format!("loop ending outside of the function at 0x{:x}", end)));
+        }
+
+        let counter = reg(LCOUNT);
+        let mut ops = vec![Op::Mov { dst: counter, src: Value::Reg(operand_reg(instruction, 0)) }];
+
+        match instruction.opcode {
+            XtensaOpcode::Loopnez => ops.extend(compare_branch(CompareKind::Cmp, counter, Value::Imm(0), Cond::Eq, end)),
+            XtensaOpcode::Loopgtz => ops.extend(compare_branch(CompareKind::Cmp, counter, Value::Imm(0), Cond::Le, end)),
+            _ => {}
+        }
+
+        Ok(ops)
+    }
+
+    /// rsr, wsr, xsr, rur or wur. SAR and SCOMPARE1 are registers
+    /// of the IR, others are accessed as configured by
+    /// `special_regs`: through a global word or an intrinsic
+    fn lift_special_reg(&mut self, instruction: &XtensaInstruction) -> Result<Vec<Op>> {
+        let r1 = operand_reg(instruction, 0);
+        let mnemonic = instruction.opcode.mnemonic();
+        let name = match instruction.operands[1].kind {
+            XtensaOperandKind::SpecialReg(n) => special_reg_name(n),
+            XtensaOperandKind::UserReg(n) => user_reg_name(n),
+            _ => panic!()
+        };
+
+        let (read, write) = match instruction.opcode {
+            XtensaOpcode::Rsr | XtensaOpcode::Rur => (true, false),
+            XtensaOpcode::Wsr | XtensaOpcode::Wur => (false, true),
+            XtensaOpcode::Xsr => (true, true),
+            _ => panic!()
+        };
+
+        if let Some(pseudo) = instruction.special_reg_pseudo() {
+            let p = reg(pseudo);
+            // SAR is 6 bits wide, BR 16
+            let set = match pseudo {
+                SAR => {
+                    self.sar = None;
+                    Op::Binary { op: BinOp::And, dst: p, a: Value::Reg(r1), b: Value::Imm(63) }
+                }
+                BR => Op::Binary { op: BinOp::And, dst: p, a: Value::Reg(r1), b: Value::Imm(0xffff) },
+                _ => Op::Mov { dst: p, src: Value::Reg(r1) },
+            };
+
+            return Ok(match (read, write) {
+                (true, false) => vec![Op::Mov { dst: r1, src: Value::Reg(p) }],
+                (false, true) => vec![set],
+                _ => {
+                    let t = Reg::Temp(0);
+                    vec![Op::Mov { dst: t, src: Value::Reg(p) }, set, Op::Mov { dst: r1, src: Value::Reg(t) }]
+                }
+            });
+        }
+
+        // Loop registers are only meaningful to the zero-overhead
+        // loops which are lifted to counted loops
+        if let XtensaOperandKind::SpecialReg(0 ..= 2) = instruction.operands[1].kind {
+            return Err(Error::Unsupported(format!("{:}.{:}", mnemonic, name)));
+        }
+
+        Ok(vec![match self.special_regs.get(&name) {
+            SrAccess::Slot(symbol) => match (read, write) {
+                (true, false) => Op::LoadGlobal { dst: r1, symbol },
+                (false, true) => Op::StoreGlobal { src: r1, symbol },
+                _ => Op::ExchangeGlobal { reg: r1, symbol },
+            },
+            SrAccess::Intrinsic => Op::Intrinsic {
+                name: format!("__xt_{:}_{:}", mnemonic, name),
+                dst: if read { Some(r1) } else { None },
+                args: if write { vec![Value::Reg(r1)] } else { Vec::new() },
+            },
+        }])
+    }
+
+    /// Lifts one instruction
+    pub fn lift(&mut self, instruction: &XtensaInstruction, objects: &ObjectStorage) -> Result<Vec<Op>> {
+        Ok(match instruction.opcode {
+            XtensaOpcode::Add |
+            XtensaOpcode::Sub |
+            XtensaOpcode::And |
+            XtensaOpcode::Or |
+            XtensaOpcode::Xor |
+            XtensaOpcode::Mull |
+            XtensaOpcode::Addi |
+            XtensaOpcode::Addmi |
+            XtensaOpcode::Slli |
+            XtensaOpcode::Slri |
+            XtensaOpcode::Srai => { self.lift_binary(instruction) }
+            XtensaOpcode::Addx2 |
+            XtensaOpcode::Addx4 |
+            XtensaOpcode::Addx8 => {
+                let shift = match instruction.opcode {
+                    XtensaOpcode::Addx2 => 1,
+                    XtensaOpcode::Addx4 => 2,
+                    _ => 3,
+                };
+
+                vec![Op::AddShifted {
+                    dst: operand_reg(instruction, 0),
+                    a: operand_reg(instruction, 1),
+                    shift,
+                    b: operand_reg(instruction, 2),
+                }]
+            }
+            XtensaOpcode::Subx2 |
+            XtensaOpcode::Subx4 |
+            XtensaOpcode::Subx8 => {
+                let shift = match instruction.opcode {
+                    XtensaOpcode::Subx2 => 1,
+                    XtensaOpcode::Subx4 => 2,
+                    _ => 3,
+                };
+                let t = Reg::Temp(0);
+
+                vec![
+                    Op::Binary { op: BinOp::Shl, dst: t, a: Value::Reg(operand_reg(instruction, 1)), b: Value::Imm(shift) },
+                    Op::Binary { op: BinOp::Sub, dst: operand_reg(instruction, 0), a: Value::Reg(t), b: Value::Reg(operand_reg(instruction, 2)) },
+                ]
+            }
+            XtensaOpcode::Neg |
+            XtensaOpcode::Abs => { self.lift_negate(instruction) }
+            XtensaOpcode::L32i |
+            XtensaOpcode::S32i |
+            XtensaOpcode::L8ui |
+            XtensaOpcode::S8i  |
+            XtensaOpcode::L16ui |
+            XtensaOpcode::L16si |
+            XtensaOpcode::S16i |
+            XtensaOpcode::L32ai |
+            XtensaOpcode::S32ri |
+            XtensaOpcode::S32c1i => { self.lift_load_store(instruction) }
+            XtensaOpcode::Bbci |
+            XtensaOpcode::Bbsi |
+            XtensaOpcode::Bbc |
+            XtensaOpcode::Bbs |
+            XtensaOpcode::Beq |
+            XtensaOpcode::Bne |
+            XtensaOpcode::Blt |
+            XtensaOpcode::Bge |
+            XtensaOpcode::Bltu |
+            XtensaOpcode::Bgeu |
+            XtensaOpcode::Bnone |
+            XtensaOpcode::Bany |
+            XtensaOpcode::Ball |
+            XtensaOpcode::Bnall |
+            XtensaOpcode::Beqz |
+            XtensaOpcode::Bnez |
+            XtensaOpcode::Bltz |
+            XtensaOpcode::Bgez |
+            XtensaOpcode::Beqi |
+            XtensaOpcode::Bnei |
+            XtensaOpcode::Blti |
+            XtensaOpcode::Bgei |
+            XtensaOpcode::Bltui |
+            XtensaOpcode::Bgeui |
+            XtensaOpcode::Jmp => { self.lift_branch(instruction) }
+            XtensaOpcode::L32r => { self.lift_load_relative(instruction, objects)? }
+            XtensaOpcode::Ret => { vec![Op::Return { windowed: false }] }
+            XtensaOpcode::Retw => {
+                self.require_windowed(instruction)?;
+                vec![Op::Return { windowed: true }]
+            }
+            XtensaOpcode::Memw => { vec![Op::Barrier] }
+            XtensaOpcode::Nop => { vec![Op::Nop] }
+            XtensaOpcode::Mov => {
+                vec![Op::Mov { dst: operand_reg(instruction, 0), src: Value::Reg(operand_reg(instruction, 1)) }]
+            }
+            XtensaOpcode::Movi => {
+                vec![Op::Mov { dst: operand_reg(instruction, 0), src: Value::Imm(operand_imm(instruction, 1)) }]
+            }
+            XtensaOpcode::Call0 |
+            XtensaOpcode::Callx0 |
+            XtensaOpcode::Call4 |
+            XtensaOpcode::Call8 |
+            XtensaOpcode::Call12 |
+            XtensaOpcode::Callx4 |
+            XtensaOpcode::Callx8 |
+            XtensaOpcode::Callx12 => { self.lift_call(instruction, objects)? }
+            XtensaOpcode::Entry => { self.lift_entry(instruction)? }
+            XtensaOpcode::Ssl |
+            XtensaOpcode::Ssr |
+            XtensaOpcode::Ssai |
+            XtensaOpcode::Ssa8l |
+            XtensaOpcode::Ssa8b => { self.lift_set_sar(instruction) }
+            XtensaOpcode::Sll |
+            XtensaOpcode::Srl |
+            XtensaOpcode::Sra => { self.lift_shift_sar(instruction) }
+            XtensaOpcode::Src => { self.lift_src(instruction) }
+            XtensaOpcode::Extui |
+            XtensaOpcode::Sext |
+            XtensaOpcode::Nsa |
+            XtensaOpcode::Nsau |
+            XtensaOpcode::Clamps => { self.lift_bit_ops(instruction) }
+            XtensaOpcode::Min |
+            XtensaOpcode::Max |
+            XtensaOpcode::Minu |
+            XtensaOpcode::Maxu => { self.lift_min_max(instruction) }
+            XtensaOpcode::Mul16u |
+            XtensaOpcode::Mul16s |
+            XtensaOpcode::Muluh |
+            XtensaOpcode::Mulsh |
+            XtensaOpcode::Quou |
+            XtensaOpcode::Quos |
+            XtensaOpcode::Remu |
+            XtensaOpcode::Rems => { self.lift_mul_div(instruction) }
+            XtensaOpcode::Moveqz |
+            XtensaOpcode::Movnez |
+            XtensaOpcode::Movltz |
+            XtensaOpcode::Movgez |
+            XtensaOpcode::Movt |
+            XtensaOpcode::Movf => { self.lift_conditional_move(instruction) }
+            XtensaOpcode::Loop |
+            XtensaOpcode::Loopnez |
+            XtensaOpcode::Loopgtz => { self.lift_loop(instruction)? }
+            XtensaOpcode::Rsr |
+            XtensaOpcode::Wsr |
+            XtensaOpcode::Xsr |
+            XtensaOpcode::Rur |
+            XtensaOpcode::Wur => { self.lift_special_reg(instruction)? }
+            XtensaOpcode::Rsil => {
+                vec![Op::Intrinsic {
+                    name: "__xt_rsil".to_string(),
+                    dst: Some(operand_reg(instruction, 0)),
+                    args: vec![Value::Imm(operand_imm(instruction, 1))],
+                }]
+            }
+            _ => { return Err(Error::Unsupported(instruction.opcode.mnemonic().to_string())) }
+        })
+    }
+
+    /// Parses and lifts each instruction of a function and adds
+    /// the back edges of zero-overhead loops, counter 0 meaning
+    /// 2^32 iterations as with `loop`. Errors are located and
+    /// fail the function unless `lenient`
+    pub fn lift_function(&mut self, function: &Function, objects: &ObjectStorage, lenient: bool) -> Result<LiftedFunction> {
+        let parsed: Vec<Result<XtensaInstruction>> = (0 .. function.instructions.len())
+            .map(|i| function.xtensa_instruction(i))
+            .collect();
+
+        if !lenient {
+            if let Some(Err(e)) = parsed.iter().find(|p| p.is_err()) {
+                return Err(e.clone());
+            }
+        }
+
+        // Instructions which failed to parse don't take part in analysis
+        let instructions: Vec<XtensaInstruction> = parsed.iter()
+            .map(|p| p.clone().unwrap_or_default())
+            .collect();
+
+        let offsets: Vec<u32> = function.instructions.iter().map(|i| i.offset).collect();
+        let cfg = Cfg::from_instructions(&instructions, &offsets);
+
+        self.loop_ends = cfg.loops.iter().map(|l| offsets[l.end]).collect();
+        self.sar = None;
+
+        let mut lifted = LiftedFunction { instructions, offsets, cfg, code: Vec::new(), errors: BTreeMap::new() };
+
+        for (n, instruction) in function.instructions.iter().enumerate() {
+            // SAR is only tracked along straight-line code
+            if lifted.cfg.is_branch_target(instruction.offset) {
+                self.sar = None;
+            }
+
+            let ops = match parsed[n] {
+                Ok(ref xtensa_instruction) => self.lift(xtensa_instruction, objects)
+                    .map_err(|e| e.at(&function.name, instruction.offset, &instruction.opcode)),
+                Err(ref e) => Err(e.clone()),
+            };
+
+            match ops {
+                Ok(mut ops) => {
+                    ops.extend(lifted.loop_end_after(n));
+                    lifted.code.push(Some(ops));
+                }
+                Err(e) => {
+                    if !lenient {
+                        return Err(e);
+                    }
+
+                    self.sar = None;
+                    lifted.errors.insert(n, e);
+                    lifted.code.push(None);
+                }
+            }
+        }
+
+        Ok(lifted)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use translation::xtensa_lift::Lifter;
+    use translation::xtensa_op::XtensaInstruction;
+    use translation::xtensa_abi::XtensaAbi;
+    use translation::xtensa_sr::SrMap;
+    use object_storage::ObjectStorage;
+    use assembly::ParseInstruction;
+    use function::fixture;
+
+    fn instruction(s: &str) -> XtensaInstruction {
+        let mut i = XtensaInstruction::new();
+        i.from_str(s).unwrap();
+        i
+    }
+
+    /// Lifts instructions in sequence, returns IR text of each
+    fn lift(lifter: &mut Lifter, code: &[&str]) -> Vec<String> {
+        let objects = ObjectStorage::default();
+
+        code.iter()
+            .map(|s| lifter.lift(&instruction(s), &objects).unwrap())
+            .map(|ops| ops.iter().map(|op| op.to_string()).collect::<Vec<String>>().join("; "))
+            .collect()
+    }
+
+    #[test]
+    fn test_lift_1() {
+        let mut lifter = Lifter::default();
+        let code = [
+            "addi a1, a1, -16", "l16si a8, a2, 2", "s32ri a8, a1, 12", "addx4 a2, a3, a2",
+            "bnall a2, a3, 0x100", "bbs a2, a3, 0x100", "max a2, a2, a3", "movt a2, a3, b1",
+        ];
+
+        assert_eq!(lift(&mut lifter, &code), vec![
+            "a1 = a1 + 0xfffffff0",
+            "a8 = load16s [a2 + 0x2]",
+            "store32.release [a1 + 0xc] = a8",
+            "a2 = (a3 << 2) + a2",
+            "flags = testclear a3, a2; if ne goto loc_100",
+            "t0 = a3 & 0x1f; t0 = a2 >> t0; flags = test t0, 1; if ne goto loc_100",
+            "flags = cmp a2, a3; if le a2 = a3",
+            "flags = test br, 2; if ne a2 = a3",
+        ]);
+    }
+
+    #[test]
+    fn test_lift_2() {
+        let mut lifter = Lifter::default();
+        let code = ["xor a2, a3, a4", "neg a2, a3", "abs a2, a3", "abs a2, a2", "subx4 a2, a3, a4"];
+
+        assert_eq!(lift(&mut lifter, &code), vec![
+            "a2 = a3 ^ a4",
+            "a2 = 0 - a3",
+            "t0 = 0 - a3; flags = cmp a3, 0; if ge a2 = a3; if lt a2 = t0",
+            "t0 = 0 - a2; flags = cmp a2, 0; if lt a2 = t0",
+            "t0 = a3 << 2; a2 = t0 - a4",
+        ]);
+        assert!(lifter.lift(&instruction("jx a2"), &ObjectStorage::default()).is_err());
+    }
+
+    #[test]
+    fn test_lift_sar_1() {
+        let mut lifter = Lifter::default();
+        let code = ["ssai 8", "src a2, a3, a2", "sll a2, a2", "ssl a4", "sll a2, a2", "xsr.sar a3"];
+
+        assert_eq!(lift(&mut lifter, &code), vec![
+            "sar = 8",
+            "a2 = funnel(a3, a2, 8)",
+            "a2 = a2 << 0x18",
+            "sar = a4 & 0x1f; sar = 0x20 - sar",
+            "t0 = 0x20 - sar; a2 = a2 << t0",
+            "t0 = sar; sar = a3 & 0x3f; a3 = t0",
+        ]);
+        assert_eq!(lifter.sar, None);
+    }
+
+    #[test]
+    fn test_lift_special_reg_1() {
+        let mut lifter = Lifter::new(XtensaAbi::Call0, SrMap::parse("excsave1 slot XT_EXCSAVE1").unwrap());
+
+        assert_eq!(lift(&mut lifter, &["rsr.ps a2", "xsr.excsave1 a3", "rsil a4, 3", "wsr.br a5", "rsr.br a6"]), vec![
+            "a2 = __xt_rsr_ps()", "a3 <-> [XT_EXCSAVE1]", "a4 = __xt_rsil(3)", "br = a5 & 0xffff", "a6 = br",
+        ]);
+
+        let entry = instruction("entry a1, 32");
+        assert!(lifter.lift(&entry, &ObjectStorage::default()).is_err());
+
+        lifter.abi = XtensaAbi::Windowed;
+        assert_eq!(lifter.lift(&entry, &ObjectStorage::default()).unwrap()[0].to_string(), "entry 32");
+    }
+    #[test]
+    fn test_lift_function_1() {
+        let f = fixture(&["ssai 4", "loop a3, 0x10c", "srl a2, a2", "rsr.lbeg a4", "ret"]);
+        let objects = ObjectStorage::default();
+
+        assert!(Lifter::default().lift_function(&f, &objects, false).is_err());
+
+        let lifted = Lifter::default().lift_function(&f, &objects, true).unwrap();
+        let code: Vec<String> = lifted.code.iter()
+            .map(|ops| match *ops {
+                Some(ref ops) => ops.iter().map(|op| op.to_string()).collect::<Vec<String>>().join("; "),
+                None => "-".to_string(),
+            })
+            .collect();
+
+        // SAR isn't known inside the loop body
+        assert_eq!(code, vec!["sar = 4", "lcount = a3", "a2 = a2 >> sar", "-", "ret"]);
+        assert_eq!(lifted.errors.keys().collect::<Vec<&usize>>(), vec![&3]);
+        assert_eq!(lifted.loop_end_after(3)[0].to_string(), "if --lcount goto loc_106");
+        assert!(lifted.loop_end_after(2).is_empty());
+    }
+}
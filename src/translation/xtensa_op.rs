@@ -1,5 +1,8 @@
-use assembly::ParseInstruction;
+use std::fmt;
+
+use assembly::{ParseInstruction, Operand};
 use translation::xtensa_operand::{XtensaOperand, XtensaOperandKind};
+use error::{Error, Result};
 
 /// xtensa opcodes enum.
 /// Narrow versions are assigned same
@@ -10,23 +13,86 @@ pub enum XtensaOpcode {
     Add, Addi, And, Sub,
     Or, Slli, Slri, Srai,
     Mov, Movi, Addx2, Addx4,
-    Addx8,
+    Addx8, Subx2, Subx4, Subx8,
+    Xor, Neg, Abs,
     // Flow control
     Bbsi, Bbci, Ret, Jmp,
+    Jx,
+    // Conditional branches
+    Beq, Bne, Blt, Bge,
+    Bltu, Bgeu, Beqz, Bnez,
+    Bltz, Bgez, Beqi, Bnei,
+    Blti, Bgei, Bltui, Bgeui,
+    Ball, Bany, Bnall, Bnone,
+    Bbc, Bbs,
     // Memory sync barrier
-    Memw,
+    Memw, Nop,
     // Load operations
     L32r, L32i, L16ui,
     L16si, L8ui, S8i,
     // Calls
     Call0, Callx0,
+    // Windowed ABI
+    Entry, Retw, Call4, Call8,
+    Call12, Callx4, Callx8, Callx12,
     // Store operations
-    S32i,
+    S32i, S16i,
+    // Atomic and ordered memory access
+    L32ai, S32ri, S32c1i,
+    Addmi,
+    // Shift amount register
+    Ssl, Ssr, Ssai, Ssa8l,
+    Ssa8b, Sll, Srl, Sra,
+    Src,
+    // Bit field and saturation
+    Extui, Sext, Nsa, Nsau,
+    Clamps, Min, Max, Minu,
+    Maxu,
+    // Multiply and divide
+    Mul16u, Mul16s, Mull, Muluh,
+    Mulsh, Quou, Quos, Remu,
+    Rems,
+    // Conditional moves
+    Moveqz, Movnez, Movltz, Movgez,
+    Movt, Movf,
+    // Zero-overhead loops
+    Loop, Loopnez, Loopgtz,
+    // Special and user registers
+    Rsr, Wsr, Xsr, Rsil,
+    Rur, Wur,
     Other,
 }
 
+/// Register number SAR is tracked under in defs and uses,
+/// past the 16 address registers
+pub const SAR: u8 = 16;
+
+/// Register number BR is tracked under. Boolean registers
+/// b0..b15 are its bits, an access to any of them is an
+/// access to BR
+pub const BR: u8 = 17;
+
+/// Register number SCOMPARE1, compared against by s32c1i, is tracked under
+pub const SCOMPARE1: u8 = 33;
+
+/// Register number the zero-overhead loop counter is tracked under
+pub const LCOUNT: u8 = 34;
+
+/// Mnemonics which may name their special or user register after a dot
+const SR_ACCESS: [&str; 5] = ["rsr", "wsr", "xsr", "rur", "wur"];
+
+/// Immediates encodable in beqi, bnei, blti and bgei
+pub const B4CONST: [i32; 16] = [
+    -1, 1, 2, 3, 4, 5, 6, 7, 8, 10, 12, 16, 32, 64, 128, 256
+];
+
+/// Immediates encodable in bltui and bgeui
+pub const B4CONSTU: [u32; 16] = [
+    32768, 65536, 2, 3, 4, 5, 6, 7, 8, 10, 12, 16, 32, 64, 128, 256
+];
+
 /// Holds xtensa opcode and operands
-#[derive(Default, PartialEq)]
+#[derive(Clone, Default, PartialEq)]
 pub struct XtensaInstruction {
     pub opcode: XtensaOpcode,
     pub operands: Vec<XtensaOperand>,
@@ -52,11 +118,238 @@ impl XtensaInstruction {
     pub fn new() -> XtensaInstruction {
         Default::default()
     }
+
+    /// Holds raw bytes of an instruction the decoder doesn't know
+    pub fn undecoded(bytes: &[u8]) -> XtensaInstruction {
+        XtensaInstruction {
+            opcode: XtensaOpcode::Other,
+            operands: bytes.iter()
+                .map(|&b| XtensaOperand { kind: XtensaOperandKind::Imm(b as u32) })
+                .collect(),
+        }
+    }
+
+    /// Whether this is a conditional branch.
+    /// Branch target is always the last operand
+    pub fn is_branch(&self) -> bool {
+        matches!(self.opcode,
+            XtensaOpcode::Bbsi | XtensaOpcode::Bbci |
+            XtensaOpcode::Beq | XtensaOpcode::Bne |
+            XtensaOpcode::Blt | XtensaOpcode::Bge |
+            XtensaOpcode::Bltu | XtensaOpcode::Bgeu |
+            XtensaOpcode::Beqz | XtensaOpcode::Bnez |
+            XtensaOpcode::Bltz | XtensaOpcode::Bgez |
+            XtensaOpcode::Beqi | XtensaOpcode::Bnei |
+            XtensaOpcode::Blti | XtensaOpcode::Bgei |
+            XtensaOpcode::Bltui | XtensaOpcode::Bgeui |
+            XtensaOpcode::Ball | XtensaOpcode::Bany |
+            XtensaOpcode::Bnall | XtensaOpcode::Bnone |
+            XtensaOpcode::Bbc | XtensaOpcode::Bbs |
+            XtensaOpcode::Loopnez | XtensaOpcode::Loopgtz)
+    }
+
+    /// Returns branch or jump target address
+    pub fn branch_target(&self) -> Option<u32> {
+        if self.is_branch() || self.opcode == XtensaOpcode::Jmp {
+            self.operands.last().map(|o| o.get_imm() as u32)
+        } else {
+            None
+        }
+    }
+
+    /// Returns address of the first instruction past
+    /// the body of a zero-overhead loop
+    pub fn loop_end(&self) -> Option<u32> {
+        match self.opcode {
+            XtensaOpcode::Loop | XtensaOpcode::Loopnez |
+            XtensaOpcode::Loopgtz => self.operands.last().map(|o| o.get_imm() as u32),
+            _ => None
+        }
+    }
+
+    /// Whether this is a call of any ABI, direct or indirect
+    pub fn is_call(&self) -> bool {
+        matches!(self.opcode,
+            XtensaOpcode::Call0 | XtensaOpcode::Callx0 |
+            XtensaOpcode::Call4 | XtensaOpcode::Callx4 |
+            XtensaOpcode::Call8 | XtensaOpcode::Callx8 |
+            XtensaOpcode::Call12 | XtensaOpcode::Callx12)
+    }
+
+    /// Returns target address of a direct call
+    pub fn call_target(&self) -> Option<u32> {
+        match self.opcode {
+            XtensaOpcode::Call0 | XtensaOpcode::Call4 |
+            XtensaOpcode::Call8 | XtensaOpcode::Call12 => {
+                self.operands.first().map(|o| o.get_imm() as u32)
+            }
+            _ => None
+        }
+    }
+
+    /// Window rotation of callN, 0 for call0 and other instructions
+    pub fn rotation(&self) -> u8 {
+        match self.opcode {
+            XtensaOpcode::Call4 | XtensaOpcode::Callx4 => 4,
+            XtensaOpcode::Call8 | XtensaOpcode::Callx8 => 8,
+            XtensaOpcode::Call12 | XtensaOpcode::Callx12 => 12,
+            _ => 0
+        }
+    }
+
+    /// Outgoing arguments of callN, a(N+2)..a(N+7). Which
+    /// of them the callee reads is unknown, so they are not
+    /// part of `uses`
+    pub fn window_args(&self) -> Vec<u8> {
+        match self.rotation() {
+            0 => Vec::new(),
+            n => (n + 2 .. (n + 8).min(16)).collect(),
+        }
+    }
+
+    /// Whether execution never continues with the next instruction
+    pub fn is_terminator(&self) -> bool {
+        matches!(self.opcode,
+            XtensaOpcode::Ret | XtensaOpcode::Retw |
+            XtensaOpcode::Jmp | XtensaOpcode::Jx)
+    }
+
+    /// Whether the first operand is the destination register
+    fn writes_first_operand(&self) -> bool {
+        matches!(self.opcode,
+            XtensaOpcode::Add | XtensaOpcode::Addi |
+            XtensaOpcode::And | XtensaOpcode::Sub |
+            XtensaOpcode::Or | XtensaOpcode::Slli |
+            XtensaOpcode::Slri | XtensaOpcode::Srai |
+            XtensaOpcode::Mov | XtensaOpcode::Movi |
+            XtensaOpcode::Addx2 | XtensaOpcode::Addx4 |
+            XtensaOpcode::Addx8 | XtensaOpcode::Subx2 |
+            XtensaOpcode::Subx4 | XtensaOpcode::Subx8 |
+            XtensaOpcode::Xor | XtensaOpcode::Neg |
+            XtensaOpcode::Abs | XtensaOpcode::L32r |
+            XtensaOpcode::L32i | XtensaOpcode::L16ui |
+            XtensaOpcode::L16si | XtensaOpcode::L8ui |
+            XtensaOpcode::L32ai | XtensaOpcode::S32c1i |
+            XtensaOpcode::Addmi |
+            XtensaOpcode::Sll | XtensaOpcode::Srl |
+            XtensaOpcode::Sra | XtensaOpcode::Src |
+            XtensaOpcode::Extui | XtensaOpcode::Sext |
+            XtensaOpcode::Nsa | XtensaOpcode::Nsau |
+            XtensaOpcode::Clamps | XtensaOpcode::Min |
+            XtensaOpcode::Max | XtensaOpcode::Minu |
+            XtensaOpcode::Maxu | XtensaOpcode::Mul16u |
+            XtensaOpcode::Mul16s | XtensaOpcode::Mull |
+            XtensaOpcode::Muluh | XtensaOpcode::Mulsh |
+            XtensaOpcode::Quou | XtensaOpcode::Quos |
+            XtensaOpcode::Remu | XtensaOpcode::Rems |
+            XtensaOpcode::Moveqz | XtensaOpcode::Movnez |
+            XtensaOpcode::Movltz | XtensaOpcode::Movgez |
+            XtensaOpcode::Movt | XtensaOpcode::Movf |
+            XtensaOpcode::Rsr | XtensaOpcode::Xsr |
+            XtensaOpcode::Rsil | XtensaOpcode::Rur |
+            XtensaOpcode::Entry)
+    }
+
+    fn operand_regs(&self) -> Vec<u8> {
+        self.operands.iter()
+            .filter_map(|o| match o.kind {
+                XtensaOperandKind::Reg(_) |
+                XtensaOperandKind::BoolReg(_) => Some(o.get_reg()),
+                _ => None
+            })
+            .collect()
+    }
+
+    /// Whether the destination is written only if a condition holds
+    pub fn is_conditional_move(&self) -> bool {
+        matches!(self.opcode,
+            XtensaOpcode::Moveqz | XtensaOpcode::Movnez |
+            XtensaOpcode::Movltz | XtensaOpcode::Movgez |
+            XtensaOpcode::Movt | XtensaOpcode::Movf)
+    }
+
+    /// Whether the instruction sets SAR
+    pub fn writes_sar(&self) -> bool {
+        matches!(self.opcode,
+            XtensaOpcode::Ssl | XtensaOpcode::Ssr |
+            XtensaOpcode::Ssai | XtensaOpcode::Ssa8l |
+            XtensaOpcode::Ssa8b)
+    }
+
+    /// Whether the instruction shifts by SAR
+    pub fn reads_sar(&self) -> bool {
+        matches!(self.opcode,
+            XtensaOpcode::Sll | XtensaOpcode::Srl |
+            XtensaOpcode::Sra | XtensaOpcode::Src)
+    }
+
+    /// Returns register number a special register of rsr, wsr
+    /// or xsr is tracked under, for SAR and SCOMPARE1
+    pub fn special_reg_pseudo(&self) -> Option<u8> {
+        match self.opcode {
+            XtensaOpcode::Rsr | XtensaOpcode::Wsr |
+            XtensaOpcode::Xsr => match self.operands.get(1).map(|o| &o.kind) {
+                Some(&XtensaOperandKind::SpecialReg(3)) => Some(SAR),
+                Some(&XtensaOperandKind::SpecialReg(4)) => Some(BR),
+                Some(&XtensaOperandKind::SpecialReg(12)) => Some(SCOMPARE1),
+                _ => None
+            },
+            _ => None
+        }
+    }
+
+    /// Registers written by the instruction, including
+    /// the return address written by call0/callx0, the
+    /// return value callN copies back and SAR
+    pub fn defs(&self) -> Vec<u8> {
+        let mut result = Vec::new();
+
+        if self.writes_first_operand() {
+            result.extend(self.operand_regs().into_iter().take(1));
+        }
+
+        match self.opcode {
+            XtensaOpcode::Call0 | XtensaOpcode::Callx0 => result.push(0),
+            _ if self.rotation() > 0 => result.push(self.rotation() + 2),
+            _ if self.writes_sar() => result.push(SAR),
+            _ if self.loop_end().is_some() => result.push(LCOUNT),
+            XtensaOpcode::Wsr | XtensaOpcode::Xsr => result.extend(self.special_reg_pseudo()),
+            _ => {}
+        }
+
+        result
+    }
+
+    /// Registers read by the instruction. Calling
+    /// convention registers are not included.
+    /// Conditional moves also read the destination
+    /// since it is kept if the condition fails, s32c1i
+    /// and xsr store it before replacing it with the old value
+    pub fn uses(&self) -> Vec<u8> {
+        let regs = self.operand_regs();
+        let mut result: Vec<u8> = match self.opcode {
+            XtensaOpcode::Entry | XtensaOpcode::S32c1i |
+            XtensaOpcode::Xsr => regs,
+            _ if self.is_conditional_move() => regs,
+            _ if self.writes_first_operand() => regs.into_iter().skip(1).collect(),
+            _ => regs
+        };
+
+        match self.opcode {
+            XtensaOpcode::Ret => result.push(0),
+            XtensaOpcode::S32c1i => result.push(SCOMPARE1),
+            _ if self.reads_sar() => result.push(SAR),
+            XtensaOpcode::Rsr | XtensaOpcode::Xsr => result.extend(self.special_reg_pseudo()),
+            _ => {}
+        }
+
+        result
+    }
 }
 
 impl InstructionBuilder {
-    pub fn from_opcode_str(opcode: &str) -> InstructionBuilder {
-        match opcode {
+    pub fn from_opcode_str(opcode: &str) -> Result<InstructionBuilder> {
+        Ok(match opcode {
             "and"  | "and.n"  => op!(And,   [ Reg, Reg, Reg ]),
             "or"   | "or.n"   => op!(Or,    [ Reg, Reg, Reg ]),
             "add"  | "add.n"  => op!(Add,   [ Reg, Reg, Reg ]),
@@ -64,6 +357,12 @@ impl InstructionBuilder {
             "addx2"           => op!(Addx2, [ Reg, Reg, Reg ]),
             "addx4"           => op!(Addx4, [ Reg, Reg, Reg ]),
             "addx8"           => op!(Addx8, [ Reg, Reg, Reg ]),
+            "subx2"           => op!(Subx2, [ Reg, Reg, Reg ]),
+            "subx4"           => op!(Subx4, [ Reg, Reg, Reg ]),
+            "subx8"           => op!(Subx8, [ Reg, Reg, Reg ]),
+            "xor"             => op!(Xor,   [ Reg, Reg, Reg ]),
+            "neg"             => op!(Neg,   [ Reg, Reg ]),
+            "abs"             => op!(Abs,   [ Reg, Reg ]),
             "ret"  | "ret.n"  => op!(Ret,   []),
             "l32i" | "l32i.n" => op!(L32i,  [ Reg, Reg, Imm ]),
             "l32r"            => op!(L32r,  [ Reg, Imm ]),
@@ -72,58 +371,280 @@ impl InstructionBuilder {
             "l16si"           => op!(L16si, [ Reg, Reg, Imm ]),
             "s32i" | "s32i.n" => op!(S32i,  [ Reg, Reg, Imm ]),
             "s8i"             => op!(S8i,   [ Reg, Reg, Imm ]),
+            "s16i"            => op!(S16i,  [ Reg, Reg, Imm ]),
+            "l32ai"           => op!(L32ai, [ Reg, Reg, Imm ]),
+            "s32ri"           => op!(S32ri, [ Reg, Reg, Imm ]),
+            "s32c1i"          => op!(S32c1i,[ Reg, Reg, Imm ]),
+            "addmi"           => op!(Addmi, [ Reg, Reg, Imm ]),
             "slli"            => op!(Slli,  [ Reg, Reg, Imm ]),
-            "slri"            => op!(Slri,  [ Reg, Reg, Imm ]),
+            "srli" | "slri"   => op!(Slri,  [ Reg, Reg, Imm ]),
             "srai"            => op!(Srai,  [ Reg, Reg, Imm ]),
             "bbsi"            => op!(Bbsi,  [ Reg, Imm, Imm ]),
             "bbci"            => op!(Bbci,  [ Reg, Imm, Imm ]),
-            "addi"            => op!(Addi,  [ Reg, Reg, Imm ]),
+            "bbs"             => op!(Bbs,   [ Reg, Reg, Imm ]),
+            "bbc"             => op!(Bbc,   [ Reg, Reg, Imm ]),
+            "beq"             => op!(Beq,   [ Reg, Reg, Imm ]),
+            "bne"             => op!(Bne,   [ Reg, Reg, Imm ]),
+            "blt"             => op!(Blt,   [ Reg, Reg, Imm ]),
+            "bge"             => op!(Bge,   [ Reg, Reg, Imm ]),
+            "bltu"            => op!(Bltu,  [ Reg, Reg, Imm ]),
+            "bgeu"            => op!(Bgeu,  [ Reg, Reg, Imm ]),
+            "ball"            => op!(Ball,  [ Reg, Reg, Imm ]),
+            "bany"            => op!(Bany,  [ Reg, Reg, Imm ]),
+            "bnall"           => op!(Bnall, [ Reg, Reg, Imm ]),
+            "bnone"           => op!(Bnone, [ Reg, Reg, Imm ]),
+            "beqz" | "beqz.n" => op!(Beqz,  [ Reg, Imm ]),
+            "bnez" | "bnez.n" => op!(Bnez,  [ Reg, Imm ]),
+            "bltz"            => op!(Bltz,  [ Reg, Imm ]),
+            "bgez"            => op!(Bgez,  [ Reg, Imm ]),
+            "beqi"            => op!(Beqi,  [ Reg, Imm, Imm ]),
+            "bnei"            => op!(Bnei,  [ Reg, Imm, Imm ]),
+            "blti"            => op!(Blti,  [ Reg, Imm, Imm ]),
+            "bgei"            => op!(Bgei,  [ Reg, Imm, Imm ]),
+            "bltui"           => op!(Bltui, [ Reg, Imm, Imm ]),
+            "bgeui"           => op!(Bgeui, [ Reg, Imm, Imm ]),
+            "addi" | "addi.n" => op!(Addi,  [ Reg, Reg, Imm ]),
             "mov" | "mov.n"   => op!(Mov,   [ Reg, Reg ]),
             "movi" | "movi.n" => op!(Movi,  [ Reg, Imm ]),
             "call0"           => op!(Call0, [ Imm ]),
             "callx0"          => op!(Callx0,[ Reg ]),
+            "call4"           => op!(Call4, [ Imm ]),
+            "call8"           => op!(Call8, [ Imm ]),
+            "call12"          => op!(Call12,[ Imm ]),
+            "callx4"          => op!(Callx4,[ Reg ]),
+            "callx8"          => op!(Callx8,[ Reg ]),
+            "callx12"         => op!(Callx12,[ Reg ]),
+            "entry"           => op!(Entry, [ Reg, Imm ]),
+            "retw" | "retw.n" => op!(Retw,  []),
             "j"               => op!(Jmp,   [ Imm ]),
+            "jx"              => op!(Jx,    [ Reg ]),
             "memw"            => op!(Memw,  []),
-            _ => { panic!("Opcode not supported: {:?}", opcode); }
-        }
+            "nop"  | "nop.n"  => op!(Nop,   []),
+            "ssl"             => op!(Ssl,   [ Reg ]),
+            "ssr"             => op!(Ssr,   [ Reg ]),
+            "ssai"            => op!(Ssai,  [ Imm ]),
+            "ssa8l"           => op!(Ssa8l, [ Reg ]),
+            "ssa8b"           => op!(Ssa8b, [ Reg ]),
+            "sll"             => op!(Sll,   [ Reg, Reg ]),
+            "srl"             => op!(Srl,   [ Reg, Reg ]),
+            "sra"             => op!(Sra,   [ Reg, Reg ]),
+            "src"             => op!(Src,   [ Reg, Reg, Reg ]),
+            "extui"           => op!(Extui, [ Reg, Reg, Imm, Imm ]),
+            "sext"            => op!(Sext,  [ Reg, Reg, Imm ]),
+            "nsa"             => op!(Nsa,   [ Reg, Reg ]),
+            "nsau"            => op!(Nsau,  [ Reg, Reg ]),
+            "clamps"          => op!(Clamps,[ Reg, Reg, Imm ]),
+            "min"             => op!(Min,   [ Reg, Reg, Reg ]),
+            "max"             => op!(Max,   [ Reg, Reg, Reg ]),
+            "minu"            => op!(Minu,  [ Reg, Reg, Reg ]),
+            "maxu"            => op!(Maxu,  [ Reg, Reg, Reg ]),
+            "mul16u"          => op!(Mul16u,[ Reg, Reg, Reg ]),
+            "mul16s"          => op!(Mul16s,[ Reg, Reg, Reg ]),
+            "mull"            => op!(Mull,  [ Reg, Reg, Reg ]),
+            "muluh"           => op!(Muluh, [ Reg, Reg, Reg ]),
+            "mulsh"           => op!(Mulsh, [ Reg, Reg, Reg ]),
+            "quou"            => op!(Quou,  [ Reg, Reg, Reg ]),
+            "quos"            => op!(Quos,  [ Reg, Reg, Reg ]),
+            "remu"            => op!(Remu,  [ Reg, Reg, Reg ]),
+            "rems"            => op!(Rems,  [ Reg, Reg, Reg ]),
+            "moveqz"          => op!(Moveqz,[ Reg, Reg, Reg ]),
+            "movnez"          => op!(Movnez,[ Reg, Reg, Reg ]),
+            "movltz"          => op!(Movltz,[ Reg, Reg, Reg ]),
+            "movgez"          => op!(Movgez,[ Reg, Reg, Reg ]),
+            "movt"            => op!(Movt,  [ Reg, Reg, BoolReg ]),
+            "movf"            => op!(Movf,  [ Reg, Reg, BoolReg ]),
+            "loop"            => op!(Loop,  [ Reg, Imm ]),
+            "loopnez"         => op!(Loopnez,[ Reg, Imm ]),
+            "loopgtz"         => op!(Loopgtz,[ Reg, Imm ]),
+            "rsr"             => op!(Rsr,   [ Reg, SpecialReg ]),
+            "wsr"             => op!(Wsr,   [ Reg, SpecialReg ]),
+            "xsr"             => op!(Xsr,   [ Reg, SpecialReg ]),
+            "rsil"            => op!(Rsil,  [ Reg, Imm ]),
+            "rur"             => op!(Rur,   [ Reg, UserReg ]),
+            "wur"             => op!(Wur,   [ Reg, UserReg ]),
+            _ => { return Err(Error::UnknownMnemonic(opcode.to_string())); }
+        })
     }
 
-    pub fn build<'a, I>(&self, tokens: I) -> XtensaInstruction
+    pub fn build<'a, I>(&self, tokens: I) -> Result<XtensaInstruction>
     where I: Iterator<Item=&'a str> {
         let mut operands = Vec::<XtensaOperand>::new();
         let t: Vec<&str> = tokens.collect();
 
         for i in 0 .. self.operand_kind.len() {
-            let token = t[i];
-
-            operands.push(
-                match self.operand_kind[i] {
-                    XtensaOperandKind::Reg(_) => XtensaOperand::new(XtensaOperandKind::Reg(0), token),
-                    XtensaOperandKind::Imm(_) => XtensaOperand::new(XtensaOperandKind::Imm(0), token),
-                    _ => panic!("Unknown operand type")
-                }
-            )
+            let token = match t.get(i) {
+                Some(token) => token,
+                None => return Err(Error::MissingOperand)
+            };
+
+            operands.push(XtensaOperand::new(self.operand_kind[i].clone(), token)?);
         }
 
-        XtensaInstruction {
+        Ok(XtensaInstruction {
             opcode: self.opcode,
             operands: operands
-        }
+        })
     }
 }
 
 impl ParseInstruction for XtensaInstruction {
-    fn from_str(&mut self, s: &str) {
+    fn from_str(&mut self, s: &str) -> Result<()> {
         let mut tokens = s
             .split(|c| c == ' ' || c == ',')
             .filter(|s| !s.is_empty());
 
-        let opcode = tokens.nth(0).unwrap();
-        let builder = InstructionBuilder::from_opcode_str(opcode);
-        let instruction = builder.build(tokens);
+        let opcode = match tokens.nth(0) {
+            Some(opcode) => opcode,
+            None => return Err(Error::UnknownMnemonic(String::new()))
+        };
+
+        // rsr.ps a2 is the same as rsr a2, ps
+        let (opcode, register) = match opcode.find('.') {
+            Some(dot) if SR_ACCESS.contains(&&opcode[.. dot]) => (&opcode[.. dot], Some(&opcode[dot + 1 ..])),
+            _ => (opcode, None)
+        };
+
+        let builder = InstructionBuilder::from_opcode_str(opcode)?;
+        let instruction = builder.build(tokens.chain(register))?;
 
         self.opcode = instruction.opcode;
         self.operands = instruction.operands;
+        Ok(())
+    }
+}
+
+impl XtensaOpcode {
+    /// Returns canonical (wide) mnemonic
+    pub fn mnemonic(&self) -> &'static str {
+        match *self {
+            XtensaOpcode::Add => "add",
+            XtensaOpcode::Addi => "addi",
+            XtensaOpcode::And => "and",
+            XtensaOpcode::Sub => "sub",
+            XtensaOpcode::Or => "or",
+            XtensaOpcode::Slli => "slli",
+            XtensaOpcode::Slri => "srli",
+            XtensaOpcode::Srai => "srai",
+            XtensaOpcode::Mov => "mov",
+            XtensaOpcode::Movi => "movi",
+            XtensaOpcode::Addx2 => "addx2",
+            XtensaOpcode::Addx4 => "addx4",
+            XtensaOpcode::Addx8 => "addx8",
+            XtensaOpcode::Subx2 => "subx2",
+            XtensaOpcode::Subx4 => "subx4",
+            XtensaOpcode::Subx8 => "subx8",
+            XtensaOpcode::Xor => "xor",
+            XtensaOpcode::Neg => "neg",
+            XtensaOpcode::Abs => "abs",
+            XtensaOpcode::Bbsi => "bbsi",
+            XtensaOpcode::Bbci => "bbci",
+            XtensaOpcode::Beq => "beq",
+            XtensaOpcode::Bne => "bne",
+            XtensaOpcode::Blt => "blt",
+            XtensaOpcode::Bge => "bge",
+            XtensaOpcode::Bltu => "bltu",
+            XtensaOpcode::Bgeu => "bgeu",
+            XtensaOpcode::Beqz => "beqz",
+            XtensaOpcode::Bnez => "bnez",
+            XtensaOpcode::Bltz => "bltz",
+            XtensaOpcode::Bgez => "bgez",
+            XtensaOpcode::Beqi => "beqi",
+            XtensaOpcode::Bnei => "bnei",
+            XtensaOpcode::Blti => "blti",
+            XtensaOpcode::Bgei => "bgei",
+            XtensaOpcode::Bltui => "bltui",
+            XtensaOpcode::Bgeui => "bgeui",
+            XtensaOpcode::Ball => "ball",
+            XtensaOpcode::Bany => "bany",
+            XtensaOpcode::Bnall => "bnall",
+            XtensaOpcode::Bnone => "bnone",
+            XtensaOpcode::Bbc => "bbc",
+            XtensaOpcode::Bbs => "bbs",
+            XtensaOpcode::Ret => "ret",
+            XtensaOpcode::Jmp => "j",
+            XtensaOpcode::Jx => "jx",
+            XtensaOpcode::Memw => "memw",
+            XtensaOpcode::Nop => "nop",
+            XtensaOpcode::L32r => "l32r",
+            XtensaOpcode::L32i => "l32i",
+            XtensaOpcode::L16ui => "l16ui",
+            XtensaOpcode::L16si => "l16si",
+            XtensaOpcode::L8ui => "l8ui",
+            XtensaOpcode::S8i => "s8i",
+            XtensaOpcode::Call0 => "call0",
+            XtensaOpcode::Callx0 => "callx0",
+            XtensaOpcode::Entry => "entry",
+            XtensaOpcode::Retw => "retw",
+            XtensaOpcode::Call4 => "call4",
+            XtensaOpcode::Call8 => "call8",
+            XtensaOpcode::Call12 => "call12",
+            XtensaOpcode::Callx4 => "callx4",
+            XtensaOpcode::Callx8 => "callx8",
+            XtensaOpcode::Callx12 => "callx12",
+            XtensaOpcode::S32i => "s32i",
+            XtensaOpcode::S16i => "s16i",
+            XtensaOpcode::L32ai => "l32ai",
+            XtensaOpcode::S32ri => "s32ri",
+            XtensaOpcode::S32c1i => "s32c1i",
+            XtensaOpcode::Addmi => "addmi",
+            XtensaOpcode::Ssl => "ssl",
+            XtensaOpcode::Ssr => "ssr",
+            XtensaOpcode::Ssai => "ssai",
+            XtensaOpcode::Ssa8l => "ssa8l",
+            XtensaOpcode::Ssa8b => "ssa8b",
+            XtensaOpcode::Sll => "sll",
+            XtensaOpcode::Srl => "srl",
+            XtensaOpcode::Sra => "sra",
+            XtensaOpcode::Src => "src",
+            XtensaOpcode::Extui => "extui",
+            XtensaOpcode::Sext => "sext",
+            XtensaOpcode::Nsa => "nsa",
+            XtensaOpcode::Nsau => "nsau",
+            XtensaOpcode::Clamps => "clamps",
+            XtensaOpcode::Min => "min",
+            XtensaOpcode::Max => "max",
+            XtensaOpcode::Minu => "minu",
+            XtensaOpcode::Maxu => "maxu",
+            XtensaOpcode::Mul16u => "mul16u",
+            XtensaOpcode::Mul16s => "mul16s",
+            XtensaOpcode::Mull => "mull",
+            XtensaOpcode::Muluh => "muluh",
+            XtensaOpcode::Mulsh => "mulsh",
+            XtensaOpcode::Quou => "quou",
+            XtensaOpcode::Quos => "quos",
+            XtensaOpcode::Remu => "remu",
+            XtensaOpcode::Rems => "rems",
+            XtensaOpcode::Moveqz => "moveqz",
+            XtensaOpcode::Movnez => "movnez",
+            XtensaOpcode::Movltz => "movltz",
+            XtensaOpcode::Movgez => "movgez",
+            XtensaOpcode::Movt => "movt",
+            XtensaOpcode::Movf => "movf",
+            XtensaOpcode::Loop => "loop",
+            XtensaOpcode::Loopnez => "loopnez",
+            XtensaOpcode::Loopgtz => "loopgtz",
+            XtensaOpcode::Rsr => "rsr",
+            XtensaOpcode::Wsr => "wsr",
+            XtensaOpcode::Xsr => "xsr",
+            XtensaOpcode::Rsil => "rsil",
+            XtensaOpcode::Rur => "rur",
+            XtensaOpcode::Wur => "wur",
+            XtensaOpcode::Other => "unknown",
+        }
+    }
+}
+
+/// Formats instruction in the same syntax
+/// `from_str` accepts
+impl fmt::Display for XtensaInstruction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:}", self.opcode.mnemonic())?;
+
+        for (i, operand) in self.operands.iter().enumerate() {
+            write!(f, "{:}{:}", if i == 0 { " " } else { ", " }, operand)?;
+        }
+
+        Ok(())
     }
 }
 
@@ -135,22 +656,24 @@ impl Default for XtensaOpcode {
 
 #[cfg(test)]
 mod tests {
-    use translation::xtensa_op::{XtensaInstruction, XtensaOpcode, XtensaOperandKind, XtensaOperand};
+    use translation::xtensa_op::{XtensaInstruction, XtensaOpcode, XtensaOperandKind, XtensaOperand, SAR, BR, SCOMPARE1, LCOUNT};
     use assembly::ParseInstruction;
+    use error::Error;
 
     #[test]
-    #[should_panic]
     fn test_parse_1() {
         let mut i = XtensaInstruction::new();
 
-        i.from_str("");
+        assert!(i.from_str("").is_err());
+        assert_eq!(i.from_str("rfe"), Err(Error::UnknownMnemonic("rfe".to_string())));
+        assert_eq!(i.from_str("add a2, a3"), Err(Error::MissingOperand));
     }
 
     #[test]
     fn test_parse_2() {
         let mut i = XtensaInstruction::new();
 
-        i.from_str("and a1, a2, a3");
+        i.from_str("and a1, a2, a3").unwrap();
 
         assert!(i == XtensaInstruction {
             opcode: XtensaOpcode::And,
@@ -166,7 +689,7 @@ mod tests {
     fn test_parse_3() {
         let mut i = XtensaInstruction::new();
 
-        i.from_str("l32r a14, 0xaabbccdd");
+        i.from_str("l32r a14, 0xaabbccdd").unwrap();
 
         assert!(i == XtensaInstruction {
             opcode: XtensaOpcode::L32r,
@@ -176,4 +699,169 @@ mod tests {
             ],
         });
     }
+
+    #[test]
+    fn test_parse_4() {
+        let mut i = XtensaInstruction::new();
+
+        i.from_str("bgeui a3, 32768, 0x40224a56").unwrap();
+
+        assert!(i == XtensaInstruction {
+            opcode: XtensaOpcode::Bgeui,
+            operands: vec![
+                XtensaOperand { kind: XtensaOperandKind::Reg(3) },
+                XtensaOperand { kind: XtensaOperandKind::Imm(32768) },
+                XtensaOperand { kind: XtensaOperandKind::Imm(0x40224a56) },
+            ],
+        });
+
+        assert_eq!(i.branch_target(), Some(0x40224a56));
+    }
+
+    #[test]
+    fn test_parse_5() {
+        let mut i = XtensaInstruction::new();
+
+        i.from_str("extui a2, a3, 16, 8").unwrap();
+
+        assert!(i == XtensaInstruction {
+            opcode: XtensaOpcode::Extui,
+            operands: vec![
+                XtensaOperand { kind: XtensaOperandKind::Reg(2) },
+                XtensaOperand { kind: XtensaOperandKind::Reg(3) },
+                XtensaOperand { kind: XtensaOperandKind::Imm(16) },
+                XtensaOperand { kind: XtensaOperandKind::Imm(8) },
+            ],
+        });
+
+        assert_eq!(i.defs(), vec![2]);
+        assert_eq!(i.uses(), vec![3]);
+
+        i.from_str("minu a5, a6, a7").unwrap();
+        assert_eq!(i.defs(), vec![5]);
+        assert_eq!(i.uses(), vec![6, 7]);
+
+        assert_eq!(i.from_str("clamps a2, a3"), Err(Error::MissingOperand));
+    }
+
+    #[test]
+    fn test_parse_6() {
+        let mut i = XtensaInstruction::new();
+
+        i.from_str("quou a2, a3, a4").unwrap();
+        assert!(i.opcode == XtensaOpcode::Quou);
+        assert_eq!(i.defs(), vec![2]);
+        assert_eq!(i.uses(), vec![3, 4]);
+
+        i.from_str("mul16s a8, a8, a9").unwrap();
+        assert_eq!(i.to_string(), "mul16s a8, a8, a9");
+    }
+
+    #[test]
+    fn test_parse_7() {
+        let mut i = XtensaInstruction::new();
+
+        i.from_str("moveqz a2, a3, a4").unwrap();
+        assert_eq!(i.defs(), vec![2]);
+        assert_eq!(i.uses(), vec![2, 3, 4]);
+
+        i.from_str("movt a2, a3, b1").unwrap();
+        assert_eq!(i.to_string(), "movt a2, a3, b1");
+        assert_eq!(i.uses(), vec![2, 3, BR]);
+
+        assert!(i.from_str("movf a2, a3, a4").is_err());
+    }
+
+    #[test]
+    fn test_defs_uses_1() {
+        let mut i = XtensaInstruction::new();
+
+        i.from_str("l32i a6, a2, 0x300").unwrap();
+        assert_eq!(i.defs(), vec![6]);
+        assert_eq!(i.uses(), vec![2]);
+
+        i.from_str("s32i a7, a2, 0x300").unwrap();
+        assert_eq!(i.defs(), vec![]);
+        assert_eq!(i.uses(), vec![7, 2]);
+
+        i.from_str("callx0 a8").unwrap();
+        assert_eq!(i.defs(), vec![0]);
+        assert_eq!(i.uses(), vec![8]);
+
+        i.from_str("callx8 a9").unwrap();
+        assert_eq!(i.defs(), vec![10]);
+        assert_eq!(i.uses(), vec![9]);
+        assert_eq!(i.window_args(), vec![10, 11, 12, 13, 14, 15]);
+
+        i.from_str("s16i a7, a2, 0x1fe").unwrap();
+        assert_eq!(i.defs(), vec![]);
+        assert_eq!(i.uses(), vec![7, 2]);
+
+        i.from_str("loopgtz a4, 0x40100020").unwrap();
+        assert_eq!(i.defs(), vec![LCOUNT]);
+        assert_eq!(i.uses(), vec![4]);
+        assert_eq!(i.loop_end(), Some(0x40100020));
+        assert_eq!(i.branch_target(), Some(0x40100020));
+
+        i.from_str("s32c1i a7, a2, 0").unwrap();
+        assert_eq!(i.defs(), vec![7]);
+        assert_eq!(i.uses(), vec![7, 2, SCOMPARE1]);
+    }
+
+    #[test]
+    fn test_defs_uses_2() {
+        let mut i = XtensaInstruction::new();
+
+        i.from_str("ssl a3").unwrap();
+        assert_eq!(i.defs(), vec![SAR]);
+        assert_eq!(i.uses(), vec![3]);
+
+        i.from_str("src a2, a3, a4").unwrap();
+        assert_eq!(i.defs(), vec![2]);
+        assert_eq!(i.uses(), vec![3, 4, SAR]);
+
+        i.from_str("ssai 8").unwrap();
+        assert_eq!(i.defs(), vec![SAR]);
+        assert!(i.uses().is_empty());
+    }
+
+    #[test]
+    fn test_special_reg_1() {
+        let mut i = XtensaInstruction::new();
+
+        i.from_str("xsr.excsave1 a2").unwrap();
+        assert!(i.opcode == XtensaOpcode::Xsr);
+        assert_eq!(i.to_string(), "xsr a2, excsave1");
+        assert_eq!(i.defs(), vec![2]);
+        assert_eq!(i.uses(), vec![2]);
+
+        i.from_str("wsr a3, 3").unwrap();
+        assert_eq!(i.to_string(), "wsr a3, sar");
+        assert_eq!(i.defs(), vec![SAR]);
+        assert_eq!(i.uses(), vec![3]);
+
+        i.from_str("rsr.scompare1 a4").unwrap();
+        assert_eq!(i.defs(), vec![4]);
+        assert_eq!(i.uses(), vec![SCOMPARE1]);
+
+        i.from_str("wsr.br a6").unwrap();
+        assert_eq!(i.defs(), vec![BR]);
+
+        i.from_str("rur.threadptr a5").unwrap();
+        assert_eq!(i.to_string(), "rur a5, threadptr");
+
+        assert!(i.from_str("rsr.bogus a2").is_err());
+        assert!(i.from_str("l32i.n a2, a3, 0").is_ok());
+    }
+
+    #[test]
+    fn test_format_1() {
+        let mut i = XtensaInstruction::new();
+
+        i.from_str("addi.n a1, a1, -16").unwrap();
+        assert_eq!(i.to_string(), "addi a1, a1, -16");
+
+        i.from_str("l32r a14, 0xaabbccdd").unwrap();
+        assert_eq!(i.to_string(), "l32r a14, 0xaabbccdd");
+    }
 }
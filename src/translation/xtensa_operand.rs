@@ -1,43 +1,69 @@
 use std::default::Default;
+use std::fmt;
 use assembly::Operand;
+use translation::xtensa_op::BR;
+use translation::xtensa_sr::{special_reg_name, special_reg_number, user_reg_name, user_reg_number};
+use error::{Error, Result};
 
-#[derive(PartialEq)]
+#[derive(Clone, PartialEq)]
 pub enum XtensaOperandKind {
     Reg(u8),
+    /// Boolean register b0..b15
+    BoolReg(u8),
+    /// Special register of rsr/wsr/xsr, by number
+    SpecialReg(u8),
+    /// User register of rur/wur, by number
+    UserReg(u8),
     Imm(u32),
     Unknown,
 }
 
-#[derive(PartialEq)]
+#[derive(Clone, PartialEq)]
 pub struct XtensaOperand {
     pub kind: XtensaOperandKind,
 }
 
 impl XtensaOperand {
-    fn get_reg_number(s: &str) -> u8 {
-        let number_string: String = s.chars().skip(1).collect();
-        number_string.parse::<u8>().unwrap()
+    fn get_reg_number(s: &str, prefix: char) -> Result<u8> {
+        if !s.starts_with(prefix) {
+            return Err(Error::BadOperand(s.to_string()));
+        }
+
+        match s[1..].parse::<u8>() {
+            Ok(r) if r < 16 => Ok(r),
+            _ => Err(Error::BadOperand(s.to_string()))
+        }
     }
 
-    pub fn new(k: XtensaOperandKind, s: &str) -> XtensaOperand {
-        XtensaOperand { kind: match k {
+    pub fn new(k: XtensaOperandKind, s: &str) -> Result<XtensaOperand> {
+        let bad = |_| Error::BadOperand(s.to_string());
+
+        Ok(XtensaOperand { kind: match k {
             XtensaOperandKind::Reg(_) => {
-                let reg = XtensaOperand::get_reg_number(s);
+                let reg = XtensaOperand::get_reg_number(s, 'a')?;
                 XtensaOperandKind::Reg(reg)
             }
+            XtensaOperandKind::BoolReg(_) => {
+                let reg = XtensaOperand::get_reg_number(s, 'b')?;
+                XtensaOperandKind::BoolReg(reg)
+            }
+            XtensaOperandKind::SpecialReg(_) =>
+                XtensaOperandKind::SpecialReg(special_reg_number(s).ok_or_else(|| Error::BadOperand(s.to_string()))?),
+            XtensaOperandKind::UserReg(_) =>
+                XtensaOperandKind::UserReg(user_reg_number(s).ok_or_else(|| Error::BadOperand(s.to_string()))?),
             XtensaOperandKind::Imm(_) => {
                 let value: u32;
 
                 if s.starts_with("0x") {
-                    value = u32::from_str_radix(&s[2..], 16).unwrap();
+                    value = u32::from_str_radix(&s[2..], 16).map_err(bad)?;
                 } else {
-                    value = s.parse::<i32>().unwrap() as u32;
+                    value = s.parse::<i32>().map_err(bad)? as u32;
                 }
 
                 XtensaOperandKind::Imm(value)
             },
-            XtensaOperandKind::Unknown => panic!("Unknown operand kind")
-        }}
+            XtensaOperandKind::Unknown => return Err(Error::BadOperand(s.to_string()))
+        }})
     }
 }
 
@@ -47,6 +73,21 @@ impl Default for XtensaOperand {
     }
 }
 
+impl fmt::Display for XtensaOperand {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.kind {
+            XtensaOperandKind::Reg(r) => write!(f, "a{:}", r),
+            XtensaOperandKind::BoolReg(r) => write!(f, "b{:}", r),
+            XtensaOperandKind::SpecialReg(r) => write!(f, "{:}", special_reg_name(r)),
+            XtensaOperandKind::UserReg(r) => write!(f, "{:}", user_reg_name(r)),
+            // Addresses are printed in hex, small values as signed decimal
+            XtensaOperandKind::Imm(i) if (i as i32) < -4096 || (i as i32) > 4096 => write!(f, "0x{:x}", i),
+            XtensaOperandKind::Imm(i) => write!(f, "{:}", i as i32),
+            XtensaOperandKind::Unknown => write!(f, "?"),
+        }
+    }
+}
+
 impl Operand for XtensaOperand {
     fn get_imm(&self) -> i32 {
         match self.kind {
@@ -55,9 +96,11 @@ impl Operand for XtensaOperand {
         }
     }
 
+    /// Boolean registers are all tracked as BR
     fn get_reg(&self) -> u8 {
         match self.kind {
             XtensaOperandKind::Reg(i) => i,
+            XtensaOperandKind::BoolReg(_) => BR,
             _ => panic!("called get_imm on non-immediate operand")
         }
     }
@@ -66,29 +109,48 @@ impl Operand for XtensaOperand {
 #[cfg(test)]
 mod tests {
     use translation::xtensa_operand::{XtensaOperand, XtensaOperandKind};
+    use translation::xtensa_op::BR;
     use assembly::Operand;
 
     #[test]
-    #[should_panic]
     fn test_operand_imm_1() {
-        XtensaOperand::new(XtensaOperandKind::Imm(0), "");
+        assert!(XtensaOperand::new(XtensaOperandKind::Imm(0), "").is_err());
     }
 
     #[test]
     fn test_operand_imm_2() {
-        let o = XtensaOperand::new(XtensaOperandKind::Imm(0), "-333");
+        let o = XtensaOperand::new(XtensaOperandKind::Imm(0), "-333").unwrap();
         assert_eq!(o.get_imm(), -333);
     }
 
     #[test]
-    #[should_panic]
     fn test_operand_reg_1() {
-        XtensaOperand::new(XtensaOperandKind::Reg(0), "");
+        assert!(XtensaOperand::new(XtensaOperandKind::Reg(0), "").is_err());
+        assert!(XtensaOperand::new(XtensaOperandKind::Reg(0), "a16").is_err());
     }
 
     #[test]
     fn test_operand_reg_2() {
-        let o = XtensaOperand::new(XtensaOperandKind::Reg(0), "a15");
+        let o = XtensaOperand::new(XtensaOperandKind::Reg(0), "a15").unwrap();
         assert_eq!(o.get_reg(), 15);
     }
+
+    #[test]
+    fn test_operand_bool_reg_1() {
+        let o = XtensaOperand::new(XtensaOperandKind::BoolReg(0), "b3").unwrap();
+
+        assert_eq!(o.to_string(), "b3");
+        assert_eq!(o.get_reg(), BR);
+        assert!(XtensaOperand::new(XtensaOperandKind::BoolReg(0), "a3").is_err());
+    }
+
+    #[test]
+    fn test_operand_special_reg_1() {
+        let o = XtensaOperand::new(XtensaOperandKind::SpecialReg(0), "230").unwrap();
+
+        assert_eq!(o.to_string(), "ps");
+        assert!(XtensaOperand::new(XtensaOperandKind::SpecialReg(0), "ps").unwrap() == o);
+        assert!(XtensaOperand::new(XtensaOperandKind::SpecialReg(0), "threadptr").is_err());
+        assert!(XtensaOperand::new(XtensaOperandKind::UserReg(0), "threadptr").is_ok());
+    }
 }
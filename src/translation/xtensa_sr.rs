@@ -0,0 +1,160 @@
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::Read;
+
+use error::{Error, Result};
+
+/// Special register numbers and names, as used by rsr/wsr/xsr
+pub const SPECIAL_REGISTERS: [(u8, &str); 71] = [
+    (0, "lbeg"), (1, "lend"), (2, "lcount"), (3, "sar"),
+    (4, "br"), (5, "litbase"), (12, "scompare1"), (16, "acclo"),
+    (17, "acchi"), (32, "m0"), (33, "m1"), (34, "m2"),
+    (35, "m3"), (72, "windowbase"), (73, "windowstart"), (83, "ptevaddr"),
+    (90, "rasid"), (91, "itlbcfg"), (92, "dtlbcfg"), (96, "ibreakenable"),
+    (97, "memctl"), (98, "cacheattr"), (99, "atomctl"), (104, "ddr"),
+    (128, "ibreaka0"), (129, "ibreaka1"), (144, "dbreaka0"), (145, "dbreaka1"),
+    (160, "dbreakc0"), (161, "dbreakc1"), (177, "epc1"), (178, "epc2"),
+    (179, "epc3"), (180, "epc4"), (181, "epc5"), (182, "epc6"),
+    (183, "epc7"), (192, "depc"), (194, "eps2"), (195, "eps3"),
+    (196, "eps4"), (197, "eps5"), (198, "eps6"), (199, "eps7"),
+    (209, "excsave1"), (210, "excsave2"), (211, "excsave3"), (212, "excsave4"),
+    (213, "excsave5"), (214, "excsave6"), (215, "excsave7"), (224, "cpenable"),
+    (226, "interrupt"), (227, "intclear"), (228, "intenable"), (230, "ps"),
+    (231, "vecbase"), (232, "exccause"), (233, "debugcause"), (234, "ccount"),
+    (235, "prid"), (236, "icount"), (237, "icountlevel"), (238, "excvaddr"),
+    (240, "ccompare0"), (241, "ccompare1"), (242, "ccompare2"), (244, "misc0"),
+    (245, "misc1"), (246, "misc2"), (247, "misc3"),
+];
+
+/// User register numbers and names, as used by rur/wur
+pub const USER_REGISTERS: [(u8, &str); 3] = [
+    (231, "threadptr"), (232, "fcr"), (233, "fsr"),
+];
+
+fn name(table: &[(u8, &'static str)], prefix: &str, number: u8) -> String {
+    match table.iter().find(|&&(n, _)| n == number) {
+        Some(&(_, name)) => name.to_string(),
+        None => format!("{:}{:}", prefix, number),
+    }
+}
+
+fn number(table: &[(u8, &'static str)], s: &str) -> Option<u8> {
+    match table.iter().find(|&&(_, name)| name == s) {
+        Some(&(n, _)) => Some(n),
+        None => s.parse::<u8>().ok(),
+    }
+}
+
+/// Returns special register name, `srN` for unknown ones
+pub fn special_reg_name(number: u8) -> String {
+    name(&SPECIAL_REGISTERS, "sr", number)
+}
+
+/// Parses special register name or number
+pub fn special_reg_number(s: &str) -> Option<u8> {
+    number(&SPECIAL_REGISTERS, s)
+}
+
+/// Returns user register name, `urN` for unknown ones
+pub fn user_reg_name(number: u8) -> String {
+    name(&USER_REGISTERS, "ur", number)
+}
+
+/// Parses user register name or number
+pub fn user_reg_number(s: &str) -> Option<u8> {
+    number(&USER_REGISTERS, s)
+}
+
+/// How accesses to a special or user register are translated
+#[derive(Clone, PartialEq, Debug)]
+pub enum SrAccess {
+    /// Global word declared by the machine description
+    Slot(String),
+    /// Call of `__xt_<mnemonic>_<register>`, e.g. `__xt_wsr_intenable`
+    Intrinsic,
+}
+
+/// Per-target special register mapping. Registers
+/// not listed are accessed through intrinsics
+#[derive(Clone, Default)]
+pub struct SrMap {
+    registers: BTreeMap<String, SrAccess>,
+}
+
+impl SrMap {
+    pub fn new() -> SrMap {
+        Default::default()
+    }
+
+    /// Reads mapping from a file, see `parse`
+    pub fn open(path: &str) -> Result<SrMap> {
+        let mut text = String::new();
+
+        File::open(path)
+            .and_then(|mut file| file.read_to_string(&mut text))
+            .map_err(|why| Error::io(path, why))?;
+
+        SrMap::parse(&text).map_err(|e| match e {
+            Error::Config(s) => Error::Config(format!("{:}: {:}", path, s)),
+            e => e
+        })
+    }
+
+    /// Parses lines of `<register> slot <symbol>` or
+    /// `<register> intrinsic`, `#` starts a comment
+    pub fn parse(text: &str) -> Result<SrMap> {
+        let mut map = SrMap::new();
+
+        for (n, line) in text.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or("");
+            let tokens: Vec<&str> = line.split_whitespace().collect();
+            let bad = |what: &str| Error::Config(format!("line {:}: {:}", n + 1, what));
+
+            let access = match tokens[..] {
+                [] => continue,
+                [_, "slot", symbol] => SrAccess::Slot(symbol.to_string()),
+                [_, "intrinsic"] => SrAccess::Intrinsic,
+                _ => return Err(bad("expected `<register> slot <symbol>` or `<register> intrinsic`")),
+            };
+
+            if special_reg_number(tokens[0]).is_none() && user_reg_number(tokens[0]).is_none() {
+                return Err(bad(&format!("unknown register {:}", tokens[0])));
+            }
+
+            map.registers.insert(tokens[0].to_string(), access);
+        }
+
+        Ok(map)
+    }
+
+    pub fn get(&self, name: &str) -> SrAccess {
+        self.registers.get(name).cloned().unwrap_or(SrAccess::Intrinsic)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use translation::xtensa_sr::{SrMap, SrAccess, special_reg_name, special_reg_number, user_reg_name};
+
+    #[test]
+    fn test_sr_names_1() {
+        assert_eq!(special_reg_name(230), "ps");
+        assert_eq!(special_reg_name(250), "sr250");
+        assert_eq!(special_reg_number("intenable"), Some(228));
+        assert_eq!(special_reg_number("209"), Some(209));
+        assert_eq!(special_reg_number("bogus"), None);
+        assert_eq!(user_reg_name(231), "threadptr");
+    }
+
+    #[test]
+    fn test_sr_map_1() {
+        let map = SrMap::parse("# comment\nexcsave1 slot XT_EXCSAVE1\n\nintenable intrinsic # inline\n").unwrap();
+
+        assert_eq!(map.get("excsave1"), SrAccess::Slot("XT_EXCSAVE1".to_string()));
+        assert_eq!(map.get("intenable"), SrAccess::Intrinsic);
+        assert_eq!(map.get("ps"), SrAccess::Intrinsic);
+
+        assert!(SrMap::parse("ps slot").is_err());
+        assert!(SrMap::parse("bogus intrinsic").is_err());
+    }
+}